@@ -0,0 +1,259 @@
+use std::io::{Cursor, Write};
+
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+use crate::errors::ErrorKind;
+
+const MAIN_NS: &str = "http://schemas.openxmlformats.org/spreadsheetml/2006/main";
+const REL_NS: &str = "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
+const PACKAGE_REL_NS: &str = "http://schemas.openxmlformats.org/package/2006/relationships";
+
+/// How a cell is drawn. The index is its position in `cellXfs` in styles.xml.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    Plain,
+    Bold,
+    Highlight,
+}
+
+impl Style {
+    fn index(&self) -> usize {
+        match self {
+            Style::Plain => 0,
+            Style::Bold => 1,
+            Style::Highlight => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Empty,
+    Text(String),
+    Number(f64),
+    Bool(bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cell {
+    pub value: Value,
+    pub style: Style,
+}
+
+impl Cell {
+    pub fn empty() -> Self {
+        Self {
+            value: Value::Empty,
+            style: Style::Plain,
+        }
+    }
+
+    pub fn text(s: &str) -> Self {
+        Self {
+            value: Value::Text(s.to_string()),
+            style: Style::Plain,
+        }
+    }
+
+    pub fn number(n: f64) -> Self {
+        Self {
+            value: Value::Number(n),
+            style: Style::Plain,
+        }
+    }
+
+    pub fn bool(b: bool) -> Self {
+        Self {
+            value: Value::Bool(b),
+            style: Style::Plain,
+        }
+    }
+
+    pub fn style(self, style: Style) -> Self {
+        Self { style, ..self }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Sheet {
+    name: String,
+    rows: Vec<Vec<Cell>>,
+}
+
+/// An .xlsx workbook built up sheet by sheet.
+#[derive(Debug, Clone, Default)]
+pub struct Workbook {
+    sheets: Vec<Sheet>,
+}
+
+/// `0` is column A, `26` is AA.
+pub fn column_name(mut index: usize) -> String {
+    let mut name = Vec::new();
+
+    loop {
+        name.push((b'A' + (index % 26) as u8) as char);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+
+    name.iter().rev().collect()
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Excel refuses sheet names over 31 characters or with any of `[]:*?/\`.
+fn sheet_name(name: &str) -> String {
+    let name = name
+        .chars()
+        .filter(|c| !"[]:*?/\\".contains(*c))
+        .take(31)
+        .collect::<String>();
+
+    match name.trim().is_empty() {
+        true => "Sheet".to_string(),
+        false => name,
+    }
+}
+
+impl Workbook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sheet(self, name: &str, rows: Vec<Vec<Cell>>) -> Self {
+        let mut sheets = self.sheets;
+
+        sheets.push(Sheet {
+            name: sheet_name(name),
+            rows,
+        });
+
+        Self { sheets }
+    }
+
+    fn content_types(&self) -> String {
+        let sheets = (1..=self.sheets.len())
+            .map(|i| format!(r#"<Override PartName="/xl/worksheets/sheet{}.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/>"#, i))
+            .collect::<String>();
+
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/><Override PartName="/xl/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml"/>{}</Types>"#,
+            sheets
+        )
+    }
+
+    fn root_rels(&self) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><Relationships xmlns="{}"><Relationship Id="rId1" Type="{}/officeDocument" Target="xl/workbook.xml"/></Relationships>"#,
+            PACKAGE_REL_NS, REL_NS
+        )
+    }
+
+    fn workbook(&self) -> String {
+        let sheets = self
+            .sheets
+            .iter()
+            .enumerate()
+            .map(|(i, s)| format!(r#"<sheet name="{}" sheetId="{}" r:id="rId{}"/>"#, escape(&s.name), i + 1, i + 1))
+            .collect::<String>();
+
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><workbook xmlns="{}" xmlns:r="{}"><sheets>{}</sheets></workbook>"#,
+            MAIN_NS, REL_NS, sheets
+        )
+    }
+
+    fn workbook_rels(&self) -> String {
+        let sheets = (1..=self.sheets.len())
+            .map(|i| format!(r#"<Relationship Id="rId{}" Type="{}/worksheet" Target="worksheets/sheet{}.xml"/>"#, i, REL_NS, i))
+            .collect::<String>();
+
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><Relationships xmlns="{}">{}<Relationship Id="rId{}" Type="{}/styles" Target="styles.xml"/></Relationships>"#,
+            PACKAGE_REL_NS,
+            sheets,
+            self.sheets.len() + 1,
+            REL_NS
+        )
+    }
+
+    fn styles(&self) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><styleSheet xmlns="{}"><fonts count="2"><font><sz val="11"/><name val="Calibri"/></font><font><b/><sz val="11"/><name val="Calibri"/></font></fonts><fills count="3"><fill><patternFill patternType="none"/></fill><fill><patternFill patternType="gray125"/></fill><fill><patternFill patternType="solid"><fgColor rgb="FFFFC7CE"/><bgColor indexed="64"/></patternFill></fill></fills><borders count="1"><border><left/><right/><top/><bottom/><diagonal/></border></borders><cellStyleXfs count="1"><xf numFmtId="0" fontId="0" fillId="0" borderId="0"/></cellStyleXfs><cellXfs count="3"><xf numFmtId="0" fontId="0" fillId="0" borderId="0" xfId="0"/><xf numFmtId="0" fontId="1" fillId="0" borderId="0" xfId="0" applyFont="1"/><xf numFmtId="0" fontId="0" fillId="2" borderId="0" xfId="0" applyFill="1"/></cellXfs><cellStyles count="1"><cellStyle name="Normal" xfId="0" builtinId="0"/></cellStyles></styleSheet>"#,
+            MAIN_NS
+        )
+    }
+
+    fn worksheet(sheet: &Sheet) -> String {
+        let mut data = String::new();
+
+        for (r, row) in sheet.rows.iter().enumerate() {
+            data.push_str(&format!(r#"<row r="{}">"#, r + 1));
+
+            for (c, cell) in row.iter().enumerate() {
+                let reference = format!("{}{}", column_name(c), r + 1);
+                let style = cell.style.index();
+
+                let xml = match &cell.value {
+                    Value::Empty if style == 0 => continue,
+                    Value::Empty => format!(r#"<c r="{}" s="{}"/>"#, reference, style),
+                    Value::Text(t) => format!(
+                        r#"<c r="{}" s="{}" t="inlineStr"><is><t xml:space="preserve">{}</t></is></c>"#,
+                        reference,
+                        style,
+                        escape(t)
+                    ),
+                    Value::Number(n) => format!(r#"<c r="{}" s="{}"><v>{}</v></c>"#, reference, style, n),
+                    Value::Bool(b) => format!(r#"<c r="{}" s="{}" t="b"><v>{}</v></c>"#, reference, style, *b as u8),
+                };
+
+                data.push_str(&xml);
+            }
+
+            data.push_str("</row>");
+        }
+
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><worksheet xmlns="{}"><sheetData>{}</sheetData></worksheet>"#,
+            MAIN_NS, data
+        )
+    }
+
+    /// Packs the workbook into the bytes of an .xlsx file.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ErrorKind> {
+        if self.sheets.is_empty() {
+            return Err(ErrorKind::EmptyWorkbook);
+        }
+
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FileOptions::default();
+
+        let mut parts = vec![
+            ("[Content_Types].xml".to_string(), self.content_types()),
+            ("_rels/.rels".to_string(), self.root_rels()),
+            ("xl/workbook.xml".to_string(), self.workbook()),
+            ("xl/_rels/workbook.xml.rels".to_string(), self.workbook_rels()),
+            ("xl/styles.xml".to_string(), self.styles()),
+        ];
+
+        for (i, sheet) in self.sheets.iter().enumerate() {
+            parts.push((format!("xl/worksheets/sheet{}.xml", i + 1), Self::worksheet(sheet)));
+        }
+
+        for (name, xml) in parts {
+            zip.start_file(name, options)?;
+            zip.write_all(xml.as_bytes())?;
+        }
+
+        Ok(zip.finish()?.into_inner())
+    }
+}
@@ -0,0 +1,52 @@
+use std::fmt;
+
+use calamine;
+use csv;
+use zip;
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    XlsxError(calamine::XlsxError),
+    CsvError(csv::Error),
+    ZipError(zip::result::ZipError),
+    IOError(std::io::Error),
+    EmptyWorkbook,
+    MissingColumn(&'static str),
+}
+
+impl From<calamine::XlsxError> for ErrorKind {
+    fn from(error: calamine::XlsxError) -> Self {
+        Self::XlsxError(error)
+    }
+}
+
+impl From<csv::Error> for ErrorKind {
+    fn from(error: csv::Error) -> Self {
+        Self::CsvError(error)
+    }
+}
+
+impl From<zip::result::ZipError> for ErrorKind {
+    fn from(error: zip::result::ZipError) -> Self {
+        Self::ZipError(error)
+    }
+}
+
+impl From<std::io::Error> for ErrorKind {
+    fn from(error: std::io::Error) -> Self {
+        Self::IOError(error)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::XlsxError(err) => write!(f, "{}", err),
+            Self::CsvError(err) => write!(f, "{}", err),
+            Self::ZipError(err) => write!(f, "{}", err),
+            Self::IOError(err) => write!(f, "{}", err),
+            Self::EmptyWorkbook => write!(f, "The workbook has no sheets"),
+            Self::MissingColumn(column) => write!(f, "Missing column: {}", column),
+        }
+    }
+}
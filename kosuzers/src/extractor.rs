@@ -0,0 +1,295 @@
+use std::collections::HashSet;
+use std::io::Cursor;
+
+use calamine::{DataType, Range, Reader, Xlsx};
+use chrono::NaiveDate;
+
+use crate::errors::ErrorKind;
+
+const USER_ID: &[&str] = &["nis", "userid", "id"];
+const FULLNAME: &[&str] = &["fullname", "name", "nama", "namalengkap"];
+const EMAIL: &[&str] = &["email"];
+const BIRTH_DATE: &[&str] = &["birthdate", "dateofbirth", "dob", "tanggallahir"];
+const BIRTH_PLACE: &[&str] = &["birthplace", "placeofbirth", "tempatlahir"];
+const MARK: &[&str] = &["mark", "marks", "score", "nilai"];
+
+/// One valid line of a class roster.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RosterRow {
+    pub row: usize,
+    pub user_id: String,
+    pub fullname: String,
+    pub email: String,
+    pub birth_date: NaiveDate,
+    pub birth_place: String,
+}
+
+/// What's wrong with a line, `row` being the row number shown in Excel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowError {
+    pub row: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Roster {
+    pub rows: Vec<RosterRow>,
+    pub errors: Vec<RowError>,
+}
+
+/// One valid line of a mark sheet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarkRow {
+    pub row: usize,
+    pub user_id: String,
+    pub mark: i32,
+}
+
+#[derive(Debug, Default)]
+pub struct MarkSheet {
+    pub rows: Vec<MarkRow>,
+    pub errors: Vec<RowError>,
+}
+
+/// Reads the first sheet of an .xlsx file.
+pub fn read_sheet(bytes: Vec<u8>) -> Result<Range<DataType>, ErrorKind> {
+    let mut workbook = Xlsx::new(Cursor::new(bytes))?;
+
+    match workbook.worksheet_range_at(0) {
+        Some(range) => Ok(range?),
+        None => Err(ErrorKind::EmptyWorkbook),
+    }
+}
+
+/// Reads a CSV file into the same shape as a sheet, every field as text.
+pub fn read_csv(bytes: Vec<u8>) -> Result<Range<DataType>, ErrorKind> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(Cursor::new(bytes));
+
+    let records = reader.records().collect::<Result<Vec<_>, _>>()?;
+
+    let width = records.iter().map(|r| r.len()).max().unwrap_or(0);
+
+    if records.is_empty() || width == 0 {
+        return Err(ErrorKind::EmptyWorkbook);
+    }
+
+    let mut range = Range::new((0, 0), (records.len() as u32 - 1, width as u32 - 1));
+
+    for (r, record) in records.iter().enumerate() {
+        for (c, field) in record.iter().enumerate() {
+            if !field.is_empty() {
+                range.set_value((r as u32, c as u32), DataType::String(field.to_string()));
+            }
+        }
+    }
+
+    Ok(range)
+}
+
+/// Reads either an .xlsx workbook or a CSV file, telling them apart by the
+/// zip signature every .xlsx starts with.
+pub fn read_table(bytes: Vec<u8>) -> Result<Range<DataType>, ErrorKind> {
+    match bytes.starts_with(b"PK\x03\x04") {
+        true => read_sheet(bytes),
+        false => read_csv(bytes),
+    }
+}
+
+fn normalize(header: &str) -> String {
+    header
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Finds the column whose header matches one of the aliases, ignoring case,
+/// spaces and punctuation.
+pub fn find_column(header: &[DataType], aliases: &[&str]) -> Option<usize> {
+    header
+        .iter()
+        .position(|cell| aliases.contains(&normalize(&cell_string(cell)).as_str()))
+}
+
+/// A cell as trimmed text. Whole numbers lose their `.0`, since ids like NIS
+/// are usually typed in as numbers.
+pub fn cell_string(cell: &DataType) -> String {
+    match cell {
+        DataType::String(s) => s.trim().to_string(),
+        DataType::Int(i) => i.to_string(),
+        DataType::Float(f) if f.fract() == 0.0 => format!("{}", *f as i64),
+        DataType::Float(f) => f.to_string(),
+        DataType::Bool(b) => b.to_string(),
+        _ => String::new(),
+    }
+}
+
+pub fn cell_date(cell: &DataType) -> Option<NaiveDate> {
+    match cell {
+        DataType::String(s) => ["%Y-%m-%d", "%d/%m/%Y", "%d-%m-%Y"]
+            .iter()
+            .find_map(|f| NaiveDate::parse_from_str(s.trim(), f).ok()),
+        DataType::DateTime(_) | DataType::Float(_) | DataType::Int(_) => cell.as_date(),
+        _ => None,
+    }
+}
+
+/// A mark must be a whole, non-negative number, typed either as a number or
+/// as text.
+pub fn cell_mark(cell: &DataType) -> Option<i32> {
+    let value = match cell {
+        DataType::Int(i) => *i as f64,
+        DataType::Float(f) => *f,
+        DataType::String(s) => s.trim().parse::<f64>().ok()?,
+        _ => return None,
+    };
+
+    match value.fract() == 0.0 && value >= 0.0 && value <= i32::MAX as f64 {
+        true => Some(value as i32),
+        false => None,
+    }
+}
+
+fn required(header: &[DataType], aliases: &'static [&'static str]) -> Result<usize, ErrorKind> {
+    find_column(header, aliases).ok_or(ErrorKind::MissingColumn(aliases[0]))
+}
+
+/// Parses a roster sheet whose first row holds the headers. Every line is
+/// checked on its own, so one pass reports all the mistakes in the file.
+pub fn extract_roster(range: &Range<DataType>) -> Result<Roster, ErrorKind> {
+    let mut rows = range.rows();
+
+    let header = match rows.next() {
+        Some(h) => h,
+        None => return Err(ErrorKind::MissingColumn(USER_ID[0])),
+    };
+
+    let user_id = required(header, USER_ID)?;
+    let fullname = required(header, FULLNAME)?;
+    let email = required(header, EMAIL)?;
+    let birth_date = required(header, BIRTH_DATE)?;
+    let birth_place = find_column(header, BIRTH_PLACE);
+
+    let first_row = range.start().map(|(r, _)| r as usize).unwrap_or(0) + 2;
+
+    let mut roster = Roster::default();
+    let mut seen_ids = HashSet::new();
+    let mut seen_emails = HashSet::new();
+
+    for (i, cells) in rows.enumerate() {
+        let row = first_row + i;
+
+        let get = |column: usize| cells.get(column).map(cell_string).unwrap_or_default();
+
+        if cells.iter().all(|c| cell_string(c).is_empty()) {
+            continue;
+        }
+
+        let mut problems = Vec::new();
+
+        let id = get(user_id);
+        let name = get(fullname);
+        let mail = get(email).to_lowercase();
+        let date = cells.get(birth_date).and_then(cell_date);
+
+        if id.is_empty() {
+            problems.push("missing NIS".to_string());
+        } else if !seen_ids.insert(id.clone()) {
+            problems.push(format!("NIS {} appears more than once", id));
+        }
+
+        if name.is_empty() {
+            problems.push("missing full name".to_string());
+        }
+
+        match mail.split_once('@') {
+            Some((local, domain)) if !local.is_empty() && domain.contains('.') => {
+                if !seen_emails.insert(mail.clone()) {
+                    problems.push(format!("email {} appears more than once", mail));
+                }
+            }
+            _ => problems.push(format!("invalid email \"{}\"", mail)),
+        }
+
+        if date.is_none() {
+            problems.push("invalid birth date".to_string());
+        }
+
+        match (problems.is_empty(), date) {
+            (true, Some(d)) => roster.rows.push(RosterRow {
+                row,
+                user_id: id,
+                fullname: name,
+                email: mail,
+                birth_date: d,
+                birth_place: birth_place.map(get).unwrap_or_default(),
+            }),
+            _ => roster.errors.push(RowError {
+                row,
+                message: problems.join(", "),
+            }),
+        }
+    }
+
+    Ok(roster)
+}
+
+/// Parses a sheet of `user_id, mark` pairs whose first row holds the headers.
+pub fn extract_marks(range: &Range<DataType>) -> Result<MarkSheet, ErrorKind> {
+    let mut rows = range.rows();
+
+    let header = match rows.next() {
+        Some(h) => h,
+        None => return Err(ErrorKind::MissingColumn(USER_ID[0])),
+    };
+
+    let user_id = required(header, USER_ID)?;
+    let mark = required(header, MARK)?;
+
+    let first_row = range.start().map(|(r, _)| r as usize).unwrap_or(0) + 2;
+
+    let mut sheet = MarkSheet::default();
+    let mut seen_ids = HashSet::new();
+
+    for (i, cells) in rows.enumerate() {
+        let row = first_row + i;
+
+        if cells.iter().all(|c| cell_string(c).is_empty()) {
+            continue;
+        }
+
+        let mut problems = Vec::new();
+
+        let id = cells.get(user_id).map(cell_string).unwrap_or_default();
+        let value = cells.get(mark).and_then(cell_mark);
+
+        if id.is_empty() {
+            problems.push("missing NIS".to_string());
+        } else if !seen_ids.insert(id.clone()) {
+            problems.push(format!("NIS {} appears more than once", id));
+        }
+
+        if value.is_none() {
+            let raw = cells.get(mark).map(cell_string).unwrap_or_default();
+            problems.push(format!("invalid mark \"{}\"", raw));
+        }
+
+        match (problems.is_empty(), value) {
+            (true, Some(m)) => sheet.rows.push(MarkRow {
+                row,
+                user_id: id,
+                mark: m,
+            }),
+            _ => sheet.errors.push(RowError {
+                row,
+                message: problems.join(", "),
+            }),
+        }
+    }
+
+    Ok(sheet)
+}
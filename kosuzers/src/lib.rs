@@ -46,3 +46,10 @@
 
 
 */
+
+pub mod creator;
+pub mod errors;
+pub mod extractor;
+
+#[cfg(test)]
+mod tests;
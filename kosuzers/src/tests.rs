@@ -0,0 +1,149 @@
+use calamine::{DataType, Range};
+use chrono::NaiveDate;
+
+use crate::creator::{column_name, Cell, Style, Workbook};
+use crate::errors::ErrorKind;
+use crate::extractor::{extract_marks, extract_roster, read_sheet, read_table};
+
+fn sheet(rows: Vec<Vec<DataType>>) -> Range<DataType> {
+    let width = rows.iter().map(|r| r.len()).max().unwrap_or(1) as u32;
+
+    let mut range = Range::new((0, 0), (rows.len() as u32 - 1, width - 1));
+
+    for (r, row) in rows.into_iter().enumerate() {
+        for (c, cell) in row.into_iter().enumerate() {
+            range.set_value((r as u32, c as u32), cell);
+        }
+    }
+
+    range
+}
+
+fn text(s: &str) -> DataType {
+    DataType::String(s.to_string())
+}
+
+#[test]
+fn roster_rows_are_parsed() {
+    let range = sheet(vec![
+        vec![text("NIS"), text("Full Name"), text("E-mail"), text("Birth Date")],
+        // 37622 is 2003-01-01 as an Excel date
+        vec![DataType::Float(1001.0), text("Siti"), text("Siti@Mail.com"), DataType::DateTime(37622.0)],
+        vec![text("1002"), text("Budi"), text("budi@mail.com"), text("15/08/2004")],
+    ]);
+
+    let roster = extract_roster(&range).unwrap();
+
+    assert!(roster.errors.is_empty());
+    assert_eq!(roster.rows.len(), 2);
+    assert_eq!(roster.rows[0].user_id, "1001");
+    assert_eq!(roster.rows[0].email, "siti@mail.com");
+    assert_eq!(roster.rows[0].birth_date, NaiveDate::from_ymd_opt(2003, 1, 1).unwrap());
+    assert_eq!(roster.rows[1].birth_date, NaiveDate::from_ymd_opt(2004, 8, 15).unwrap());
+    assert_eq!(roster.rows[1].row, 3);
+}
+
+#[test]
+fn roster_errors_are_reported_per_row() {
+    let range = sheet(vec![
+        vec![text("nis"), text("nama"), text("email"), text("tanggal lahir")],
+        vec![text("1001"), text("Siti"), text("siti@mail.com"), text("2003-01-01")],
+        vec![text("1001"), text(""), text("not-an-email"), text("yesterday")],
+        vec![DataType::Empty, DataType::Empty, DataType::Empty, DataType::Empty],
+        vec![text(""), text("Budi"), text("siti@mail.com"), text("2004-08-15")],
+    ]);
+
+    let roster = extract_roster(&range).unwrap();
+
+    assert_eq!(roster.rows.len(), 1);
+    assert_eq!(roster.errors.len(), 2);
+
+    assert_eq!(roster.errors[0].row, 3);
+    assert!(roster.errors[0].message.contains("more than once"));
+    assert!(roster.errors[0].message.contains("full name"));
+    assert!(roster.errors[0].message.contains("invalid email"));
+    assert!(roster.errors[0].message.contains("birth date"));
+
+    // The blank line is skipped but still counts towards row numbers
+    assert_eq!(roster.errors[1].row, 5);
+    assert!(roster.errors[1].message.contains("missing NIS"));
+}
+
+#[test]
+fn roster_needs_its_columns() {
+    let range = sheet(vec![vec![text("NIS"), text("Full Name"), text("Birth Date")]]);
+
+    match extract_roster(&range) {
+        Err(ErrorKind::MissingColumn(column)) => assert_eq!(column, "email"),
+        _ => panic!("expected a missing column"),
+    }
+}
+
+#[test]
+fn column_names() {
+    assert_eq!(column_name(0), "A");
+    assert_eq!(column_name(25), "Z");
+    assert_eq!(column_name(26), "AA");
+    assert_eq!(column_name(701), "ZZ");
+    assert_eq!(column_name(702), "AAA");
+}
+
+#[test]
+fn workbook_reads_back() {
+    let bytes = Workbook::new()
+        .sheet(
+            "Grades: 10/A",
+            vec![
+                vec![Cell::text("Name").style(Style::Bold), Cell::text("Score & <notes>")],
+                vec![Cell::text("Siti"), Cell::number(87.5).style(Style::Highlight)],
+                vec![Cell::text("Budi"), Cell::empty(), Cell::bool(true)],
+            ],
+        )
+        .to_bytes()
+        .unwrap();
+
+    let range = read_sheet(bytes).unwrap();
+
+    assert_eq!(range.get_value((0, 1)), Some(&DataType::String("Score & <notes>".to_string())));
+    assert_eq!(range.get_value((1, 1)), Some(&DataType::Float(87.5)));
+    assert_eq!(range.get_value((2, 1)), Some(&DataType::Empty));
+    assert_eq!(range.get_value((2, 2)), Some(&DataType::Bool(true)));
+}
+
+#[test]
+fn marks_are_read_from_csv() {
+    let csv = "NIS,Nilai\n1001,90\n\"1002\", 75.0 \n1003,7.5\n1001,80\n,\n1004,\n";
+
+    let range = read_table(csv.as_bytes().to_vec()).unwrap();
+    let sheet = extract_marks(&range).unwrap();
+
+    assert_eq!(sheet.rows.len(), 2);
+    assert_eq!(sheet.rows[1].user_id, "1002");
+    assert_eq!(sheet.rows[1].mark, 75);
+
+    assert_eq!(sheet.errors.len(), 3);
+    assert_eq!(sheet.errors[0].row, 4);
+    assert!(sheet.errors[0].message.contains("invalid mark \"7.5\""));
+    assert!(sheet.errors[1].message.contains("more than once"));
+    assert_eq!(sheet.errors[2].row, 7);
+}
+
+#[test]
+fn marks_are_read_from_xlsx() {
+    let bytes = Workbook::new()
+        .sheet(
+            "Marks",
+            vec![
+                vec![Cell::text("user id"), Cell::text("Score")],
+                vec![Cell::text("1001"), Cell::number(88.0)],
+            ],
+        )
+        .to_bytes()
+        .unwrap();
+
+    let sheet = extract_marks(&read_table(bytes).unwrap()).unwrap();
+
+    assert!(sheet.errors.is_empty());
+    assert_eq!(sheet.rows[0].user_id, "1001");
+    assert_eq!(sheet.rows[0].mark, 88);
+}
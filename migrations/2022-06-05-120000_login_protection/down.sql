@@ -0,0 +1,3 @@
+DROP TABLE audit_logs;
+DROP TABLE account_locks;
+DROP TABLE login_attempts
\ No newline at end of file
@@ -0,0 +1,29 @@
+CREATE TABLE login_attempts(
+    id VARCHAR NOT NULL PRIMARY KEY,
+    user_id VARCHAR,
+    ip VARCHAR NOT NULL,
+    success BOOLEAN NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE TABLE account_locks(
+    user_id VARCHAR NOT NULL PRIMARY KEY,
+    failed_attempts INT NOT NULL,
+    last_failed_at TIMESTAMP,
+    locked_until TIMESTAMP,
+
+    FOREIGN KEY (user_id) REFERENCES users(user_id) ON DELETE CASCADE
+);
+
+CREATE TABLE audit_logs(
+    id VARCHAR NOT NULL PRIMARY KEY,
+    action VARCHAR NOT NULL,
+    user_id VARCHAR,
+    actor_id VARCHAR,
+    ip VARCHAR,
+    detail TEXT,
+    created_at TIMESTAMP NOT NULL,
+
+    FOREIGN KEY (user_id) REFERENCES users(user_id) ON DELETE SET NULL,
+    FOREIGN KEY (actor_id) REFERENCES users(user_id) ON DELETE SET NULL
+)
@@ -0,0 +1,8 @@
+ALTER TABLE users ALTER COLUMN system_role DROP DEFAULT;
+
+UPDATE users SET system_role = 'teacher'
+WHERE system_role = 'user' AND user_id IN (SELECT user_id FROM teachers);
+
+UPDATE users SET system_role = 'student' WHERE system_role = 'user';
+
+ALTER TABLE users RENAME COLUMN system_role TO status;
@@ -0,0 +1,6 @@
+-- Class roles now come from students/teachers/admins, only school admins keep a global one
+ALTER TABLE users RENAME COLUMN status TO system_role;
+
+UPDATE users SET system_role = 'user' WHERE system_role <> 'admin';
+
+ALTER TABLE users ALTER COLUMN system_role SET DEFAULT 'user';
@@ -0,0 +1,4 @@
+ALTER TABLE marks DROP CONSTRAINT marks_submission_id_fkey;
+
+ALTER TABLE marks ADD CONSTRAINT marks_submission_id_fkey
+    FOREIGN KEY (submission_id) REFERENCES submissions(submission_id);
@@ -0,0 +1,5 @@
+-- Marks go away with their submission, so marked assignments can still be deleted
+ALTER TABLE marks DROP CONSTRAINT marks_submission_id_fkey;
+
+ALTER TABLE marks ADD CONSTRAINT marks_submission_id_fkey
+    FOREIGN KEY (submission_id) REFERENCES submissions(submission_id) ON DELETE CASCADE;
@@ -0,0 +1,5 @@
+ALTER TABLE submissions DROP COLUMN excused;
+
+ALTER TABLE assignments DROP COLUMN category_id;
+
+DROP TABLE grade_categories;
@@ -0,0 +1,15 @@
+CREATE TABLE grade_categories (
+    id VARCHAR PRIMARY KEY NOT NULL,
+    class_id VARCHAR NOT NULL,
+    name VARCHAR NOT NULL,
+    weight INT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+
+    FOREIGN KEY (class_id) REFERENCES classes(class_id) ON DELETE CASCADE
+);
+
+ALTER TABLE assignments ADD COLUMN category_id VARCHAR
+    REFERENCES grade_categories(id) ON DELETE SET NULL;
+
+-- Excused work is left out of a student's grade instead of counting as missing
+ALTER TABLE submissions ADD COLUMN excused BOOLEAN NOT NULL DEFAULT FALSE;
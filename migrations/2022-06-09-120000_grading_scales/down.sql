@@ -0,0 +1,3 @@
+DROP TABLE grade_bands;
+
+DROP TABLE grading_scales;
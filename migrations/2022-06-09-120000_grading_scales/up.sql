@@ -0,0 +1,18 @@
+-- KKM is the minimum passing score, out of 100
+CREATE TABLE grading_scales (
+    class_id VARCHAR PRIMARY KEY NOT NULL,
+    kkm INT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+
+    FOREIGN KEY (class_id) REFERENCES classes(class_id) ON DELETE CASCADE
+);
+
+CREATE TABLE grade_bands (
+    id VARCHAR PRIMARY KEY NOT NULL,
+    class_id VARCHAR NOT NULL,
+    min_score INT NOT NULL,
+    label VARCHAR NOT NULL,
+    description VARCHAR,
+
+    FOREIGN KEY (class_id) REFERENCES grading_scales(class_id) ON DELETE CASCADE
+);
@@ -0,0 +1,5 @@
+DROP TABLE criterion_scores;
+
+DROP TABLE rubric_levels;
+
+DROP TABLE rubric_criteria;
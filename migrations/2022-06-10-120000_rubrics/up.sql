@@ -0,0 +1,32 @@
+CREATE TABLE rubric_criteria (
+    id VARCHAR PRIMARY KEY NOT NULL,
+    assignment_id VARCHAR NOT NULL,
+    title VARCHAR NOT NULL,
+    description VARCHAR,
+    position INT NOT NULL,
+
+    FOREIGN KEY (assignment_id) REFERENCES assignments(assignment_id) ON DELETE CASCADE
+);
+
+CREATE TABLE rubric_levels (
+    id VARCHAR PRIMARY KEY NOT NULL,
+    criterion_id VARCHAR NOT NULL,
+    title VARCHAR NOT NULL,
+    description VARCHAR,
+    points INT NOT NULL,
+
+    FOREIGN KEY (criterion_id) REFERENCES rubric_criteria(id) ON DELETE CASCADE
+);
+
+CREATE TABLE criterion_scores (
+    id VARCHAR PRIMARY KEY NOT NULL,
+    mark_id VARCHAR NOT NULL,
+    criterion_id VARCHAR NOT NULL,
+    level_id VARCHAR NOT NULL,
+    points INT NOT NULL,
+    comment TEXT,
+
+    FOREIGN KEY (mark_id) REFERENCES marks(id) ON DELETE CASCADE,
+    FOREIGN KEY (criterion_id) REFERENCES rubric_criteria(id) ON DELETE CASCADE,
+    FOREIGN KEY (level_id) REFERENCES rubric_levels(id) ON DELETE CASCADE
+);
@@ -0,0 +1,3 @@
+ALTER TABLE assignments DROP COLUMN close_date;
+ALTER TABLE assignments DROP COLUMN late_penalty;
+ALTER TABLE assignments DROP COLUMN late_policy;
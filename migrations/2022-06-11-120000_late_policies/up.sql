@@ -0,0 +1,4 @@
+-- late_policy is one of 'accept', 'reject', 'flat' or 'per_day', late_penalty a percentage of the total
+ALTER TABLE assignments ADD COLUMN late_policy VARCHAR NOT NULL DEFAULT 'accept';
+ALTER TABLE assignments ADD COLUMN late_penalty INT;
+ALTER TABLE assignments ADD COLUMN close_date TIMESTAMP;
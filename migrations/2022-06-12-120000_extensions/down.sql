@@ -0,0 +1,3 @@
+DROP TABLE extensions;
+ALTER TABLE submissions DROP COLUMN due_time;
+ALTER TABLE submissions DROP COLUMN due_date;
@@ -0,0 +1,13 @@
+-- A student's own deadline, overriding the assignment's while set
+ALTER TABLE submissions ADD COLUMN due_date DATE;
+ALTER TABLE submissions ADD COLUMN due_time TIME;
+
+CREATE TABLE extensions (
+    id VARCHAR PRIMARY KEY,
+    submission_id VARCHAR NOT NULL REFERENCES submissions(submission_id) ON DELETE CASCADE,
+    granted_by VARCHAR REFERENCES users(user_id) ON DELETE SET NULL,
+    due_date DATE,
+    due_time TIME,
+    reason TEXT,
+    created_at TIMESTAMP NOT NULL
+);
@@ -0,0 +1,3 @@
+DROP TABLE submission_events;
+ALTER TABLE submissions DROP COLUMN resubmit_allowed;
+ALTER TABLE submissions DROP COLUMN state;
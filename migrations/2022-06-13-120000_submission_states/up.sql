@@ -0,0 +1,16 @@
+-- state is one of 'assigned', 'turned_in', 'late', 'returned', 'resubmitted' or 'missing'
+ALTER TABLE submissions ADD COLUMN state VARCHAR NOT NULL DEFAULT 'assigned';
+ALTER TABLE submissions ADD COLUMN resubmit_allowed BOOLEAN NOT NULL DEFAULT FALSE;
+
+UPDATE submissions SET state = 'turned_in' WHERE submitted AND on_time IS NOT FALSE;
+UPDATE submissions SET state = 'late' WHERE submitted AND on_time IS FALSE;
+
+CREATE TABLE submission_events (
+    id VARCHAR PRIMARY KEY,
+    submission_id VARCHAR NOT NULL REFERENCES submissions(submission_id) ON DELETE CASCADE,
+    from_state VARCHAR,
+    to_state VARCHAR NOT NULL,
+    actor_id VARCHAR REFERENCES users(user_id) ON DELETE SET NULL,
+    note TEXT,
+    created_at TIMESTAMP NOT NULL
+);
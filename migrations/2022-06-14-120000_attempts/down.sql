@@ -0,0 +1,3 @@
+DROP TABLE attempt_files;
+DROP TABLE attempts;
+ALTER TABLE assignments DROP COLUMN max_attempts;
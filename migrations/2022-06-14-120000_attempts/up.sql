@@ -0,0 +1,30 @@
+-- No limit while max_attempts is empty
+ALTER TABLE assignments ADD COLUMN max_attempts INT;
+
+CREATE TABLE attempts (
+    id VARCHAR PRIMARY KEY NOT NULL,
+    submission_id VARCHAR NOT NULL,
+    number INT NOT NULL,
+    state VARCHAR NOT NULL,
+    on_time BOOLEAN,
+    submitted_at TIMESTAMP NOT NULL,
+    mark INT,
+    marker_id VARCHAR,
+
+    UNIQUE (submission_id, number),
+    FOREIGN KEY (submission_id) REFERENCES submissions(submission_id) ON DELETE CASCADE,
+    FOREIGN KEY (marker_id) REFERENCES users(user_id) ON DELETE SET NULL
+);
+
+-- What was attached when the attempt was handed in. Files and links held
+-- here outlive the attachments they were copied from.
+CREATE TABLE attempt_files (
+    id VARCHAR PRIMARY KEY NOT NULL,
+    attempt_id VARCHAR NOT NULL,
+    file_id VARCHAR,
+    link_id VARCHAR,
+
+    FOREIGN KEY (attempt_id) REFERENCES attempts(id) ON DELETE CASCADE,
+    FOREIGN KEY (file_id) REFERENCES files(file_id) ON DELETE CASCADE,
+    FOREIGN KEY (link_id) REFERENCES links(id) ON DELETE CASCADE
+);
@@ -0,0 +1,3 @@
+DROP TABLE assignment_targets;
+DROP TABLE student_group_members;
+DROP TABLE student_groups;
@@ -0,0 +1,32 @@
+CREATE TABLE student_groups (
+    id VARCHAR PRIMARY KEY NOT NULL,
+    class_id VARCHAR NOT NULL,
+    name VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+
+    UNIQUE (class_id, name),
+    FOREIGN KEY (class_id) REFERENCES classes(class_id) ON DELETE CASCADE
+);
+
+CREATE TABLE student_group_members (
+    group_id VARCHAR NOT NULL,
+    user_id VARCHAR NOT NULL,
+
+    PRIMARY KEY (group_id, user_id),
+    FOREIGN KEY (group_id) REFERENCES student_groups(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(user_id) ON DELETE CASCADE
+);
+
+-- An assignment without targets is for the whole class. Each target is
+-- either one student or one group.
+CREATE TABLE assignment_targets (
+    id VARCHAR PRIMARY KEY NOT NULL,
+    assignment_id VARCHAR NOT NULL,
+    user_id VARCHAR,
+    group_id VARCHAR,
+
+    CHECK ((user_id IS NULL) <> (group_id IS NULL)),
+    FOREIGN KEY (assignment_id) REFERENCES assignments(assignment_id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(user_id) ON DELETE CASCADE,
+    FOREIGN KEY (group_id) REFERENCES student_groups(id) ON DELETE CASCADE
+);
@@ -0,0 +1,4 @@
+ALTER TABLE submissions DROP COLUMN mark_adjustment;
+ALTER TABLE submissions DROP COLUMN group_id;
+
+ALTER TABLE assignments DROP COLUMN group_work;
@@ -0,0 +1,8 @@
+-- Group work is done in teams, the groups the assignment targets. Members
+-- of a team hand in, get back and are marked on their work together.
+ALTER TABLE assignments ADD COLUMN group_work BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE submissions ADD COLUMN group_id VARCHAR;
+ALTER TABLE submissions ADD COLUMN mark_adjustment INT NOT NULL DEFAULT 0;
+ALTER TABLE submissions
+    ADD FOREIGN KEY (group_id) REFERENCES student_groups(id) ON DELETE SET NULL;
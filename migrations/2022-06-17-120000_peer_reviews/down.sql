@@ -0,0 +1,5 @@
+DROP TABLE peer_review_scores;
+DROP TABLE peer_reviews;
+
+ALTER TABLE assignments DROP COLUMN peer_weight;
+ALTER TABLE assignments DROP COLUMN peer_reviewers;
@@ -0,0 +1,35 @@
+-- With peer_reviewers set, every piece of work handed in goes to that many
+-- other students once it's due. peer_weight is the percentage of the final
+-- mark made up by their average score.
+ALTER TABLE assignments ADD COLUMN peer_reviewers INT;
+ALTER TABLE assignments ADD COLUMN peer_weight INT NOT NULL DEFAULT 0;
+
+-- The comment is a private comment on the reviewed submission, shown to its
+-- student without the reviewer's name
+CREATE TABLE peer_reviews (
+    id VARCHAR PRIMARY KEY NOT NULL,
+    submission_id VARCHAR NOT NULL,
+    reviewer_id VARCHAR NOT NULL,
+    score INT,
+    comment_id VARCHAR,
+    created_at TIMESTAMP NOT NULL,
+    reviewed_at TIMESTAMP,
+
+    UNIQUE (submission_id, reviewer_id),
+    FOREIGN KEY (submission_id) REFERENCES submissions(submission_id) ON DELETE CASCADE,
+    FOREIGN KEY (reviewer_id) REFERENCES users(user_id) ON DELETE CASCADE,
+    FOREIGN KEY (comment_id) REFERENCES private_comments(id) ON DELETE SET NULL
+);
+
+CREATE TABLE peer_review_scores (
+    id VARCHAR PRIMARY KEY NOT NULL,
+    review_id VARCHAR NOT NULL,
+    criterion_id VARCHAR NOT NULL,
+    level_id VARCHAR NOT NULL,
+    points INT NOT NULL,
+    comment TEXT,
+
+    FOREIGN KEY (review_id) REFERENCES peer_reviews(id) ON DELETE CASCADE,
+    FOREIGN KEY (criterion_id) REFERENCES rubric_criteria(id) ON DELETE CASCADE,
+    FOREIGN KEY (level_id) REFERENCES rubric_levels(id) ON DELETE CASCADE
+);
@@ -0,0 +1,7 @@
+-- The absolute paths are gone, paths relative to the working directory are
+-- the closest thing
+UPDATE files SET file_path = 'media/' || file_path;
+
+UPDATE files SET file_url = regexp_replace(file_url, '/api/media/(profiles|classes)/', '/api/media/img/\1/');
+UPDATE users SET profile_photo = regexp_replace(profile_photo, '/api/media/(profiles|classes|placeholder)', '/api/media/img/\1');
+UPDATE classes SET class_image = regexp_replace(class_image, '/api/media/(profiles|classes|placeholder)', '/api/media/img/\1');
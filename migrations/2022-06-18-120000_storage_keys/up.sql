@@ -0,0 +1,7 @@
+-- Files are stored under keys relative to the media root, and served from
+-- /api/media/<key> whichever backend holds them
+UPDATE files SET file_path = regexp_replace(file_path, '^.*/media/', '');
+
+UPDATE files SET file_url = replace(file_url, '/api/media/img/', '/api/media/');
+UPDATE users SET profile_photo = replace(profile_photo, '/api/media/img/', '/api/media/');
+UPDATE classes SET class_image = replace(class_image, '/api/media/img/', '/api/media/');
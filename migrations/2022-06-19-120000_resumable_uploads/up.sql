@@ -0,0 +1,32 @@
+-- tus uploads in progress. Each chunk received is kept in storage under
+-- uploads/<upload id>/<chunk id> until the last one is in, when they're put
+-- together into a file and attached like a regular upload.
+CREATE TABLE resumable_uploads (
+    id VARCHAR PRIMARY KEY NOT NULL,
+    uploader VARCHAR NOT NULL,
+    upload_length BIGINT NOT NULL,
+    upload_offset BIGINT NOT NULL DEFAULT 0,
+    filename VARCHAR NOT NULL,
+    filetype VARCHAR,
+    assignment_id VARCHAR,
+    announcement_id VARCHAR,
+    submission_id VARCHAR,
+    attachment_id VARCHAR,
+    created_at TIMESTAMP NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+
+    FOREIGN KEY (uploader) REFERENCES users(user_id) ON DELETE CASCADE,
+    FOREIGN KEY (assignment_id) REFERENCES assignments(assignment_id) ON DELETE CASCADE,
+    FOREIGN KEY (announcement_id) REFERENCES announcements(announcement_id) ON DELETE CASCADE,
+    FOREIGN KEY (submission_id) REFERENCES submissions(submission_id) ON DELETE CASCADE,
+    FOREIGN KEY (attachment_id) REFERENCES attachments(attachment_id) ON DELETE SET NULL
+);
+
+CREATE TABLE resumable_upload_chunks (
+    id VARCHAR PRIMARY KEY NOT NULL,
+    upload_id VARCHAR NOT NULL,
+    chunk_offset BIGINT NOT NULL,
+    size BIGINT NOT NULL,
+
+    FOREIGN KEY (upload_id) REFERENCES resumable_uploads(id) ON DELETE CASCADE
+);
@@ -0,0 +1,15 @@
+-- The sizes a profile photo or class picture is served in, made when it's
+-- uploaded. Each is stored under <folder>/<file id>/<variant>.<extension>.
+CREATE TABLE file_variants (
+    id VARCHAR PRIMARY KEY NOT NULL,
+    file_id VARCHAR NOT NULL,
+    variant VARCHAR NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    file_path VARCHAR NOT NULL,
+    file_url VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+
+    FOREIGN KEY (file_id) REFERENCES files(file_id) ON DELETE CASCADE,
+    UNIQUE (file_id, variant)
+);
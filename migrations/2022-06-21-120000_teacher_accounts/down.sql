@@ -0,0 +1 @@
+UPDATE users SET system_role = 'user' WHERE system_role = 'teacher';
@@ -0,0 +1,4 @@
+-- Only teacher accounts and school admins create and co-teach classes.
+-- Whoever teaches a class already keeps being able to.
+UPDATE users SET system_role = 'teacher'
+WHERE system_role = 'user' AND user_id IN (SELECT user_id FROM teachers);
@@ -0,0 +1 @@
+ALTER TABLE marks DROP COLUMN as_of;
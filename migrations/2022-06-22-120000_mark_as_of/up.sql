@@ -0,0 +1,4 @@
+-- When the work a mark is for was handed in, set when an earlier attempt
+-- was marked so the late penalty keeps to it when the mark is worked out
+-- again. Marks without one go by the latest hand-in.
+ALTER TABLE marks ADD COLUMN as_of TIMESTAMP;
@@ -0,0 +1,8 @@
+CREATE TABLE verification_resends(
+    id VARCHAR NOT NULL PRIMARY KEY,
+    user_id VARCHAR,
+    ip VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+
+    FOREIGN KEY (user_id) REFERENCES users(user_id) ON DELETE CASCADE
+);
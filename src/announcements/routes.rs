@@ -1,9 +1,10 @@
+use diesel::PgConnection;
 use rocket::serde::json::Json;
 use rocket::serde::json::serde_json::json;
 use rocket_dyn_templates::handlebars::JsonValue;
 use crate::announcements::models::Announcement;
 use crate::announcements::models::FillableAnnouncement;
-use crate::auth::ClassGuard;
+use crate::policy::{Can, CreateAnnouncement, DeleteAnnouncement, ViewClass};
 use crate::{db, Status};
 use crate::attachments::models::Attachment;
 use crate::comments::models::Comment;
@@ -11,33 +12,44 @@ use crate::traits::{ClassUser, Manipulable};
 use crate::users::models::{Student, User};
 use crate::utils::{get_attachments, get_comments, send_mail};
 
+/// The announcement, as long as it was posted in the class.
+fn class_announcement(class_id: &str, announcement_id: &str, conn: &PgConnection) -> Result<Announcement, Status> {
+    match Announcement::find_announcement(conn, announcement_id) {
+        Ok(a) if a.class_id.as_deref() == Some(class_id) => Ok(a),
+        _ => Err(Status::NotFound),
+    }
+}
+
 #[get("/<class_id>/announcements")]
-pub fn get_announcements(key: ClassGuard, class_id: &str, conn: db::DbConn) -> Json<Vec<Announcement>> {
+pub fn get_announcements(key: Can<ViewClass>, class_id: &str, conn: db::DbConn) -> Json<Vec<Announcement>> {
     let announcements = Announcement::load_in_class(&conn, class_id).unwrap();
     Json(announcements)
 }
 
 #[get("/<class_id>/announcements/<announcement_id>")]
-pub fn get_announcement(key: ClassGuard, class_id: &str, announcement_id: &str, conn: db::DbConn) -> Json<JsonValue> {
-    let announcement = Announcement::find_announcement(&conn, announcement_id).unwrap();
+pub fn get_announcement(key: Can<ViewClass>, class_id: &str, announcement_id: &str, conn: db::DbConn) -> Result<Json<JsonValue>, Status> {
+    let announcement = class_announcement(class_id, announcement_id, &conn)?;
 
     let comments = Comment::load_by_announcement(announcement_id, &conn).unwrap();
 
-    let comment_response = get_comments(&comments, &conn);
+    let comment_response = match get_comments(&comments, &conn) {
+        Ok(c) => c,
+        Err(_) => return Err(Status::InternalServerError),
+    };
 
     let attachments = Attachment::load_by_announcement_id(&announcement_id.to_string(), &conn).unwrap();
 
     let attachment_response = get_attachments(&attachments, &conn);
 
-    Json(json!({
+    Ok(Json(json!({
         "announcement": announcement,
         "comments": comment_response,
         "attachments": attachment_response
-    }))
+    })))
 }
 
 #[post("/<class_id>/announcements")]
-pub fn draft_announcement(key: ClassGuard, class_id: &str, conn: db::DbConn) -> Json<JsonValue> {
+pub fn draft_announcement(key: Can<CreateAnnouncement>, class_id: &str, conn: db::DbConn) -> Json<JsonValue> {
     let default = Announcement::default();
 
     default.draft(&conn);
@@ -46,7 +58,7 @@ pub fn draft_announcement(key: ClassGuard, class_id: &str, conn: db::DbConn) ->
 }
 
 #[patch("/<class_id>/announcements", data = "<announcement>")]
-pub async fn update_announcement(key: ClassGuard, class_id: &str, announcement: Json<FillableAnnouncement>, conn: db::DbConn) -> Result<Json<Announcement>, Status> {
+pub async fn update_announcement(key: Can<CreateAnnouncement>, class_id: &str, announcement: Json<FillableAnnouncement>, conn: db::DbConn) -> Result<Json<Announcement>, Status> {
     let data = announcement.into_inner();
 
     let creator = match User::find_user(&key.0, &conn)
@@ -55,7 +67,17 @@ pub async fn update_announcement(key: ClassGuard, class_id: &str, announcement:
         Err(_) => return Err(Status::NotFound)
     };
 
-    let announcement = Announcement::find_announcement(&conn, &data.announcement_id).unwrap();
+    // Drafts get their class when they're posted, and only the class they're
+    // posted in
+    let announcement = match Announcement::find_announcement(&conn, &data.announcement_id) {
+        Ok(a) if a.class_id.is_none() || a.class_id.as_deref() == Some(class_id) => a,
+        _ => return Err(Status::NotFound),
+    };
+
+    let data = FillableAnnouncement {
+        class_id: Some(class_id.to_string()),
+        ..data
+    };
 
     let update = announcement.update(data, &conn).unwrap();
 
@@ -89,8 +111,8 @@ pub async fn update_announcement(key: ClassGuard, class_id: &str, announcement:
 }
 
 #[delete("/<class_id>/announcements/<announcement_id>")]
-pub fn delete_announcement(key: ClassGuard, class_id: &str, announcement_id: &str, conn: db::DbConn) -> Result<Json<JsonValue>, Status> {
-    let announcement = Announcement::find_announcement(&conn, &announcement_id).unwrap();
+pub fn delete_announcement(key: Can<DeleteAnnouncement>, class_id: &str, announcement_id: &str, conn: db::DbConn) -> Result<Json<JsonValue>, Status> {
+    let announcement = class_announcement(class_id, announcement_id, &conn)?;
 
     announcement.delete(&conn).unwrap();
 
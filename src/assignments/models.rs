@@ -1,12 +1,19 @@
-use crate::errors::ThearningResult;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::assignments::utils::{assignees, days_late, deadline, on_time};
+use crate::errors::{ErrorKind, ThearningResult};
 use chrono::{Local, NaiveDate, NaiveDateTime, NaiveTime};
 use diesel;
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use crate::schema::assignments;
-use crate::traits::Manipulable;
+use crate::groups::models::StudentGroup;
+use crate::schema::{assignment_targets, assignments, submissions};
+use crate::submissions::models::{FillableSubmissions, Submissions};
+use crate::traits::{ClassUser, Manipulable};
+use crate::users::models::Student;
 use crate::utils::generate_random_id;
 
 #[derive(Serialize, Deserialize, Queryable, Insertable, Clone)]
@@ -24,6 +31,14 @@ pub struct Assignment {
     pub created_at: NaiveDateTime,
     pub creator: Option<String>,
     pub draft: bool,
+    pub category_id: Option<String>,
+    pub late_policy: String,
+    pub late_penalty: Option<i32>,
+    pub close_date: Option<NaiveDateTime>,
+    pub max_attempts: Option<i32>,
+    pub group_work: bool,
+    pub peer_reviewers: Option<i32>,
+    pub peer_weight: i32,
 }
 
 #[derive(Serialize, Deserialize, Queryable, Insertable, Clone)]
@@ -37,6 +52,85 @@ pub struct FillableAssignments {
     pub instructions: Option<String>,
     pub total_marks: Option<i32>,
     pub creator: Option<String>,
+    #[serde(default)]
+    pub category_id: Option<String>,
+    #[serde(default = "default_late_policy")]
+    pub late_policy: String,
+    #[serde(default)]
+    pub late_penalty: Option<i32>,
+    #[serde(default)]
+    pub close_date: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub max_attempts: Option<i32>,
+    /// One submission per targeted group, shared by its members
+    #[serde(default)]
+    pub group_work: bool,
+    /// How many other students review each piece of work, none without
+    #[serde(default)]
+    pub peer_reviewers: Option<i32>,
+    /// Percentage of the final mark that comes from peer review
+    #[serde(default)]
+    pub peer_weight: i32,
+}
+
+fn default_late_policy() -> String {
+    LatePolicy::Accept.to_string()
+}
+
+/// What happens to work handed in after it's due. `Flat` takes
+/// `late_penalty` percent of the total off once, `PerDay` for every day late.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LatePolicy {
+    Accept,
+    Reject,
+    Flat,
+    PerDay,
+}
+
+impl TryFrom<&str> for LatePolicy {
+    type Error = ErrorKind;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "accept" => Ok(Self::Accept),
+            "reject" => Ok(Self::Reject),
+            "flat" => Ok(Self::Flat),
+            "per_day" => Ok(Self::PerDay),
+            _ => Err(ErrorKind::InvalidValue),
+        }
+    }
+}
+
+impl fmt::Display for LatePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let policy = match self {
+            Self::Accept => "accept",
+            Self::Reject => "reject",
+            Self::Flat => "flat",
+            Self::PerDay => "per_day",
+        };
+
+        write!(f, "{}", policy)
+    }
+}
+
+/// Marks taken off a piece of work worth `total` that's `days_late` days
+/// late, never more than the total.
+pub fn late_penalty(policy: LatePolicy, percent: i32, total: i32, days_late: i64) -> i32 {
+    if days_late <= 0 {
+        return 0;
+    }
+
+    let percent = match policy {
+        LatePolicy::Accept | LatePolicy::Reject => 0,
+        LatePolicy::Flat => percent as i64,
+        LatePolicy::PerDay => percent as i64 * days_late,
+    };
+
+    let penalty = (total as f64 * percent.min(100) as f64 / 100.0).round() as i32;
+
+    penalty.min(total)
 }
 
 #[derive(Serialize, Deserialize)]
@@ -44,6 +138,44 @@ pub struct AssignmentData {
     pub id: String,
     pub assignment: FillableAssignments,
     pub attachments: Option<Vec<String>>,
+    /// Left out, the assignment keeps whoever it was for
+    #[serde(default)]
+    pub targets: Option<Targets>,
+}
+
+#[derive(Serialize, Deserialize, Queryable, Insertable, Clone)]
+#[table_name = "assignment_targets"]
+pub struct AssignmentTarget {
+    pub id: String,
+    pub assignment_id: String,
+    pub user_id: Option<String>,
+    pub group_id: Option<String>,
+}
+
+/// Who an assignment is for: some students, some groups, or with neither
+/// the whole class.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+pub struct Targets {
+    #[serde(default)]
+    pub students: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+impl Targets {
+    pub fn is_class(&self) -> bool {
+        self.students.is_empty() && self.groups.is_empty()
+    }
+}
+
+/// What retargeting did to the submissions. Students who already started
+/// on the work keep their submission even when the work isn't theirs
+/// anymore.
+#[derive(Serialize, Default, Debug)]
+pub struct Retargeted {
+    pub assigned: Vec<String>,
+    pub unassigned: Vec<String>,
+    pub kept: Vec<String>,
 }
 
 impl Assignment {
@@ -51,6 +183,43 @@ impl Assignment {
         assignments::table.find(id).get_result::<Self>(conn)
     }
 
+    pub fn due_at(&self) -> Option<NaiveDateTime> {
+        deadline(self.due_date, self.due_time)
+    }
+
+    pub fn late_policy(&self) -> LatePolicy {
+        LatePolicy::try_from(self.late_policy.as_str()).unwrap_or(LatePolicy::Accept)
+    }
+
+    /// Marks taken off for handing the work in at `submitted_at`.
+    pub fn penalty(&self, due: Option<NaiveDateTime>, submitted_at: NaiveDateTime) -> i32 {
+        let total = match self.total_marks {
+            Some(t) if t > 0 => t,
+            _ => 100,
+        };
+
+        match due {
+            Some(d) => late_penalty(self.late_policy(), self.late_penalty.unwrap_or(0), total, days_late(d, submitted_at)),
+            None => 0,
+        }
+    }
+
+    /// Whether work due at `due` can still be handed in at `at`. The close
+    /// date doesn't cut anyone off before their own deadline, so a student
+    /// given an extension past it can still hand in.
+    pub fn accepts_at(&self, due: Option<NaiveDateTime>, at: NaiveDateTime) -> bool {
+        let closed = matches!(self.close_date, Some(c) if at >= c && due.map_or(true, |d| at >= d));
+        let rejected = self.late_policy() == LatePolicy::Reject && on_time(due, at) == Some(false);
+
+        !closed && !rejected
+    }
+
+    pub fn set_total_marks(&self, total_marks: Option<i32>, conn: &PgConnection) -> ThearningResult<Self> {
+        Ok(diesel::update(assignments::table.find(&self.assignment_id))
+            .set(assignments::total_marks.eq(total_marks))
+            .get_result::<Self>(conn)?)
+    }
+
     pub fn draft(&self, conn: &PgConnection) -> QueryResult<Self> {
         diesel::insert_into(assignments::table)
             .values(&*self)
@@ -70,6 +239,145 @@ impl Assignment {
         Ok(a)
     }
 
+    /// The class's published assignments the student has been given.
+    pub fn load_for_student(class_id: &String, user_id: &String, conn: &PgConnection) -> ThearningResult<Vec<Self>> {
+        Ok(assignments::table
+            .inner_join(submissions::table)
+            .filter(assignments::class_id.eq(class_id))
+            .filter(assignments::draft.eq(false))
+            .filter(submissions::user_id.eq(user_id))
+            .select(assignments::all_columns)
+            .load::<Self>(conn)?)
+    }
+
+    pub fn targets(&self, conn: &PgConnection) -> ThearningResult<Targets> {
+        let rows = assignment_targets::table
+            .filter(assignment_targets::assignment_id.eq(&self.assignment_id))
+            .load::<AssignmentTarget>(conn)?;
+
+        Ok(Targets {
+            students: rows.iter().filter_map(|t| t.user_id.clone()).collect(),
+            groups: rows.iter().filter_map(|t| t.group_id.clone()).collect(),
+        })
+    }
+
+    pub fn set_targets(&self, targets: &Targets, conn: &PgConnection) -> ThearningResult<()> {
+        diesel::delete(assignment_targets::table.filter(assignment_targets::assignment_id.eq(&self.assignment_id)))
+            .execute(conn)?;
+
+        let target = |user_id: Option<&String>, group_id: Option<&String>| AssignmentTarget {
+            id: format!("{}{}", generate_random_id(), generate_random_id()),
+            assignment_id: self.assignment_id.clone(),
+            user_id: user_id.cloned(),
+            group_id: group_id.cloned(),
+        };
+
+        let rows = targets
+            .students
+            .iter()
+            .map(|s| target(Some(s), None))
+            .chain(targets.groups.iter().map(|g| target(None, Some(g))))
+            .collect::<Vec<AssignmentTarget>>();
+
+        if !rows.is_empty() {
+            diesel::insert_into(assignment_targets::table)
+                .values(&rows)
+                .execute(conn)?;
+        }
+
+        Ok(())
+    }
+
+    /// The students of the class this assignment is for.
+    pub fn assignees(&self, conn: &PgConnection) -> ThearningResult<Vec<String>> {
+        let class_id = match &self.class_id {
+            Some(c) => c,
+            None => return Ok(Vec::new()),
+        };
+
+        let enrolled = Student::load_in_class(class_id, conn)?
+            .into_iter()
+            .map(|s| s.user_id)
+            .collect::<Vec<String>>();
+
+        let targets = self.targets(conn)?;
+
+        let members = StudentGroup::members_of(&targets.groups, conn)?;
+
+        Ok(assignees(&enrolled, &targets, &members))
+    }
+
+    /// The team each student works in, for group work. Teams are the
+    /// groups the assignment targets.
+    pub fn teams(&self, conn: &PgConnection) -> ThearningResult<HashMap<String, String>> {
+        if !self.group_work {
+            return Ok(HashMap::new());
+        }
+
+        let mut teams = HashMap::new();
+
+        for m in StudentGroup::memberships(&self.targets(conn)?.groups, conn)? {
+            teams.entry(m.user_id).or_insert(m.group_id);
+        }
+
+        Ok(teams)
+    }
+
+    /// Opens a submission for every assignee without one and drops those of
+    /// students it's no longer for, as long as they haven't started on it.
+    /// For group work every submission is put in its student's team, and
+    /// those kept after leaving one go on alone.
+    pub fn sync_submissions(&self, conn: &PgConnection) -> ThearningResult<Retargeted> {
+        let assignees = self.assignees(conn)?;
+
+        let teams = self.teams(conn)?;
+
+        let existing = Submissions::load_by_assignment(&self.assignment_id, conn)?;
+
+        let mut retargeted = Retargeted::default();
+
+        for user_id in &assignees {
+            let team = teams.get(user_id);
+
+            match existing.iter().find(|s| &s.user_id == user_id) {
+                Some(s) if s.group_id.as_ref() != team => {
+                    s.join_team(team, conn)?;
+                }
+                Some(_) => {}
+                None => {
+                    Submissions::create(
+                        FillableSubmissions {
+                            assignment_id: self.assignment_id.clone(),
+                            user_id: user_id.clone(),
+                            group_id: team.cloned(),
+                        },
+                        conn,
+                    )?;
+
+                    retargeted.assigned.push(user_id.clone());
+                }
+            }
+        }
+
+        for submission in existing.iter().filter(|s| !assignees.contains(&s.user_id)) {
+            match submission.is_untouched(conn)? {
+                true => {
+                    submission.remove(conn)?;
+                    retargeted.unassigned.push(submission.user_id.clone());
+                }
+                false => {
+                    if submission.group_id.is_some() {
+                        submission.join_team(None, conn)?;
+                    }
+
+                    retargeted.kept.push(submission.user_id.clone());
+                }
+            }
+        }
+
+        Ok(retargeted)
+    }
+
     pub fn load_by_classuser(class_id: &String, user_id: &String, draft: Option<bool>, conn: &PgConnection) -> ThearningResult<Vec<Self>> {
 
         let a = match draft {
@@ -113,6 +421,14 @@ impl Default for Assignment {
             creator: None,
             created_at: Local::now().naive_local(),
             draft: true,
+            category_id: None,
+            late_policy: LatePolicy::Accept.to_string(),
+            late_penalty: None,
+            close_date: None,
+            max_attempts: None,
+            group_work: false,
+            peer_reviewers: None,
+            peer_weight: 0,
         }
     }
 }
@@ -135,6 +451,14 @@ impl Manipulable<FillableAssignments> for Assignment {
             assignments::instructions.eq(&update.instructions),
             assignments::total_marks.eq(&update.total_marks),
             assignments::creator.eq(&update.creator),
+            assignments::category_id.eq(&update.category_id),
+            assignments::late_policy.eq(&update.late_policy),
+            assignments::late_penalty.eq(&update.late_penalty),
+            assignments::close_date.eq(&update.close_date),
+            assignments::max_attempts.eq(&update.max_attempts),
+            assignments::group_work.eq(&update.group_work),
+            assignments::peer_reviewers.eq(&update.peer_reviewers),
+            assignments::peer_weight.eq(&update.peer_weight),
             assignments::draft.eq(false),
         ))
         .execute(conn)?;
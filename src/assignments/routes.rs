@@ -1,4 +1,6 @@
-use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use chrono::{Local, NaiveDateTime};
+use diesel::dsl::any;
+use diesel::{Connection, ExpressionMethods, QueryDsl, RunQueryDsl};
 use rocket::http::Status;
 use rocket::serde::json::serde_json::json;
 use rocket::serde::json::Json;
@@ -7,22 +9,37 @@ use rocket_dyn_templates::handlebars::JsonValue;
 use tokio;
 
 use crate::assignments::models::AssignmentData;
-use crate::assignments::models::Assignment;
+use crate::assignments::models::{Assignment, LatePolicy};
+use crate::assignments::utils::shared_members;
 use crate::attachments::models::Attachment;
-use crate::auth::ClassGuard;
+use crate::attempts::models::Attempt;
+use crate::policy::{
+    can_delete_assignment, class_assignment, published_assignment, Can, CreateAssignment, DeleteAssignment,
+    SubmitAssignment, ViewSubmissions,
+};
 use crate::comments::models::{Comment, PrivateComment};
 use crate::{db, utils};
 use crate::db::DbConn;
+use crate::gradebook::models::{GradeCategory, GradingScale, ScaledScore};
+use crate::errors::ErrorKind;
+use crate::gradebook::utils::percentage;
+use crate::groups::models::StudentGroup;
+use crate::peer_reviews::models::PeerReview;
 use crate::schema::attachments;
-use crate::submissions::models::{FillableSubmissions, Submissions};
+use crate::rubrics::models::{CriterionScore, Rubric};
+use crate::submissions::models::{Extension, Mark, SubmissionEvent, Submissions};
 use crate::traits::{ClassUser, Manipulable};
 use crate::users::models::{ResponseUser, Student, User};
 use crate::users::routes::get_user;
 use crate::utils::{send_mail, update};
 
 #[post("/<class_id>/assignments")]
-pub fn draft(key: ClassGuard, class_id: &str, conn: db::DbConn) -> Result<Json<JsonValue>, Status> {
-    let default = Assignment::default();
+pub fn draft(key: Can<CreateAssignment>, class_id: &str, conn: db::DbConn) -> Result<Json<JsonValue>, Status> {
+    // Drafts have no class yet, they belong to whoever started them
+    let default = Assignment {
+        creator: Some(key.0.clone()),
+        ..Assignment::default()
+    };
 
     default.draft(&conn);
 
@@ -31,27 +48,80 @@ pub fn draft(key: ClassGuard, class_id: &str, conn: db::DbConn) -> Result<Json<J
 
 #[patch("/<class_id>/assignments", data = "<data>")]
 pub async fn update_assignment(
-    key: ClassGuard,
+    key: Can<CreateAssignment>,
     class_id: &str,
     data: Json<AssignmentData>,
     conn: db::DbConn,
 ) -> Result<Json<JsonValue>, Status> {
     let data = data.into_inner();
 
-    let assignment = match Assignment::get_by_id(&data.id, &conn) {
-        Ok(v) => v,
-        Err(_) => return Err(Status::NotFound),
+    let assignment = class_assignment(&key.0, class_id, &data.id, &conn)?;
+
+    let policy = match LatePolicy::try_from(data.assignment.late_policy.as_str()) {
+        Ok(p) => p,
+        Err(_) => return Err(Status::UnprocessableEntity),
     };
 
-    let students = Student::load_in_class(&class_id.to_string(), &conn).unwrap();
+    let penalty_needed = policy == LatePolicy::Flat || policy == LatePolicy::PerDay;
+
+    match data.assignment.late_penalty {
+        Some(p) if !(0..=100).contains(&p) => return Err(Status::UnprocessableEntity),
+        None if penalty_needed => return Err(Status::UnprocessableEntity),
+        _ => {}
+    }
+
+    if matches!(data.assignment.max_attempts, Some(m) if m < 1) {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    if matches!(data.assignment.peer_reviewers, Some(n) if n < 1) || !(0..=100).contains(&data.assignment.peer_weight) {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    if let Some(category_id) = &data.assignment.category_id {
+        match GradeCategory::find(category_id, &conn) {
+            Ok(c) if c.class_id == class_id => {}
+            _ => return Err(Status::UnprocessableEntity),
+        }
+    }
+
+    if let Some(targets) = &data.targets {
+        let enrolled = Student::load_in_class(&class_id.to_string(), &conn)
+            .unwrap()
+            .into_iter()
+            .map(|s| s.user_id)
+            .collect::<Vec<String>>();
 
-    for i in &students {
-        let new_submission = FillableSubmissions {
-            assignment_id: assignment.assignment_id.clone(),
-            user_id: i.user_id.clone(),
+        if targets.students.iter().any(|s| !enrolled.contains(s)) {
+            return Err(Status::UnprocessableEntity);
+        }
+
+        for group_id in &targets.groups {
+            match StudentGroup::find(group_id, &conn) {
+                Ok(g) if g.class_id == class_id => {}
+                _ => return Err(Status::UnprocessableEntity),
+            }
+        }
+    }
+
+    // Group work is done in teams, so only groups can be given it and no
+    // student can be in two of them
+    if data.assignment.group_work {
+        let targets = match &data.targets {
+            Some(t) => t.clone(),
+            None => match assignment.targets(&conn) {
+                Ok(t) => t,
+                Err(_) => return Err(Status::InternalServerError),
+            },
         };
-        match Submissions::create(new_submission, &conn) {
-            Ok(s) => (),
+
+        if targets.groups.is_empty() || !targets.students.is_empty() {
+            return Err(Status::UnprocessableEntity);
+        }
+
+        match StudentGroup::memberships(&targets.groups, &conn) {
+            Ok(m) if shared_members(&m).is_empty() => {}
+            Ok(_) => return Err(Status::UnprocessableEntity),
             Err(_) => return Err(Status::InternalServerError),
         }
     }
@@ -60,14 +130,45 @@ pub async fn update_assignment(
 
     assignment_data.creator = Some(get_user(&key.0, &conn).unwrap().user_id);
 
+    // A rubric decides the total on its own
+    match Rubric::load(&assignment.assignment_id, &conn) {
+        Ok(r) if !r.is_empty() => assignment_data.total_marks = Some(r.max_points()),
+        Ok(_) => {}
+        Err(_) => return Err(Status::InternalServerError),
+    }
+
     let new = update(assignment, assignment_data, &conn).unwrap();
 
+    let retargeted = conn.transaction::<_, ErrorKind, _>(|| {
+        if let Some(targets) = &data.targets {
+            new.set_targets(targets, &conn)?;
+        }
+
+        new.sync_submissions(&conn)
+    });
+
+    let retargeted = match retargeted {
+        Ok(r) => r,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    // Penalties and the peer review weight follow the assignment, so marks
+    // given so far are worked out again
+    for sm in Submissions::load_by_assignment(&new.assignment_id, &conn).unwrap_or_default() {
+        if let Ok(mark) = Mark::get_by_submission_id(&sm.submission_id, &conn) {
+            if sm.mark(&mark.value, &conn).is_err() {
+                return Err(Status::InternalServerError);
+            }
+        }
+    }
+
     let creator = User::find_user(&new.creator.as_ref().unwrap(), &conn).unwrap();
 
+    // Only those just given the work hear about it
     let mut emails = Vec::new();
 
-    for i in &students {
-        emails.push(User::find_user(&i.user_id, &conn).unwrap().email)
+    for i in &retargeted.assigned {
+        emails.push(User::find_user(i, &conn).unwrap().email)
     }
 
     let html = format!(r#"<!DOCTYPE html>
@@ -88,33 +189,84 @@ pub async fn update_assignment(
     
     send_mail(creator, emails, html, "New Assignment").await;
 
-    Ok(Json(json!({ "new_assignment": &new })))
+    Ok(Json(json!({ "new_assignment": &new, "retargeted": retargeted })))
+}
+
+/// Emails every student who still has the assignment to hand in, each with
+/// their own deadline.
+#[post("/<class_id>/assignments/<assignment_id>/reminders")]
+pub async fn remind(
+    key: Can<CreateAssignment>,
+    class_id: &str,
+    assignment_id: &str,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let assignment = published_assignment(class_id, assignment_id, &conn)?;
+
+    let sender = match User::find_user(&key.0, &conn) {
+        Ok(u) => u,
+        Err(_) => return Err(Status::NotFound),
+    };
+
+    let unsubmitted = match Submissions::load_unsubmitted(&assignment.assignment_id, &conn) {
+        Ok(s) => s,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let now = Local::now().naive_local();
+
+    let mut reminded = Vec::new();
+
+    for sm in unsubmitted.iter().filter(|s| s.needs_reminder(&assignment, now)) {
+        let student = match User::find_user(&sm.user_id, &conn) {
+            Ok(u) => u,
+            Err(_) => continue,
+        };
+
+        let due = match sm.due_at(&assignment) {
+            Some(d) => format!("It is due on {}.", d.format("%d %B %Y, %H:%M")),
+            None => "It has no due date.".to_string(),
+        };
+
+        let html = format!(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Assignment Reminder</title>
+</head>
+<body>
+    <div style="display: block; align-items: center;">
+        <h2 style="font-family: Arial, Helvetica, sans-serif;">You haven't handed in {} yet</h2>
+        <br>
+        <h4 style="font-family: Arial, Helvetica, sans-serif;">{}</h4>
+    </div>
+</body>
+</html>"#, assignment.assignment_name.as_deref().unwrap_or_default(), due);
+
+        send_mail(sender.clone(), vec![student.email], html, "Assignment Reminder").await;
+
+        reminded.push(student.user_id);
+    }
+
+    Ok(Json(json!({ "reminded": reminded })))
 }
 
 #[delete("/<class_id>/assignments/<assignment_id>")]
 pub fn delete_assignment(
-    key: ClassGuard,
+    key: Can<DeleteAssignment>,
     class_id: &str,
     assignment_id: String,
     conn: db::DbConn,
 ) -> Result<Status, Status> {
     let user = get_user(&key.0, &conn).unwrap();
 
-    let assignment = match Assignment::get_by_id(&assignment_id, &conn) {
-        Ok(a) => a,
-        Err(_) => return Err(Status::NotFound),
-    };
+    let assignment = class_assignment(&key.0, class_id, &assignment_id, &conn)?;
 
-    if user.is_student() {
+    if !can_delete_assignment(&user.user_id, key.1, &assignment) {
         return Err(Status::Forbidden);
     }
 
-    if assignment.draft == false {
-        if user.is_teacher() && assignment.creator.as_ref().unwrap() == &user.user_id {
-            return Err(Status::Forbidden);
-        }
-    }
-
     assignment.delete(&conn).unwrap();
 
     let att = match Attachment::load_by_assignment_id(&assignment.assignment_id, &conn) {
@@ -131,7 +283,7 @@ pub fn delete_assignment(
 
 #[get("/<class_id>/assignments/students/<assignment_id>")]
 pub fn students_assignment(
-    key: ClassGuard,
+    key: Can<SubmitAssignment>,
     class_id: &str,
     assignment_id: &str,
     conn: DbConn,
@@ -141,56 +293,111 @@ pub fn students_assignment(
         Err(_) => return Err(Status::NotFound),
     };
 
-    if !user.is_student() {
-        return Err(Status::Forbidden);
-    }
-
-    let assignment = match Assignment::get_by_id(&assignment_id.to_string(), &conn) {
-        Ok(a) => a,
-        Err(_) => return Err(Status::NotFound),
-    };
+    let assignment = class_assignment(&key.0, class_id, assignment_id, &conn)?;
 
     let comments = Comment::load_by_assignment(&assignment.assignment_id, &conn).unwrap();
 
-    let comment_response = utils::get_comments(&comments, &conn);
+    let comment_response = match utils::get_comments(&comments, &conn) {
+        Ok(c) => c,
+        Err(_) => return Err(Status::InternalServerError),
+    };
 
     let assignment_attachments = attachments::table
         .filter(attachments::assignment_id.eq(&assignment.assignment_id))
         .load::<Attachment>(&*conn)
         .unwrap();
 
-    let submission =
-        Submissions::get_by_id(&assignment_id.to_string(), &user.user_id, &conn).unwrap();
+    // Work given to other students isn't there for this one
+    let submission = match Submissions::get_by_id(&assignment_id.to_string(), &user.user_id, &conn) {
+        Ok(s) => s,
+        Err(_) => return Err(Status::NotFound),
+    };
 
+    // Peer reviewers' comments come with their reviews, without their names
     let private_comments =
-        PrivateComment::load_by_submission(&submission.submission_id, &conn).unwrap();
+        PrivateComment::load_conversation(&submission.submission_id, &conn).unwrap();
+
+    let private_comment_response = match utils::get_comments(&private_comments, &conn) {
+        Ok(c) => c,
+        Err(_) => return Err(Status::InternalServerError),
+    };
 
-    let private_comment_response = utils::get_comments(&private_comments, &conn);
+    let team = match submission.team(&conn) {
+        Ok(t) => t,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let shared = team.iter().map(|s| s.submission_id.clone()).collect::<Vec<String>>();
 
+    // Everything the team attached, whoever did
     let submission_attachments = attachments::table
-        .filter(attachments::submission_id.eq(&submission.submission_id))
+        .filter(attachments::submission_id.eq(any(&shared)))
         .load::<Attachment>(&*conn)
         .unwrap();
 
+    let team = team.into_iter().map(|s| s.user_id).collect::<Vec<String>>();
+
     let assignment_resp = utils::get_attachments(&assignment_attachments, &conn);
 
     let submission_resp = utils::get_attachments(&submission_attachments, &conn);
 
+    let scale = match GradingScale::load(&class_id.to_string(), &conn) {
+        Ok(s) => s,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let grade = submission
+        .marks_allotted
+        .map(|m| scale.scale(percentage(&assignment, m)));
+
+    let rubric = match Rubric::load(&assignment.assignment_id, &conn) {
+        Ok(r) => r,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let scores = match Mark::get_by_submission_id(&submission.submission_id, &conn) {
+        Ok(m) => CriterionScore::load_by_mark(&m.id, &conn).unwrap_or_default(),
+        Err(_) => Vec::new(),
+    };
+
+    let rubric_feedback = rubric.feedback(&scores);
+
+    let peer_reviews = PeerReview::load_done(&shared, &conn)
+        .and_then(|rs| rs.iter().map(|r| r.anonymous(&rubric, &conn)).collect::<Result<Vec<_>, _>>());
+
+    let peer_reviews = match peer_reviews {
+        Ok(r) => r,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let history = match SubmissionEvent::load_by_submission(&submission.submission_id, &conn) {
+        Ok(h) => h,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let late_penalty = match submission.penalty(&assignment, &conn) {
+        Ok(p) => p,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
     Ok(Json(
-        json!({"assignment_attachments": assignment_resp, "assignment": assignment, "submission": submission, "submission_attachments": submission_resp, "comments": comment_response, "private_comments": private_comment_response}),
+        json!({"assignment_attachments": assignment_resp, "assignment": assignment, "submission": submission, "due_at": submission.due_at(&assignment), "late_penalty": late_penalty, "grade": grade, "rubric": rubric, "rubric_feedback": rubric_feedback, "peer_reviews": peer_reviews, "history": history, "team": team, "submission_attachments": submission_resp, "comments": comment_response, "private_comments": private_comment_response}),
     ))
 }
 
 #[derive(Serialize)]
 struct SubmissionResponse<'a> {
     submission: &'a Submissions,
+    due_at: Option<NaiveDateTime>,
+    late_penalty: i32,
+    grade: Option<ScaledScore>,
     attachment_amount: i32,
     user: ResponseUser,
 }
 
 #[get("/<class_id>/assignments/teachers/<assignment_id>")]
 pub fn teachers_assignment(
-    key: ClassGuard,
+    key: Can<ViewSubmissions>,
     class_id: &str,
     assignment_id: &str,
     conn: DbConn,
@@ -200,14 +407,7 @@ pub fn teachers_assignment(
         Err(_) => return Err(Status::NotFound),
     };
 
-    if user.is_student() {
-        return Err(Status::Forbidden);
-    }
-
-    let assignment = match Assignment::get_by_id(&assignment_id.to_string(), &conn) {
-        Ok(a) => a,
-        Err(_) => return Err(Status::NotFound),
-    };
+    let assignment = class_assignment(&key.0, class_id, assignment_id, &conn)?;
 
     let unsubmitted = Submissions::load_unsubmitted(&assignment.assignment_id, &conn).unwrap();
 
@@ -222,17 +422,43 @@ pub fn teachers_assignment(
         Err(_) => Vec::<Submissions>::new(),
     };
 
+    let scale = match GradingScale::load(&class_id.to_string(), &conn) {
+        Ok(s) => s,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let users = submission
+        .iter()
+        .map(|sm| User::find_user(&sm.user_id, &conn))
+        .collect::<Result<Vec<User>, _>>()
+        .and_then(|u| ResponseUser::load(u, &conn));
+
+    let users = match users {
+        Ok(u) => u,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let ids = submission.iter().map(|sm| sm.submission_id.clone()).collect::<Vec<String>>();
+
+    let handed_in = match Attempt::latest_of(&ids, &conn) {
+        Ok(h) => h,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
     let mut submissions = Vec::new();
 
-    for sm in &submission {
-        let attachment = attachments::table.filter(attachments::submission_id.eq(&sm.submission_id))
+    for (sm, user) in submission.iter().zip(users) {
+        let attachment = attachments::table.filter(attachments::submission_id.eq(any(sm.shared_ids(&conn).unwrap())))
         .load::<Attachment>(&*conn)
         .unwrap();
 
         submissions.push(SubmissionResponse {
             submission: sm,
+            due_at: sm.due_at(&assignment),
+            late_penalty: sm.penalty_at(&assignment, handed_in.get(&sm.submission_id).copied()),
+            grade: sm.marks_allotted.map(|m| scale.scale(percentage(&assignment, m))),
             attachment_amount: attachment.len() as i32,
-            user: ResponseUser::from(User::find_user(&sm.user_id, &conn).unwrap()),
+            user,
         });
     }
 
@@ -245,7 +471,10 @@ pub fn teachers_assignment(
 
     let comments = Comment::load_by_assignment(&assignment.assignment_id, &conn).unwrap();
 
-    let comment_response = utils::get_comments(&comments, &conn);
+    let comment_response = match utils::get_comments(&comments, &conn) {
+        Ok(c) => c,
+        Err(_) => return Err(Status::InternalServerError),
+    };
 
     Ok(Json(
         json!({"assignment_attachments": assignment_resp, "assignment": assignment, "submissions": submissions, "comments": comment_response}),
@@ -254,7 +483,7 @@ pub fn teachers_assignment(
 
 #[get("/<class_id>/assignments/teachers/<assignment_id>/submissions/<submission_id>")]
 pub fn teachers_submissions(
-    key: ClassGuard,
+    key: Can<ViewSubmissions>,
     class_id: &str,
     assignment_id: &str,
     submission_id: &str,
@@ -265,22 +494,17 @@ pub fn teachers_submissions(
         Err(_) => return Err(Status::NotFound),
     };
 
-    if user.is_student() {
-        return Err(Status::Forbidden);
-    }
-
-    let assignment = match Assignment::get_by_id(&assignment_id.to_string(), &conn) {
-        Ok(a) => a,
-        Err(_) => return Err(Status::NotFound),
-    };
+    let assignment = class_assignment(&key.0, class_id, assignment_id, &conn)?;
 
     let submission = match Submissions::find_submission(&submission_id.to_string(), &conn) {
-        Ok(s) => s,
-        Err(_) => return Err(Status::NotFound),
+        Ok(s) if s.assignment_id == assignment.assignment_id => s,
+        _ => return Err(Status::NotFound),
     };
 
+    let shared = submission.shared_ids(&conn).unwrap();
+
     let submission_attachments = attachments::table
-        .filter(attachments::submission_id.eq(&submission.submission_id))
+        .filter(attachments::submission_id.eq(any(&shared)))
         .load::<Attachment>(&*conn)
         .unwrap();
 
@@ -290,14 +514,20 @@ pub fn teachers_submissions(
 
     let student = User::find_user(&submission.user_id, &conn).unwrap();
 
+    let extensions = Extension::load_by_submission(&submission.submission_id, &conn).unwrap();
+
+    let history = SubmissionEvent::load_by_submission(&submission.submission_id, &conn).unwrap();
+
+    let attempts = Attempt::load_by_submission(&submission.submission_id, &conn).unwrap();
+
     Ok(Json(
-        json!({"submission_attachments": submission_resp, "submission": submission, "student":student, "private_comments": private_comments}),
+        json!({"submission_attachments": submission_resp, "submission": submission, "due_at": submission.due_at(&assignment), "extensions": extensions, "history": history, "attempts": attempts, "student":student, "private_comments": private_comments}),
     ))
 }
 
 #[get("/<class_id>/assignments/teachers/<teacher_id>?<draft>", rank = 2)]
 pub fn all_teachers_assignments(
-    key: ClassGuard,
+    key: Can<ViewSubmissions>,
     class_id: &str,
     teacher_id: &str,
     draft: Option<bool>,
@@ -308,10 +538,6 @@ pub fn all_teachers_assignments(
         Err(_) => return Err(Status::NotFound),
     };
 
-    if user.is_student() {
-        return Err(Status::Forbidden);
-    }
-
     let assignments = match Assignment::load_by_classuser(&teacher_id.to_string(), &user.user_id, draft, &conn) {
         Ok(a) => a,
         Err(_) => return Err(Status::NotFound),
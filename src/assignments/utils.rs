@@ -1,8 +1,13 @@
+use std::collections::{BTreeSet, HashMap};
+
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use diesel::query_dsl::QueryDsl;
 use diesel::result::Error;
 
+use crate::assignments::models::Targets;
+use crate::groups::models::GroupMember;
 use crate::schema::assignments;
 
 pub fn get_ids(connection: &PgConnection) -> Result<Vec<String>, Error> {
@@ -10,3 +15,64 @@ pub fn get_ids(connection: &PgConnection) -> Result<Vec<String>, Error> {
         .select(assignments::assignment_id)
         .load::<String>(connection)
 }
+
+/// When something with this due date and time is due. A date alone is due at
+/// the end of that day, a time alone isn't a deadline.
+pub fn deadline(date: Option<NaiveDate>, time: Option<NaiveTime>) -> Option<NaiveDateTime> {
+    match (date, time) {
+        (Some(d), Some(t)) => Some(NaiveDateTime::new(d, t)),
+        (Some(d), None) => Some(NaiveDateTime::new(d, NaiveTime::from_hms(23, 59, 59))),
+        (None, _) => None,
+    }
+}
+
+/// Whether `at` beats the deadline, `None` when there's no deadline.
+pub fn on_time(due: Option<NaiveDateTime>, at: NaiveDateTime) -> Option<bool> {
+    due.map(|d| at < d)
+}
+
+/// Days past due, a started day counting as a whole one.
+pub fn days_late(due: NaiveDateTime, at: NaiveDateTime) -> i64 {
+    let late = (at - due).num_seconds();
+
+    match late > 0 {
+        true => (late + 86399) / 86400,
+        false => 0,
+    }
+}
+
+/// The enrolled students an assignment is for, given the members of the
+/// groups it targets. Students who left the class drop out.
+pub fn assignees(enrolled: &[String], targets: &Targets, members: &[String]) -> Vec<String> {
+    if targets.is_class() {
+        return enrolled.iter().cloned().collect::<BTreeSet<String>>().into_iter().collect();
+    }
+
+    targets
+        .students
+        .iter()
+        .chain(members)
+        .filter(|s| enrolled.contains(s))
+        .cloned()
+        .collect::<BTreeSet<String>>()
+        .into_iter()
+        .collect()
+}
+
+/// Students in more than one of the teams. Group work needs everyone in
+/// exactly one, so the team's submission is theirs.
+pub fn shared_members(memberships: &[GroupMember]) -> Vec<String> {
+    let mut teams = HashMap::<&String, BTreeSet<&String>>::new();
+
+    for m in memberships {
+        teams.entry(&m.user_id).or_default().insert(&m.group_id);
+    }
+
+    teams
+        .into_iter()
+        .filter(|(_, groups)| groups.len() > 1)
+        .map(|(user_id, _)| user_id.clone())
+        .collect::<BTreeSet<String>>()
+        .into_iter()
+        .collect()
+}
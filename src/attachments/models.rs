@@ -9,6 +9,7 @@ use std::ops::Deref;
 use tokio;
 
 use crate::assignments::models::Assignment;
+use crate::attempts::models::Attempt;
 use crate::errors::ThearningResult;
 use crate::files::models::UploadedFile;
 use crate::schema::assignments;
@@ -96,20 +97,18 @@ impl Attachment {
             .load::<Self>(conn)?)
     }
 
+    /// Removes the attachment along with its file or link, unless a
+    /// submission attempt still holds on to them.
     pub fn delete(&self, conn: &PgConnection) -> ThearningResult<Self> {
         match &self.file_id {
+            Some(id) if Attempt::holds_file(id, conn)? => (),
             Some(id) => {
-                let file = UploadedFile::receive(id, conn)?;
-
-                tokio::task::spawn(async move {
-                    tokio::fs::remove_file(file.file_path).await.unwrap();
-                });
-
-                diesel::delete(files::table.filter(files::file_id.eq(id))).execute(conn);
+                UploadedFile::receive(id, conn)?.remove(conn)?;
             }
             None => (),
         }
         match &self.link_id {
+            Some(id) if Attempt::holds_link(id, conn)? => (),
             Some(id) => {
                 diesel::delete(links::table.filter(links::id.eq(id.trim()))).execute(conn);
             }
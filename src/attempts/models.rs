@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+
+use chrono::{Local, NaiveDateTime};
+use diesel::dsl::any;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use serde::{Deserialize, Serialize};
+
+use crate::attachments::models::Attachment;
+use crate::errors::ThearningResult;
+use crate::files::models::UploadedFile;
+use crate::links::models::Link;
+use crate::schema::{attachments, attempt_files, attempts};
+use crate::submissions::models::Submissions;
+use crate::utils::generate_random_id;
+
+/// One turn-in of a submission, numbered from 1. What was handed in never
+/// changes afterwards, only its mark does.
+#[derive(Serialize, Deserialize, Queryable, Insertable, Clone)]
+#[table_name = "attempts"]
+pub struct Attempt {
+    pub id: String,
+    pub submission_id: String,
+    pub number: i32,
+    pub state: String,
+    pub on_time: Option<bool>,
+    pub submitted_at: NaiveDateTime,
+    pub mark: Option<i32>,
+    pub marker_id: Option<String>,
+}
+
+/// A file or link that was attached when the attempt was handed in.
+#[derive(Serialize, Deserialize, Queryable, Insertable, Clone)]
+#[table_name = "attempt_files"]
+pub struct AttemptFile {
+    pub id: String,
+    pub attempt_id: String,
+    pub file_id: Option<String>,
+    pub link_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AttemptMark {
+    pub value: i32,
+}
+
+#[derive(Serialize)]
+pub struct AttemptFileResponse {
+    pub file: Option<UploadedFile>,
+    pub link: Option<Link>,
+}
+
+impl Attempt {
+    pub fn find(id: &String, conn: &PgConnection) -> ThearningResult<Self> {
+        Ok(attempts::table.find(id).get_result::<Self>(conn)?)
+    }
+
+    /// The submission's attempts, first one first.
+    pub fn load_by_submission(submission_id: &String, conn: &PgConnection) -> ThearningResult<Vec<Self>> {
+        Ok(attempts::table
+            .filter(attempts::submission_id.eq(submission_id))
+            .order(attempts::number.asc())
+            .load::<Self>(conn)?)
+    }
+
+    /// When the submission was last handed in, if it ever was.
+    pub fn latest_at(submission_id: &String, conn: &PgConnection) -> ThearningResult<Option<NaiveDateTime>> {
+        Ok(attempts::table
+            .filter(attempts::submission_id.eq(submission_id))
+            .order(attempts::number.desc())
+            .select(attempts::submitted_at)
+            .first::<NaiveDateTime>(conn)
+            .optional()?)
+    }
+
+    /// [`Attempt::latest_at`] for many submissions at once. Submissions never
+    /// handed in are left out.
+    pub fn latest_of(submission_ids: &[String], conn: &PgConnection) -> ThearningResult<HashMap<String, NaiveDateTime>> {
+        Ok(attempts::table
+            .filter(attempts::submission_id.eq(any(submission_ids)))
+            .order(attempts::number.asc())
+            .select((attempts::submission_id, attempts::submitted_at))
+            .load::<(String, NaiveDateTime)>(conn)?
+            .into_iter()
+            .collect())
+    }
+
+    pub fn count(submission_id: &String, conn: &PgConnection) -> ThearningResult<i64> {
+        Ok(attempts::table
+            .filter(attempts::submission_id.eq(submission_id))
+            .count()
+            .get_result::<i64>(conn)?)
+    }
+
+    /// Records a submission that was just turned in as its next attempt,
+    /// along with everything attached to it right now.
+    pub fn snapshot(submission: &Submissions, conn: &PgConnection) -> ThearningResult<Self> {
+        let attempt = Self {
+            id: format!("{}{}", generate_random_id(), generate_random_id()),
+            submission_id: submission.submission_id.clone(),
+            number: Self::count(&submission.submission_id, conn)? as i32 + 1,
+            state: submission.state.clone(),
+            on_time: submission.on_time,
+            submitted_at: submission
+                .submitted_at()
+                .unwrap_or_else(|| Local::now().naive_local()),
+            mark: None,
+            marker_id: None,
+        };
+
+        let attempt = diesel::insert_into(attempts::table)
+            .values(&attempt)
+            .get_result::<Self>(conn)?;
+
+        let files = attachments::table
+            .filter(attachments::submission_id.eq(any(submission.shared_ids(conn)?)))
+            .order(attachments::created_at.asc())
+            .load::<Attachment>(conn)?
+            .into_iter()
+            .map(|a| AttemptFile {
+                id: format!("{}{}", generate_random_id(), generate_random_id()),
+                attempt_id: attempt.id.clone(),
+                file_id: a.file_id,
+                link_id: a.link_id,
+            })
+            .collect::<Vec<AttemptFile>>();
+
+        if !files.is_empty() {
+            diesel::insert_into(attempt_files::table)
+                .values(&files)
+                .execute(conn)?;
+        }
+
+        Ok(attempt)
+    }
+
+    pub fn files(&self, conn: &PgConnection) -> ThearningResult<Vec<AttemptFileResponse>> {
+        let files = attempt_files::table
+            .filter(attempt_files::attempt_id.eq(&self.id))
+            .load::<AttemptFile>(conn)?;
+
+        let mut res = Vec::new();
+
+        for f in files {
+            res.push(AttemptFileResponse {
+                file: match &f.file_id {
+                    Some(id) => Some(UploadedFile::receive(id, conn)?),
+                    None => None,
+                },
+                link: match &f.link_id {
+                    Some(id) => Some(Link::receive(id, conn)?),
+                    None => None,
+                },
+            });
+        }
+
+        Ok(res)
+    }
+
+    pub fn grade(&self, value: i32, marker_id: &String, conn: &PgConnection) -> ThearningResult<Self> {
+        Ok(diesel::update(attempts::table.find(&self.id))
+            .set((attempts::mark.eq(value), attempts::marker_id.eq(marker_id)))
+            .get_result::<Self>(conn)?)
+    }
+
+    /// Whether an attempt still needs the file, so it mustn't be deleted.
+    pub fn holds_file(file_id: &String, conn: &PgConnection) -> ThearningResult<bool> {
+        let count = attempt_files::table
+            .filter(attempt_files::file_id.eq(file_id))
+            .count()
+            .get_result::<i64>(conn)?;
+
+        Ok(count > 0)
+    }
+
+    pub fn holds_link(link_id: &String, conn: &PgConnection) -> ThearningResult<bool> {
+        let count = attempt_files::table
+            .filter(attempt_files::link_id.eq(link_id))
+            .count()
+            .get_result::<i64>(conn)?;
+
+        Ok(count > 0)
+    }
+}
@@ -0,0 +1,114 @@
+use diesel::prelude::*;
+use rocket::http::Status;
+use rocket::serde::json::serde_json::json;
+use rocket::serde::json::Json;
+use rocket_dyn_templates::handlebars::JsonValue;
+
+use crate::assignments::models::Assignment;
+use crate::attempts::models::{Attempt, AttemptMark};
+use crate::db;
+use crate::errors::ErrorKind;
+use crate::policy::{member_submission, Can, GradeSubmission, ViewClass};
+use crate::rubrics::models::Rubric;
+use crate::submissions::models::{FillableMark, Submissions};
+use crate::submissions::routes::give_team_mark;
+
+fn attempt_json(attempt: &Attempt, submission: &Submissions, assignment: &Assignment, conn: &PgConnection) -> Result<JsonValue, Status> {
+    let files = match attempt.files(conn) {
+        Ok(f) => f,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let late_penalty = submission.penalty_at(assignment, Some(attempt.submitted_at));
+
+    Ok(json!({ "attempt": attempt, "files": files, "late_penalty": late_penalty }))
+}
+
+#[get("/<class_id>/submissions/<submission_id>/attempts")]
+pub fn attempts(
+    key: Can<ViewClass>,
+    class_id: &str,
+    submission_id: &str,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let (submission, assignment) = member_submission(&key.0, key.1, class_id, submission_id, &conn)?;
+
+    let attempts = match Attempt::load_by_submission(&submission.submission_id, &conn) {
+        Ok(a) => a,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let mut res = Vec::new();
+
+    for attempt in &attempts {
+        res.push(attempt_json(attempt, &submission, &assignment, &conn)?);
+    }
+
+    Ok(Json(json!({ "attempts": res, "max_attempts": assignment.max_attempts })))
+}
+
+#[get("/<class_id>/attempts/<attempt_id>")]
+pub fn attempt(
+    key: Can<ViewClass>,
+    class_id: &str,
+    attempt_id: &str,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let attempt = match Attempt::find(&attempt_id.to_string(), &conn) {
+        Ok(a) => a,
+        Err(_) => return Err(Status::NotFound),
+    };
+
+    let (submission, assignment) = member_submission(&key.0, key.1, class_id, &attempt.submission_id, &conn)?;
+
+    Ok(Json(attempt_json(&attempt, &submission, &assignment, &conn)?))
+}
+
+/// Marks one attempt, which then stands as the submission's mark, and the
+/// rest of the team's on group work. Any late penalty is worked out from
+/// when that attempt was handed in.
+#[patch("/<class_id>/attempts/<attempt_id>/mark", data = "<mark>")]
+pub fn mark_attempt(
+    key: Can<GradeSubmission>,
+    class_id: &str,
+    attempt_id: &str,
+    mark: Json<AttemptMark>,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let attempt = match Attempt::find(&attempt_id.to_string(), &conn) {
+        Ok(a) => a,
+        Err(_) => return Err(Status::NotFound),
+    };
+
+    let (submission, assignment) = member_submission(&key.0, key.1, class_id, &attempt.submission_id, &conn)?;
+
+    if mark.value < 0 || matches!(assignment.total_marks, Some(t) if mark.value > t) {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let rubric = match Rubric::load(&assignment.assignment_id, &conn) {
+        Ok(r) => r,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let graded = conn.transaction::<_, ErrorKind, _>(|| {
+        let graded = attempt.grade(mark.value, &key.0, &conn)?;
+
+        give_team_mark(
+            &submission,
+            &key.0,
+            &FillableMark::plain(mark.value),
+            mark.value,
+            &rubric,
+            Some(graded.submitted_at),
+            &conn,
+        )?;
+
+        Ok(graded)
+    });
+
+    match graded {
+        Ok(a) => Ok(Json(attempt_json(&a, &submission, &assignment, &conn)?)),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
@@ -0,0 +1,59 @@
+use chrono::{Local, NaiveDateTime};
+use diesel;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::ThearningResult;
+use crate::schema::audit_logs;
+use crate::utils::generate_random_id;
+
+#[derive(Serialize, Deserialize, Queryable, Insertable, Clone)]
+#[table_name = "audit_logs"]
+pub struct AuditLog {
+    pub id: String,
+    pub action: String,
+    pub user_id: Option<String>,
+    pub actor_id: Option<String>,
+    pub ip: Option<String>,
+    pub detail: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+pub struct FillableAuditLog<'a> {
+    pub action: &'a str,
+    pub user_id: Option<&'a str>,
+    pub actor_id: Option<&'a str>,
+    pub ip: Option<&'a str>,
+    pub detail: Option<String>,
+}
+
+impl AuditLog {
+    pub fn create(new_data: FillableAuditLog, conn: &PgConnection) -> ThearningResult<Self> {
+        let log = Self {
+            id: format!("{}{}", generate_random_id(), generate_random_id()),
+            action: new_data.action.to_string(),
+            user_id: new_data.user_id.map(|s| s.to_string()),
+            actor_id: new_data.actor_id.map(|s| s.to_string()),
+            ip: new_data.ip.map(|s| s.to_string()),
+            detail: new_data.detail,
+            created_at: Local::now().naive_local(),
+        };
+
+        Ok(diesel::insert_into(audit_logs::table)
+            .values(&log)
+            .get_result::<Self>(conn)?)
+    }
+
+    pub fn load(user_id: Option<&String>, conn: &PgConnection) -> ThearningResult<Vec<Self>> {
+        let mut query = audit_logs::table
+            .order(audit_logs::created_at.desc())
+            .into_boxed();
+
+        if let Some(uid) = user_id {
+            query = query.filter(audit_logs::user_id.eq(uid));
+        }
+
+        Ok(query.load::<Self>(conn)?)
+    }
+}
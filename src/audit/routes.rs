@@ -0,0 +1,30 @@
+use rocket::http::Status;
+use rocket::serde::json::serde_json::json;
+use rocket::serde::json::Json;
+use rocket_dyn_templates::handlebars::JsonValue;
+
+use crate::audit::models::AuditLog;
+use crate::auth::ApiKey;
+use crate::db;
+use crate::users::models::{SystemRole, User};
+
+#[get("/?<user_id>")]
+fn audit_logs(
+    key: ApiKey,
+    user_id: Option<String>,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    match User::get_system_role(&key.0, &conn) {
+        Ok(SystemRole::Admin) => {}
+        _ => return Err(Status::Forbidden),
+    }
+
+    match AuditLog::load(user_id.as_ref(), &conn) {
+        Ok(logs) => Ok(Json(json!({ "logs": logs }))),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+pub fn mount(rocket: rocket::Rocket<rocket::Build>) -> rocket::Rocket<rocket::Build> {
+    rocket.mount("/api/audit", routes![audit_logs])
+}
@@ -1,20 +1,28 @@
 use chrono::{Duration, Local};
 use diesel::{Connection, PgConnection};
+use hmac::{Hmac, Mac};
 use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
 use rocket::http::Status;
+use rocket::outcome::try_outcome;
 use rocket::request::{self, FromRequest, Request};
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
 use std::env;
 
 use crate::classes::models::Classroom;
 use crate::db::database_url;
 use crate::errors::{ErrorKind, JWTCError, ThearningResult};
-use crate::users::models::{Role, User};
+use crate::sessions::models::Session;
+use crate::users::models::{Role, SystemRole, User};
 use crate::users::utils::is_email;
 
+const ACCESS_TOKEN_MINUTES: i64 = 15;
+const CHALLENGE_MINUTES: i64 = 5;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
+    pub sid: String,
     pub iat: usize,
     pub role: String,
     pub exp: usize,
@@ -23,12 +31,15 @@ pub struct Claims {
 #[derive(Clone)]
 pub struct ApiKey(pub String);
 
-pub fn generate_token(key: &String, role: &Role) -> ThearningResult<String> {
+pub fn generate_token(key: &String, role: &SystemRole, session_id: &String) -> ThearningResult<String> {
     let dt = Local::now();
 
     let now = dt.timestamp_nanos() as usize;
 
-    let exp = now + (Duration::days(7).num_nanoseconds().unwrap() as usize);
+    let exp = now
+        + (Duration::minutes(ACCESS_TOKEN_MINUTES)
+            .num_nanoseconds()
+            .unwrap() as usize);
 
     let mut sub = key.clone();
     let db_conn = PgConnection::establish(&database_url())?;
@@ -39,6 +50,7 @@ pub fn generate_token(key: &String, role: &Role) -> ThearningResult<String> {
 
     let claims = Claims {
         sub,
+        sid: session_id.to_string(),
         iat: now,
         role: role.to_string(),
         exp,
@@ -52,7 +64,53 @@ pub fn generate_token(key: &String, role: &Role) -> ThearningResult<String> {
     )?)
 }
 
+/// Opens a session for the user and returns its access and refresh tokens.
+pub fn new_session(uid: &String, conn: &PgConnection) -> ThearningResult<(String, String)> {
+    let role = User::get_system_role(uid, conn)?;
+
+    let (session, refresh_token) = Session::create(uid, conn)?;
+
+    Ok((generate_token(uid, &role, &session.id)?, refresh_token))
+}
+
+/// A short-lived token proving the password step of a two-factor login
+/// succeeded. Formatted as `<expiry>.<signature>.<user id>`.
+pub fn generate_challenge(uid: &String) -> String {
+    let expires = (Local::now() + Duration::minutes(CHALLENGE_MINUTES)).timestamp();
+
+    let signature = sign(&format!("challenge:{}:{}", uid, expires));
+
+    format!("{}.{}.{}", expires, signature, uid)
+}
+
+pub fn read_challenge(challenge: &str) -> ThearningResult<String> {
+    let mut parts = challenge.splitn(3, '.');
+
+    let (expires, signature, uid) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(e), Some(s), Some(u)) => (e, s, u),
+        _ => return Err(ErrorKind::InvalidValue),
+    };
+
+    let expires = expires
+        .parse::<i64>()
+        .map_err(|_| ErrorKind::InvalidValue)?;
+
+    if !verify_signature(&format!("challenge:{}:{}", uid, expires), signature) {
+        return Err(ErrorKind::InvalidValue);
+    }
+
+    if expires < Local::now().timestamp() {
+        return Err(ErrorKind::from(JWTCError::TokenExpired));
+    }
+
+    Ok(uid.to_string())
+}
+
 pub fn read_token(key: &str) -> ThearningResult<String> {
+    Ok(read_claims(key)?.sub)
+}
+
+pub fn read_claims(key: &str) -> ThearningResult<Claims> {
     let dt = Local::now();
 
     let now = dt.timestamp_nanos() as usize;
@@ -66,61 +124,97 @@ pub fn read_token(key: &str) -> ThearningResult<String> {
             if now > v.claims.exp {
                 return Err(ErrorKind::JWTCreationError(JWTCError::TokenExpired));
             }
-            Ok(v.claims.sub)
+            Ok(v.claims)
         }
         Err(e) => Err(ErrorKind::from(e)),
     }
 }
 
+type HmacSha256 = Hmac<Sha256>;
+
+fn mac(payload: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(env::var("SECRETS").unwrap().as_bytes()).unwrap();
+    mac.update(payload.as_bytes());
+    mac
+}
+
+/// Signs a payload with the server secret, for links that have to work
+/// without a bearer token.
+pub fn sign(payload: &str) -> String {
+    format!("{:x}", mac(payload).finalize().into_bytes())
+}
+
+pub fn verify_signature(payload: &str, signature: &str) -> bool {
+    let bytes = (0..signature.len())
+        .step_by(2)
+        .map(|i| {
+            signature
+                .get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+        })
+        .collect::<Option<Vec<u8>>>();
+
+    match bytes {
+        Some(b) => mac(payload).verify_slice(&b).is_ok(),
+        None => false,
+    }
+}
+
+/// Reads the bearer token and makes sure its session hasn't been revoked.
+fn authenticate(request: &Request<'_>) -> request::Outcome<Claims, ErrorKind> {
+    let keys = match request
+        .headers()
+        .get("Authorization")
+        .collect::<Vec<_>>()
+        .first()
+    {
+        Some(k) => k.split("Bearer").map(|i| i.trim()).collect::<String>(),
+        None => {
+            return request::Outcome::Failure((Status::BadRequest, ErrorKind::InvalidValue))
+        }
+    };
+
+    let claims = match read_claims(keys.as_str()) {
+        Ok(claims) => claims,
+        Err(e) => return request::Outcome::Failure((Status::Unauthorized, e)),
+    };
+
+    let db_conn = match PgConnection::establish(&database_url()) {
+        Ok(conn) => conn,
+        Err(e) => {
+            return request::Outcome::Failure((Status::ServiceUnavailable, ErrorKind::from(e)))
+        }
+    };
+
+    if !Session::is_valid(&claims.sid, &db_conn) {
+        return request::Outcome::Failure((
+            Status::Unauthorized,
+            ErrorKind::from(JWTCError::SessionRevoked),
+        ));
+    }
+
+    request::Outcome::Success(claims)
+}
+
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for ApiKey {
     type Error = ErrorKind;
 
     async fn from_request(request: &'r Request<'_>) -> request::Outcome<ApiKey, ErrorKind> {
-        let keys = match request
-            .headers()
-            .get("Authorization")
-            .collect::<Vec<_>>()
-            .first()
-        {
-            Some(k) => k.split("Bearer").map(|i| i.trim()).collect::<String>(),
-            None => {
-                return request::Outcome::Failure((Status::BadRequest, ErrorKind::InvalidValue))
-            }
-        };
-
-        match read_token(keys.as_str()) {
-            Ok(claim) => request::Outcome::Success(ApiKey(claim)),
-            Err(e) => request::Outcome::Failure((Status::Unauthorized, e)),
-        }
+        authenticate(request).map(|claims| ApiKey(claims.sub))
     }
 }
 
-pub struct ClassGuard(pub String);
+/// A member of the class in the route's first parameter, along with their
+/// role in it. School admins pass as class admins everywhere.
+pub struct ClassGuard(pub String, pub Role);
 
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for ClassGuard {
     type Error = ErrorKind;
 
     async fn from_request(request: &'r rocket::Request<'_>) -> request::Outcome<Self, ErrorKind> {
-        let keys = match request
-            .headers()
-            .get("Authorization")
-            .collect::<Vec<_>>()
-            .first()
-        {
-            Some(k) => k.split("Bearer").map(|i| i.trim()).collect::<String>(),
-            None => {
-                return request::Outcome::Failure((Status::BadRequest, ErrorKind::InvalidValue))
-            }
-        };
-
-        let claim = match read_token(keys.as_str()) {
-            Ok(claim) => ApiKey(claim),
-            Err(e) => return request::Outcome::Failure((Status::Unauthorized, e)),
-        };
-
-        let route = request.route().unwrap();
+        let claim = ApiKey(try_outcome!(authenticate(request)).sub);
 
         let class_id: String = match request.param(0) {
             Some(value) => match value {
@@ -136,9 +230,13 @@ impl<'r> FromRequest<'r> for ClassGuard {
 
         let db_conn = PgConnection::establish(&database_url()).unwrap();
 
-        match Classroom::user_in_class(&class_id, &claim.0, &db_conn) {
-            true => request::Outcome::Success(Self(claim.0)),
-            false => request::Outcome::Failure((Status::Unauthorized, ErrorKind::InvalidValue)),
+        match Classroom::role_of(&class_id, &claim.0, &db_conn) {
+            Ok(Some(role)) => request::Outcome::Success(Self(claim.0, role)),
+            Ok(None) => match User::get_system_role(&claim.0, &db_conn) {
+                Ok(SystemRole::Admin) => request::Outcome::Success(Self(claim.0, Role::Admin)),
+                _ => request::Outcome::Failure((Status::Unauthorized, ErrorKind::InvalidValue)),
+            },
+            Err(e) => request::Outcome::Failure((Status::InternalServerError, e)),
         }
     }
 }
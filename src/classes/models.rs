@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use crate::errors::ThearningResult;
 use chrono::{Local, NaiveDateTime};
 use diesel;
@@ -6,9 +8,10 @@ use diesel::prelude::*;
 use rocket::fs::TempFile;
 use serde::{Deserialize, Serialize};
 
-use crate::schema::{classes, topics};
+use crate::files::models::FileVariant;
+use crate::schema::{admins, classes, students, teachers, topics};
 use crate::traits::{ClassUser, Manipulable};
-use crate::users::models::{Admin, Student, Teacher};
+use crate::users::models::{Admin, Role, Student, Teacher};
 use crate::utils::generate_random_id;
 
 #[derive(Serialize, Deserialize, Queryable, AsChangeset, Insertable, Associations, Clone)]
@@ -23,6 +26,48 @@ pub struct Classroom {
     pub created_at: NaiveDateTime,
 }
 
+/// A class with the banner sizes of its picture by name, see
+/// [`UploadType::variants`](crate::files::models::UploadType::variants).
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ResponseClassroom {
+    #[serde(flatten)]
+    pub class: Classroom,
+    #[serde(default)]
+    pub class_image_variants: HashMap<String, String>,
+}
+
+impl ResponseClassroom {
+    pub fn new(class: Classroom, conn: &PgConnection) -> ThearningResult<Self> {
+        let variants = match &class.class_image {
+            Some(url) => FileVariant::urls_of(url, conn)?,
+            None => HashMap::new(),
+        };
+
+        Ok(Self {
+            class,
+            class_image_variants: variants,
+        })
+    }
+
+    /// Like `new` for a page of classes, with the variants looked up together.
+    pub fn load(classes: Vec<Classroom>, conn: &PgConnection) -> ThearningResult<Vec<Self>> {
+        let images = classes.iter().filter_map(|c| c.class_image.clone()).collect::<Vec<_>>();
+        let variants = FileVariant::urls_of_all(&images, conn)?;
+
+        Ok(classes
+            .into_iter()
+            .map(|class| Self {
+                class_image_variants: class
+                    .class_image
+                    .as_ref()
+                    .and_then(|url| variants.get(url).cloned())
+                    .unwrap_or_default(),
+                class,
+            })
+            .collect())
+    }
+}
+
 #[derive(FromForm)]
 pub struct NewClassroom<'a> {
     pub class_name: String,
@@ -57,6 +102,7 @@ pub struct Topic {
 #[derive(Serialize, Deserialize)]
 pub struct NewTopic {
     pub topic_name: String,
+    #[serde(default)]
     pub classroom_id: String,
 }
 
@@ -65,18 +111,35 @@ impl Classroom {
         Ok(classes::table.find(id).get_result::<Self>(conn)?)
     }
 
-    pub fn user_in_class(class_id: &String, uid: &String, conn: &PgConnection) -> bool {
-        let students = Student::load_in_class(class_id, conn).unwrap();
-        let teachers = Teacher::load_in_class(class_id, conn).unwrap();
-        let admins = Admin::load_in_class(class_id, conn).unwrap();
-        match (
-            students.iter().find(|x| &x.user_id == uid),
-            teachers.iter().find(|x| &x.user_id == uid),
-            admins.iter().find(|x| &x.user_id == uid),
-        ) {
-            (None, None, None) => false,
-            _ => true,
+    /// The role a user holds in a class, or `None` if they aren't a member.
+    pub fn role_of(class_id: &String, uid: &String, conn: &PgConnection) -> ThearningResult<Option<Role>> {
+        let teacher = teachers::table
+            .filter(teachers::class_id.eq(class_id))
+            .filter(teachers::user_id.eq(uid))
+            .first::<Teacher>(conn)
+            .optional()?;
+
+        if teacher.is_some() {
+            return Ok(Some(Role::Teacher));
+        }
+
+        let admin = admins::table
+            .filter(admins::class_id.eq(class_id))
+            .filter(admins::user_id.eq(uid))
+            .first::<Admin>(conn)
+            .optional()?;
+
+        if admin.is_some() {
+            return Ok(Some(Role::Admin));
         }
+
+        let student = students::table
+            .filter(students::class_id.eq(class_id))
+            .filter(students::user_id.eq(uid))
+            .first::<Student>(conn)
+            .optional()?;
+
+        Ok(student.map(|_| Role::Student))
     }
 }
 
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::env;
 
 use crate::assignments::models::Assignment;
@@ -14,21 +15,26 @@ use rocket_dyn_templates::handlebars::JsonValue;
 use crate::announcements::models::Announcement;
 
 use crate::assignments::routes::*;
+use crate::groups::routes::*;
+use crate::peer_reviews::routes::*;
+use crate::attempts::routes::*;
 use crate::auth::ApiKey;
-use crate::auth::ClassGuard;
-use crate::classes::models::{Classroom, NewClassroom, NewTopic, Topic};
-use crate::classes::utils::{generate_class_code, get_class_codes};
+use crate::policy::{Can, CreateTopic, UpdateClass, ViewClass};
+use crate::classes::models::{Classroom, NewClassroom, NewTopic, ResponseClassroom, Topic};
+use crate::classes::utils::{enroll_student, generate_class_code, get_class_codes};
 use crate::db;
 use crate::db::DbConn;
 use crate::errors::ThearningResult;
 use crate::files::models::UploadType;
 use crate::files::routes;
+use crate::gradebook::routes::*;
+use crate::rubrics::routes::*;
 use crate::schema::classes;
-use crate::schema::users;
 use crate::submissions::models::{FillableSubmissions, Submissions};
 use crate::submissions::routes::*;
 use crate::traits::{ClassUser, Manipulable};
-use crate::users::models::{Admin, ResponseUser, Role, Student, Teacher, User};
+use crate::users::models::{Admin, ResponseUser, Role, Student, SystemRole, Teacher, User};
+use crate::users::utils::join_requires_verification;
 use crate::utils::{load_classuser, update};
 use crate::comments::routes::*;
 use crate::announcements::routes::*;
@@ -39,15 +45,14 @@ async fn create_classroom<'a>(
     new_class: Form<NewClassroom<'a>>,
     connection: db::DbConn,
 ) -> Result<Json<JsonValue>, Status> {
-    let user = User::find_user(&key.0, &*connection).unwrap();
+    match User::get_system_role(&key.0, &connection) {
+        Ok(r) if r.can_teach() => {}
+        Ok(_) => return Err(Status::Forbidden),
+        Err(_) => return Err(Status::Unauthorized),
+    }
 
     let cloned_key = key.clone();
 
-    match Role::from(user.status.as_str()) {
-        Role::Student => return Err(Status::Forbidden),
-        _ => {}
-    }
-
     let new_class = new_class.into_inner();
 
     let codes = get_class_codes(&*connection).unwrap();
@@ -68,14 +73,9 @@ async fn create_classroom<'a>(
         Err(_) => return Err(Status::BadRequest),
     }
 
-    match Role::from(user.status.as_str()) {
-        Role::Teacher => {
-            create_classuser::<Teacher>(&key.0, &class.class_id, &connection);
-        }
-        Role::Admin => {
-            create_classuser::<Admin>(&key.0, &class.class_id, &connection);
-        }
-        _ => {}
+    // Whoever creates a class teaches it
+    if create_classuser::<Teacher>(&key.0, &class.class_id, &connection).is_err() {
+        return Err(Status::InternalServerError);
     }
 
     let image_file = match new_class.image {
@@ -87,11 +87,11 @@ async fn create_classroom<'a>(
         .await
         {
             Ok(v) => v,
-            Err(_) => return Err(Status::BadRequest),
+            Err(e) => return Err(e.status()),
         },
         None => {
             let url = env::var("SITE_URL").unwrap();
-            format!("{}/api/media/img/placeholder.png", url)
+            format!("{}/api/media/placeholder.png", url)
         }
     };
 
@@ -113,145 +113,159 @@ fn create_classuser<T: ClassUser>(
     T::create(key, class_id, conn)
 }
 
+/// Checks a class can be joined with the code: it exists, the caller isn't
+/// in it yet and, where required, has verified their email.
+fn joinable(key: &ApiKey, class_id: &String, connection: &db::DbConn) -> Result<(), Status> {
+    let codes = get_class_codes(connection).unwrap();
+
+    if !codes.contains(class_id) {
+        return Err(Status::NotFound);
+    }
+
+    if join_requires_verification() {
+        match User::find_user(&key.0, connection) {
+            Ok(u) if u.is_verified() => {}
+            _ => return Err(Status::Forbidden),
+        }
+    }
+
+    match Classroom::role_of(class_id, &key.0, connection) {
+        Ok(None) => Ok(()),
+        Ok(Some(_)) => Err(Status::Conflict),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
 #[post("/<class_id>", rank = 1)]
 pub fn join(
     key: ApiKey,
     class_id: String,
     connection: db::DbConn,
 ) -> Result<Json<JsonValue>, Status> {
-    let codes = get_class_codes(&connection).unwrap();
+    joinable(&key, &class_id, &connection)?;
+
+    // School admins join as class admins, everyone else as a student, teacher
+    // accounts included
+    match User::get_system_role(&key.0, &connection) {
+        Ok(SystemRole::Admin) => match create_classuser::<Admin>(&key.0, &class_id, &connection) {
+            Ok(_) => Ok(Json(json!({"status":200}))),
+            Err(_) => Err(Status::Conflict),
+        },
+        Ok(SystemRole::Teacher | SystemRole::User) => match enroll_student(&key.0, &class_id, &connection) {
+            Ok(_) => Ok(Json(json!({"status":200}))),
+            Err(_) => Err(Status::NotFound),
+        },
+        Err(_) => Err(Status::BadRequest),
+    }
+}
 
-    if !codes.contains(&class_id) {
-        return Err(Status::NotFound);
+/// Joins a class as a co-teacher, for teacher accounts and school admins.
+#[post("/<class_id>/teach", rank = 1)]
+pub fn join_as_teacher(
+    key: ApiKey,
+    class_id: String,
+    connection: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    match User::get_system_role(&key.0, &connection) {
+        Ok(r) if r.can_teach() => {}
+        Ok(_) => return Err(Status::Forbidden),
+        Err(_) => return Err(Status::BadRequest),
     }
 
-    if let Ok(r) = User::get_role(&key.0, &connection) {
-        match r {
-            Role::Student => {
-                let student = match create_classuser::<Student>(&key.0, &class_id, &connection) {
-                    Ok(u) => u,
-                    Err(_) => return Err(Status::NotFound),
-                };
-                let assignments = Assignment::load(&class_id, &connection).unwrap();
-
-                if !assignments.is_empty() {
-                    for i in assignments {
-                        let new_submission = FillableSubmissions {
-                            assignment_id: i.assignment_id,
-                            user_id: student.user_id.clone(),
-                        };
-                        Submissions::create(new_submission, &connection);
-                    }
-                }
-
-                Ok(Json(json!({"status":200})))
-            }
-            Role::Teacher => match create_classuser::<Teacher>(&key.0, &class_id, &connection) {
-                Ok(c) => Ok(Json(json!({"status":200}))),
-                Err(_) => Err(Status::Conflict),
-            },
-            Role::Admin => match create_classuser::<Admin>(&key.0, &class_id, &connection) {
-                Ok(c) => Ok(Json(json!({"status":200}))),
-                Err(_) => Err(Status::Conflict),
-            },
-        }
-    } else {
-        Err(Status::BadRequest)
+    joinable(&key, &class_id, &connection)?;
+
+    match create_classuser::<Teacher>(&key.0, &class_id, &connection) {
+        Ok(_) => Ok(Json(json!({"status":200}))),
+        Err(_) => Err(Status::Conflict),
     }
 }
 
 #[get("/", rank = 1)]
 fn classrooms(key: ApiKey, connection: db::DbConn) -> Result<Json<JsonValue>, Status> {
-    let user = users::table.find(&key.0).get_result::<User>(&*connection);
-    match Role::from(user.as_ref().unwrap().status.as_str()) {
-        Role::Student => {
-            let student = Student::find(&key.0, &connection).unwrap();
-
-            let students_class_ids = student.into_iter().map(|i| i.class_id).collect::<Vec<_>>();
-
-            let students_classes = classes::table
-                .filter(classes::class_id.eq(any(students_class_ids)))
-                .load::<Classroom>(&*connection)
-                .unwrap();
-
-            Ok(Json(json!({ "class_ids": students_classes })))
-        }
-        Role::Teacher => {
-            let teacher = Teacher::find(&key.0, &connection).unwrap();
-
-            let teachers_class_ids = teacher.into_iter().map(|i| i.class_id).collect::<Vec<_>>();
+    let mut roles = HashMap::new();
 
-            let teachers_classes = classes::table
-                .filter(classes::class_id.eq(any(teachers_class_ids)))
-                .load::<Classroom>(&*connection)
-                .unwrap();
+    for i in Student::find(&key.0, &connection).unwrap() {
+        roles.insert(i.class_id, Role::Student);
+    }
+    for i in Admin::find(&key.0, &connection).unwrap() {
+        roles.insert(i.class_id, Role::Admin);
+    }
+    for i in Teacher::find(&key.0, &connection).unwrap() {
+        roles.insert(i.class_id, Role::Teacher);
+    }
 
-            Ok(Json(json!({ "class_ids": teachers_classes })))
-        }
-        Role::Admin => {
-            let admin = Admin::find(&key.0, &connection).unwrap();
+    let class_ids = roles.keys().cloned().collect::<Vec<_>>();
 
-            let admins_class_ids = admin.into_iter().map(|i| i.class_id).collect::<Vec<_>>();
+    let user_classes = classes::table
+        .filter(classes::class_id.eq(any(class_ids)))
+        .load::<Classroom>(&*connection)
+        .unwrap();
 
-            let admins_classes = classes::table
-                .filter(classes::class_id.eq(any(admins_class_ids)))
-                .load::<Classroom>(&*connection)
-                .unwrap();
+    let user_classes = match ResponseClassroom::load(user_classes, &connection) {
+        Ok(c) => c,
+        Err(_) => return Err(Status::InternalServerError),
+    };
 
-            Ok(Json(json!({ "class_ids": admins_classes })))
-        }
-    }
+    Ok(Json(json!({ "class_ids": user_classes, "roles": roles })))
 }
 
-#[post("/topic", data = "<new_topic>")]
+#[post("/<class_id>/topics", data = "<new_topic>")]
 fn topic(
-    key: ClassGuard,
+    key: Can<CreateTopic>,
+    class_id: String,
     new_topic: Json<NewTopic>,
     connection: db::DbConn,
 ) -> Result<Json<JsonValue>, Status> {
-    let topic = new_topic.into_inner();
+    let topic = NewTopic {
+        classroom_id: class_id,
+        ..new_topic.into_inner()
+    };
 
-    match User::get_role(&key.0, &*connection).unwrap() {
-        Role::Student => {
-            return Err(Status::Forbidden);
-        }
-        _ => {
-            match Topic::create(topic, &*connection) {
-                Ok(_) => {}
-                Err(_) => {
-                    return Err(Status::Conflict);
-                }
-            };
+    match Topic::create(topic, &*connection) {
+        Ok(_) => {}
+        Err(_) => {
+            return Err(Status::Conflict);
         }
-    }
+    };
 
     Ok(Json(json!({"status":200})))
 }
 
+/// The class's users of one role, as they're shown.
+fn class_users(user_ids: impl Iterator<Item = String>, conn: &PgConnection) -> Result<Vec<ResponseUser>, Status> {
+    let users = user_ids
+        .map(|id| User::find_user(&id, conn))
+        .collect::<Result<Vec<User>, _>>();
+
+    match users.and_then(|u| ResponseUser::load(u, conn)) {
+        Ok(u) => Ok(u),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
 #[get("/<class_id>", rank = 1)]
-fn class(key: ClassGuard, class_id: String, conn: db::DbConn) -> Result<Json<JsonValue>, Status> {
+fn class(key: Can<ViewClass>, class_id: String, conn: db::DbConn) -> Result<Json<JsonValue>, Status> {
     let class = match Classroom::find(&class_id, &conn) {
         Ok(c) => c,
         Err(_) => return Err(Status::NotFound),
     };
 
-    let students = load_classuser::<Student>(&class_id, &conn)
-        .iter()
-        .map(|x| User::find_user(&x.user_id, &conn).unwrap())
-        .collect::<Vec<ResponseUser>>();
-    let admins = load_classuser::<Admin>(&class_id, &conn)
-        .iter()
-        .map(|x| User::find_user(&x.user_id, &conn).unwrap())
-        .collect::<Vec<ResponseUser>>();
-    let teachers = load_classuser::<Teacher>(&class_id, &conn)
-        .iter()
-        .map(|x| User::find_user(&x.user_id, &conn).unwrap())
-        .collect::<Vec<ResponseUser>>();
-
-    let assignments = Assignment::load(&class.class_id, &conn).unwrap();
+    let students = class_users(load_classuser::<Student>(&class_id, &conn).into_iter().map(|x| x.user_id), &conn)?;
+    let admins = class_users(load_classuser::<Admin>(&class_id, &conn).into_iter().map(|x| x.user_id), &conn)?;
+    let teachers = class_users(load_classuser::<Teacher>(&class_id, &conn).into_iter().map(|x| x.user_id), &conn)?;
+
+    let assignments = match key.1 {
+        Role::Student => Assignment::load_for_student(&class.class_id, &key.0, &conn).unwrap(),
+        _ => Assignment::load(&class.class_id, &conn).unwrap(),
+    };
 
     let announcements = Announcement::load_in_class(&conn, class_id).unwrap();
 
+    let class = match ResponseClassroom::new(class, &conn) {
+        Ok(c) => c,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
     Ok(Json(
         json!({"class": class, "students": students, "admins": admins, "teachers": teachers, "assignments":assignments, "announcements":announcements}),
     ))
@@ -259,15 +273,13 @@ fn class(key: ClassGuard, class_id: String, conn: db::DbConn) -> Result<Json<Jso
 
 #[patch("/<class_id>", data = "<new_class>")]
 async fn update_class<'a>(
-    key: ApiKey,
+    key: Can<UpdateClass>,
     class_id: String,
     new_class: Form<NewClassroom<'a>>,
     conn: db::DbConn,
 ) -> Result<Json<JsonValue>, Status> {
     let new_class = new_class.into_inner();
 
-    let user = User::find_user(&key.0, &conn).unwrap();
-
     let class = match Classroom::find(&class_id, &conn) {
         Ok(c) => c,
         Err(_) => return Err(Status::NotFound),
@@ -284,7 +296,7 @@ async fn update_class<'a>(
             .await
         {
             Ok(v) => Some(v),
-            Err(_) => return Err(Status::BadRequest),
+            Err(e) => return Err(e.status()),
         },
         None => {
             None
@@ -294,7 +306,7 @@ async fn update_class<'a>(
     let update_ = Classroom {
         class_id: class_id,
         class_name: new_class.class_name,
-        class_creator: Option::from(user.user_id),
+        class_creator: class.class_creator,
         class_description: new_class.class_description,
         class_image: match image_file {
             Some(v) => Some(v),
@@ -304,14 +316,7 @@ async fn update_class<'a>(
         created_at: class.created_at,
     };
 
-    match User::get_role(&key.0, &conn).unwrap() {
-        Role::Admin => {
-            update(cloned_class, update_, &conn);
-        }
-        _ => {
-            return Err(Status::Forbidden);
-        }
-    }
+    update(cloned_class, update_, &conn);
 
     Ok(Json(json!({"status":200})))
 }
@@ -322,6 +327,7 @@ pub fn mount(rocket: rocket::Rocket<rocket::Build>) -> rocket::Rocket<rocket::Bu
         routes![
             create_classroom,
             join,
+            join_as_teacher,
             classrooms,
             topic,
             class,
@@ -345,7 +351,36 @@ pub fn mount(rocket: rocket::Rocket<rocket::Build>) -> rocket::Rocket<rocket::Bu
             get_announcement,
             mark_submission,
             update_mark,
-            update_class
+            import_marks,
+            update_class,
+            gradebook,
+            categories,
+            create_category,
+            delete_category,
+            grades,
+            scale,
+            update_scale,
+            remedial,
+            excuse_submission,
+            extend_submission,
+            return_submission,
+            submission_history,
+            attempts,
+            attempt,
+            mark_attempt,
+            groups,
+            create_group,
+            update_group,
+            delete_group,
+            assign_peer_reviews,
+            peer_reviews,
+            my_peer_reviews,
+            review_peer,
+            extension_history,
+            remind,
+            rubric,
+            update_rubric,
+            delete_rubric
         ],
     )
 }
@@ -5,7 +5,12 @@ use diesel::result::Error;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 
+use crate::assignments::models::Assignment;
+use crate::errors::ThearningResult;
 use crate::schema::classes;
+use crate::submissions::models::{FillableSubmissions, Submissions};
+use crate::traits::{ClassUser, Manipulable};
+use crate::users::models::Student;
 
 pub fn get_class_codes(connection: &PgConnection) -> Result<Vec<String>, Error> {
     classes::table
@@ -26,3 +31,24 @@ pub fn generate_class_code(existing_codes: &Vec<String>) -> String {
         code
     }
 }
+
+/// Adds a student to a class and opens a submission for every assignment
+/// already posted there for the whole class.
+pub fn enroll_student(uid: &String, class_id: &String, conn: &PgConnection) -> ThearningResult<Student> {
+    let student = Student::create(uid, class_id, conn)?;
+
+    for i in Assignment::load(class_id, conn)? {
+        if !i.targets(conn)?.is_class() {
+            continue;
+        }
+
+        let new_submission = FillableSubmissions {
+            assignment_id: i.assignment_id,
+            user_id: student.user_id.clone(),
+            group_id: None,
+        };
+        Submissions::create(new_submission, conn)?;
+    }
+
+    Ok(student)
+}
@@ -5,7 +5,7 @@ use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use crate::schema::{comments, private_comments};
+use crate::schema::{comments, peer_reviews, private_comments};
 use crate::traits::Manipulable;
 use crate::utils::generate_random_id;
 
@@ -82,6 +82,26 @@ impl PrivateComment {
             .filter(private_comments::submission_id.eq(submission_id))
             .load::<PrivateComment>(conn)?)
     }
+
+    /// The conversation on a submission, without the comments peer
+    /// reviewers left, which are shown with their review instead.
+    pub fn load_conversation(
+        submission_id: &String,
+        conn: &PgConnection,
+    ) -> ThearningResult<Vec<Self>> {
+        let reviews = peer_reviews::table
+            .filter(peer_reviews::submission_id.eq(submission_id))
+            .select(peer_reviews::comment_id)
+            .load::<Option<String>>(conn)?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<String>>();
+
+        Ok(Self::load_by_submission(submission_id, conn)?
+            .into_iter()
+            .filter(|c| !reviews.contains(&c.id))
+            .collect())
+    }
 }
 
 impl Manipulable<FillableComment> for Comment {
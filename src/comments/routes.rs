@@ -9,7 +9,7 @@ use rocket::serde::json::Json;
 use rocket::{self, routes};
 use rocket_dyn_templates::handlebars::JsonValue;
 
-use crate::auth::ClassGuard;
+use crate::policy::{Can, PostComment};
 use crate::comments::models::{Comment, FillableComment, FillablePrivateComment, PrivateComment};
 use crate::db;
 use crate::errors::ThearningResult;
@@ -18,7 +18,7 @@ use crate::users::models::User;
 
 #[post("/<class_id>/comments", data = "<data>")]
 pub fn post_comment(
-    key: ClassGuard,
+    key: Can<PostComment>,
     class_id: &str,
     data: Json<FillableComment>,
     conn: db::DbConn,
@@ -42,7 +42,7 @@ pub fn post_comment(
 
 #[post("/<class_id>/privatecomments", data = "<data>")]
 pub fn post_private_comment(
-    key: ClassGuard,
+    key: Can<PostComment>,
     class_id: &str,
     data: Json<FillablePrivateComment>,
     conn: db::DbConn,
@@ -66,7 +66,7 @@ pub fn post_private_comment(
 
 #[delete("/<class_id>/comments", data = "<data>")]
 pub fn delete_comment(
-    key: ClassGuard,
+    key: Can<PostComment>,
     class_id: &str,
     data: String,
     conn: db::DbConn,
@@ -87,7 +87,7 @@ pub fn delete_comment(
 
 #[delete("/<class_id>/privatecomments", data = "<data>")]
 pub fn delete_private_comment(
-    key: ClassGuard,
+    key: Can<PostComment>,
     class_id: &str,
     data: String,
     conn: db::DbConn,
@@ -13,18 +13,21 @@ pub enum ErrorKind {
     JWTError(jsonwebtoken::errors::Error),
     JWTCreationError(JWTCError),
     VarError(env::VarError),
+    StorageError(String),
     InvalidValue,
 }
 
 #[derive(Debug)]
 pub enum JWTCError {
     TokenExpired,
+    SessionRevoked,
 }
 
 impl fmt::Display for JWTCError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Self::TokenExpired => write!(f, "Token is expired!"),
+            Self::SessionRevoked => write!(f, "Session is revoked!"),
         }
     }
 }
@@ -47,6 +50,12 @@ impl From<env::VarError> for ErrorKind {
     }
 }
 
+impl From<reqwest::Error> for ErrorKind {
+    fn from(error: reqwest::Error) -> Self {
+        ErrorKind::StorageError(error.to_string())
+    }
+}
+
 impl From<std::io::Error> for ErrorKind {
     fn from(error: std::io::Error) -> Self {
         ErrorKind::IOError(error)
@@ -74,6 +83,7 @@ impl fmt::Display for ErrorKind {
             ErrorKind::JWTError(err) => err.to_string(),
             ErrorKind::JWTCreationError(err) => err.to_string(),
             ErrorKind::VarError(err) => err.to_string(),
+            ErrorKind::StorageError(err) => err.to_string(),
             ErrorKind::InvalidValue => "Invalid".to_string(),
         };
 
@@ -113,6 +123,16 @@ fn forbidden() -> Json<JsonValue> {
     Json(json!({"success":false, "status": 403}))
 }
 
+#[catch(423)]
+fn locked() -> Json<JsonValue> {
+    Json(json!({"success":false, "status": 423}))
+}
+
+#[catch(429)]
+fn too_many_requests() -> Json<JsonValue> {
+    Json(json!({"success":false, "status": 429}))
+}
+
 pub fn mount(rocket: rocket::Rocket<rocket::Build>) -> rocket::Rocket<rocket::Build> {
     rocket.register(
         "/",
@@ -122,7 +142,9 @@ pub fn mount(rocket: rocket::Rocket<rocket::Build>) -> rocket::Rocket<rocket::Bu
             bad_request,
             conflict,
             server_error,
-            forbidden
+            forbidden,
+            locked,
+            too_many_requests
         ],
     )
 }
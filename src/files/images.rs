@@ -0,0 +1,182 @@
+use std::io::Cursor;
+
+use image::imageops::FilterType;
+use image::io::{Limits, Reader};
+use image::{DynamicImage, ImageError, ImageFormat, ImageOutputFormat};
+
+use crate::errors::ErrorKind;
+use crate::files::models::{FileType, UploadError};
+
+/// Pictures wider or taller than this aren't decoded at all.
+pub const MAX_DECODED_SIDE: u32 = 10_000;
+
+/// The picture itself is scaled down to fit this on its longer side.
+pub const MAX_STORED_SIDE: u32 = 2048;
+
+const JPEG_QUALITY: u8 = 85;
+
+/// A size a picture is served in, cropped to fill it.
+pub struct Variant {
+    pub name: &'static str,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub const AVATAR_SMALL: Variant = Variant {
+    name: "avatar_64",
+    width: 64,
+    height: 64,
+};
+
+pub const AVATAR_LARGE: Variant = Variant {
+    name: "avatar_256",
+    width: 256,
+    height: 256,
+};
+
+pub const CLASS_BANNER: Variant = Variant {
+    name: "banner",
+    width: 1200,
+    height: 300,
+};
+
+/// A picture as it's stored, re-encoded without any of the metadata it came
+/// with, and the variants made from it.
+pub struct Processed {
+    pub bytes: Vec<u8>,
+    pub variants: Vec<(&'static Variant, Vec<u8>)>,
+}
+
+impl From<ImageError> for UploadError {
+    fn from(error: ImageError) -> Self {
+        match error {
+            ImageError::Decoding(_) | ImageError::Limits(_) | ImageError::Unsupported(_) => {
+                UploadError::Unreadable
+            }
+            _ => UploadError::Failed(ErrorKind::InvalidValue),
+        }
+    }
+}
+
+/// The orientation tag of a JPEG's EXIF data, 1 to 8, if it has one.
+pub fn exif_orientation(bytes: &[u8]) -> Option<u16> {
+    let mut pos = 2;
+
+    while bytes.get(pos) == Some(&0xFF) {
+        let marker = *bytes.get(pos + 1)?;
+
+        // Start of scan, the image data follows
+        if marker == 0xDA || marker == 0xD9 {
+            return None;
+        }
+
+        let len = u16::from_be_bytes([*bytes.get(pos + 2)?, *bytes.get(pos + 3)?]) as usize;
+        let segment = bytes.get(pos + 4..pos + 2 + len)?;
+
+        if marker == 0xE1 && segment.starts_with(b"Exif\0\0") {
+            return tiff_orientation(&segment[6..]);
+        }
+
+        pos += 2 + len;
+    }
+
+    None
+}
+
+fn tiff_orientation(tiff: &[u8]) -> Option<u16> {
+    let big_endian = match tiff.get(0..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+
+    let read16 = |at: usize| {
+        let b = [*tiff.get(at)?, *tiff.get(at + 1)?];
+        Some(if big_endian { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) })
+    };
+
+    let read32 = |at: usize| {
+        let b = [*tiff.get(at)?, *tiff.get(at + 1)?, *tiff.get(at + 2)?, *tiff.get(at + 3)?];
+        Some(if big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+    };
+
+    let ifd = read32(4)? as usize;
+
+    for i in 0..read16(ifd)? as usize {
+        let entry = ifd + 2 + i * 12;
+
+        if read16(entry)? == 0x0112 {
+            return read16(entry + 8).filter(|o| (1..=8).contains(o));
+        }
+    }
+
+    None
+}
+
+/// Turns the picture the way its EXIF orientation says it's meant to be
+/// seen, since the tag goes with the rest of the metadata.
+fn orient(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+fn encode(image: &DynamicImage, filetype: FileType) -> Result<Vec<u8>, UploadError> {
+    let mut bytes = Cursor::new(Vec::new());
+
+    match filetype {
+        FileType::PNG => image.write_to(&mut bytes, ImageOutputFormat::Png)?,
+        _ => DynamicImage::ImageRgb8(image.to_rgb8()).write_to(&mut bytes, ImageOutputFormat::Jpeg(JPEG_QUALITY))?,
+    }
+
+    Ok(bytes.into_inner())
+}
+
+/// Decodes a JPEG or PNG, sets it upright and re-encodes it, which leaves
+/// EXIF data, GPS position included, behind. Pictures bigger than
+/// [`MAX_STORED_SIDE`] are scaled down, and every variant is cropped from
+/// the result.
+pub fn process(bytes: &[u8], filetype: FileType, variants: &'static [Variant]) -> Result<Processed, UploadError> {
+    let format = match filetype {
+        FileType::JPEG => ImageFormat::Jpeg,
+        FileType::PNG => ImageFormat::Png,
+        _ => return Err(UploadError::Unsupported),
+    };
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DECODED_SIDE);
+    limits.max_image_height = Some(MAX_DECODED_SIDE);
+
+    let mut reader = Reader::new(Cursor::new(bytes));
+    reader.set_format(format);
+    reader.limits(limits);
+
+    let mut image = reader.decode()?;
+
+    if filetype == FileType::JPEG {
+        image = orient(image, exif_orientation(bytes).unwrap_or(1));
+    }
+
+    if image.width() > MAX_STORED_SIDE || image.height() > MAX_STORED_SIDE {
+        image = image.resize(MAX_STORED_SIDE, MAX_STORED_SIDE, FilterType::Lanczos3);
+    }
+
+    let mut rendered = Vec::with_capacity(variants.len());
+
+    for variant in variants {
+        let resized = image.resize_to_fill(variant.width, variant.height, FilterType::Lanczos3);
+        rendered.push((variant, encode(&resized, filetype)?));
+    }
+
+    Ok(Processed {
+        bytes: encode(&image, filetype)?,
+        variants: rendered,
+    })
+}
@@ -1,3 +1,4 @@
+pub(crate) mod images;
 pub mod models;
 pub(crate) mod routes;
-mod utils;
+pub(crate) mod utils;
@@ -1,13 +1,24 @@
 use chrono::{Local, NaiveDateTime};
 use diesel;
+use diesel::dsl::any;
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
+use rocket::http::Status;
+use rocket::serde::json::serde_json::json;
+use rocket::serde::json::Value as JsonValue;
 use serde::{Deserialize, Serialize};
 
+use std::collections::HashMap;
 use std::fmt;
 
-use crate::{errors::ErrorKind, schema::files, traits::Embedable};
+use crate::errors::{ErrorKind, ThearningResult};
+use crate::files::images::{Variant, AVATAR_LARGE, AVATAR_SMALL, CLASS_BANNER};
+use crate::files::utils::max_upload_size;
+use crate::schema::{file_variants, files};
+use crate::utils::generate_random_id;
+use crate::{storage, traits::Embedable};
 
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum FileType {
     MP4,
     MKV,
@@ -25,6 +36,59 @@ pub enum UploadType {
     ProfilePhoto,
     ClassPicture,
     AssignmentFile,
+    /// Attachments sent with tus, which are for what's too big to send in one go.
+    ResumableFile,
+}
+
+/// Why an upload was turned down, sent back to the client as JSON.
+#[derive(Debug)]
+pub enum UploadError {
+    Empty,
+    Unsupported,
+    Unreadable,
+    Mismatch { declared: FileType, detected: FileType },
+    TooLarge { size: u64, limit: u64 },
+    Failed(ErrorKind),
+}
+
+impl UploadType {
+    /// The folder uploads of the type are stored under.
+    pub fn folder(&self) -> &'static str {
+        match &self {
+            Self::ProfilePhoto => "profiles",
+            Self::ClassPicture => "classes",
+            Self::AssignmentFile | Self::ResumableFile => "attachments",
+        }
+    }
+
+    /// In bytes, set with `MAX_UPLOAD_<TYPE>` in MiB.
+    pub fn max_size(&self) -> u64 {
+        match &self {
+            Self::ProfilePhoto => max_upload_size("PROFILE_PHOTO", 5),
+            Self::ClassPicture => max_upload_size("CLASS_PICTURE", 10),
+            Self::AssignmentFile => max_upload_size("ASSIGNMENT_FILE", 50),
+            Self::ResumableFile => max_upload_size("RESUMABLE", 500),
+        }
+    }
+
+    /// Only pictures go where pictures are shown.
+    pub fn accepts(&self, filetype: FileType) -> bool {
+        match &self {
+            Self::ProfilePhoto | Self::ClassPicture => {
+                matches!(filetype, FileType::JPEG | FileType::PNG)
+            }
+            Self::AssignmentFile | Self::ResumableFile => true,
+        }
+    }
+
+    /// The sizes pictures of the type are served in besides their own.
+    pub fn variants(&self) -> &'static [Variant] {
+        match &self {
+            Self::ProfilePhoto => &[AVATAR_SMALL, AVATAR_LARGE],
+            Self::ClassPicture => &[CLASS_BANNER],
+            Self::AssignmentFile | Self::ResumableFile => &[],
+        }
+    }
 }
 
 impl FileType {
@@ -48,6 +112,58 @@ impl FileType {
         }
     }
 
+    /// Works out the type from the file's first bytes. Word and Excel
+    /// documents are zip archives, told apart by the folders inside, and
+    /// anything that's UTF-8 without NUL bytes passes as text.
+    pub fn sniff(bytes: &[u8]) -> Option<Self> {
+        let contains = |pattern: &[u8]| bytes.windows(pattern.len()).any(|w| w == pattern);
+
+        if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(Self::JPEG)
+        } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Self::PNG)
+        } else if bytes.starts_with(b"%PDF-") {
+            Some(Self::PDF)
+        } else if bytes.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+            Some(Self::MKV)
+        } else if bytes.get(4..8) == Some(b"ftyp") {
+            Some(Self::MP4)
+        } else if bytes.starts_with(b"Rar!\x1a\x07") {
+            Some(Self::RAR)
+        } else if bytes.starts_with(b"PK\x03\x04") {
+            if contains(b"word/") {
+                Some(Self::WordDocument)
+            } else if contains(b"xl/") {
+                Some(Self::ExcelDocument)
+            } else {
+                Some(Self::ZIP)
+            }
+        } else if !bytes.is_empty() && !bytes.contains(&0) && std::str::from_utf8(bytes).is_ok() {
+            Some(Self::Text)
+        } else {
+            None
+        }
+    }
+
+    /// Whether a file declared as `self` can turn out to be `detected`.
+    /// Office documents are zip archives, so they pass as zips.
+    pub fn admits(&self, detected: FileType) -> bool {
+        *self == detected
+            || (*self == Self::ZIP && matches!(detected, Self::WordDocument | Self::ExcelDocument))
+    }
+
+    /// In bytes, set with `MAX_UPLOAD_<EXTENSION>` in MiB.
+    pub fn max_size(&self) -> u64 {
+        let default = match &self {
+            Self::MP4 | Self::MKV | Self::RAR | Self::ZIP => 50,
+            Self::PDF | Self::WordDocument | Self::ExcelDocument => 25,
+            Self::JPEG | Self::PNG => 10,
+            Self::Text => 5,
+        };
+
+        max_upload_size(&self.ext().to_uppercase(), default)
+    }
+
     pub fn ext(&self) -> &'static str {
         match &self {
             Self::MP4 => "mp4",
@@ -82,11 +198,60 @@ impl fmt::Display for FileType {
         write!(f, "{}", filetype)
     }
 }
+impl UploadError {
+    pub fn status(&self) -> Status {
+        match &self {
+            Self::Empty | Self::Unreadable => Status::UnprocessableEntity,
+            Self::Unsupported | Self::Mismatch { .. } => Status::UnsupportedMediaType,
+            Self::TooLarge { .. } => Status::PayloadTooLarge,
+            Self::Failed(_) => Status::InternalServerError,
+        }
+    }
+
+    pub fn body(&self) -> JsonValue {
+        let status = self.status().code;
+
+        match &self {
+            Self::Empty => json!({"success": false, "status": status, "error": "empty_file"}),
+            Self::Unsupported => json!({"success": false, "status": status, "error": "unsupported_type"}),
+            Self::Unreadable => json!({"success": false, "status": status, "error": "unreadable_image"}),
+            Self::Mismatch { declared, detected } => json!({
+                "success": false,
+                "status": status,
+                "error": "type_mismatch",
+                "declared": declared.ext(),
+                "detected": detected.ext(),
+            }),
+            Self::TooLarge { size, limit } => json!({
+                "success": false,
+                "status": status,
+                "error": "too_large",
+                "size": size,
+                "limit": limit,
+            }),
+            Self::Failed(_) => json!({"success": false, "status": status, "error": "upload_failed"}),
+        }
+    }
+}
+
+impl From<ErrorKind> for UploadError {
+    fn from(error: ErrorKind) -> Self {
+        UploadError::Failed(error)
+    }
+}
+
+impl From<diesel::result::Error> for UploadError {
+    fn from(error: diesel::result::Error) -> Self {
+        UploadError::Failed(ErrorKind::from(error))
+    }
+}
+
 #[derive(Serialize, Deserialize, AsChangeset, Insertable, Associations, Clone, Queryable)]
 #[table_name = "files"]
 pub struct UploadedFile {
     pub file_id: String,
     pub filename: String,
+    /// The storage key, see [`storage::Storage`]
     pub file_path: String,
     pub file_url: String,
     pub filetype: String,
@@ -127,6 +292,99 @@ impl UploadedFile {
             .filter(files::file_url.eq(url))
             .get_result::<Self>(conn)
     }
+
+    pub fn get_by_key(key: &String, conn: &PgConnection) -> QueryResult<Self> {
+        files::table
+            .filter(files::file_path.eq(key))
+            .get_result::<Self>(conn)
+    }
+
+    pub fn variants(&self, conn: &PgConnection) -> ThearningResult<Vec<FileVariant>> {
+        Ok(file_variants::table
+            .filter(file_variants::file_id.eq(&self.file_id))
+            .load::<FileVariant>(conn)?)
+    }
+
+    /// Forgets the file and removes it from storage in the background,
+    /// along with its variants.
+    pub fn remove(&self, conn: &PgConnection) -> ThearningResult<usize> {
+        let mut keys = vec![self.file_path.clone()];
+        keys.extend(self.variants(conn)?.into_iter().map(|v| v.file_path));
+
+        tokio::task::spawn(async move {
+            if let Ok(backend) = storage::backend() {
+                for key in keys {
+                    backend.delete(&key).await.ok();
+                }
+            }
+        });
+
+        Ok(diesel::delete(files::table.find(&self.file_id)).execute(conn)?)
+    }
 }
 
 impl Embedable for UploadedFile {}
+
+/// A picture resized to one of the sizes it's served in, see
+/// [`UploadType::variants`].
+#[derive(Serialize, Deserialize, Queryable, Insertable, Clone)]
+#[table_name = "file_variants"]
+pub struct FileVariant {
+    pub id: String,
+    pub file_id: String,
+    pub variant: String,
+    pub width: i32,
+    pub height: i32,
+    pub file_path: String,
+    pub file_url: String,
+    pub created_at: NaiveDateTime,
+}
+
+impl FileVariant {
+    pub fn new(
+        file: &UploadedFile,
+        variant: &Variant,
+        file_path: &String,
+        file_url: &String,
+        conn: &PgConnection,
+    ) -> ThearningResult<Self> {
+        let new_variant = Self {
+            id: format!("{}{}", generate_random_id(), generate_random_id()),
+            file_id: file.file_id.clone(),
+            variant: variant.name.to_string(),
+            width: variant.width as i32,
+            height: variant.height as i32,
+            file_path: file_path.clone(),
+            file_url: file_url.clone(),
+            created_at: Local::now().naive_local(),
+        };
+
+        Ok(diesel::insert_into(file_variants::table)
+            .values(&new_variant)
+            .get_result::<Self>(conn)?)
+    }
+
+    /// The URL of each variant of the file served at `url`, by variant name.
+    /// Empty for files without variants, like the placeholder picture.
+    pub fn urls_of(url: &String, conn: &PgConnection) -> ThearningResult<HashMap<String, String>> {
+        Ok(Self::urls_of_all(&[url.clone()], conn)?.remove(url).unwrap_or_default())
+    }
+
+    /// Like `urls_of` for every file served at one of `urls`, by file URL,
+    /// in a single query. Files without variants are left out.
+    pub fn urls_of_all(urls: &[String], conn: &PgConnection) -> ThearningResult<HashMap<String, HashMap<String, String>>> {
+        let variants = file_variants::table
+            .inner_join(files::table)
+            .filter(files::file_url.eq(any(urls)))
+            .select((files::file_url, file_variants::variant, file_variants::file_url))
+            .load::<(String, String, String)>(conn)?;
+
+        let mut res = HashMap::<String, HashMap<String, String>>::new();
+
+        for (url, variant, variant_url) in variants {
+            res.entry(url).or_default().insert(variant, variant_url);
+        }
+
+        Ok(res)
+    }
+}
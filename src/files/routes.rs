@@ -1,138 +1,200 @@
+use std::io::SeekFrom;
+use std::path::PathBuf;
 use std::{env, io};
 
+use chrono::Local;
 use diesel::{Connection, PgConnection};
 use rocket::form::Form;
-use rocket::fs::relative;
-use rocket::fs::{FileServer, TempFile};
-use rocket::http::Status;
+use rocket::fs::TempFile;
+use rocket::http::{ContentType, Status};
+use rocket::request::Request;
+use rocket::response::{self, Redirect, Responder, Response};
 use rocket::serde::json::serde_json::json;
 use rocket::serde::json::Json;
 use rocket_dyn_templates::handlebars::JsonValue;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, Take};
 
 use crate::attachments::models::{Attachment, FillableAttachment};
-use crate::auth::ApiKey;
+use crate::auth::{verify_signature, ApiKey};
 use crate::db::database_url;
-use crate::errors::ThearningResult;
-use crate::files::models::{FileType, UploadType, UploadedFile};
+use crate::errors::{ErrorKind, ThearningResult};
+use crate::files::images::{self, Variant};
+use crate::files::models::{FileType, FileVariant, UploadError, UploadType, UploadedFile};
+use crate::files::utils::{
+    byte_range, can_view, inspect, inspect_head, media_payload, sanitize_filename, signed_url, ByteRange, RangeHeader,
+};
+use crate::storage::Served;
 use crate::users::models::User;
-use crate::utils::generate_random_id;
-use crate::{db, MEDIA_URL};
+use crate::utils::{generate_random_id, read_upload};
+use crate::{db, storage};
 
-pub async fn process_image<'a>(
-    mut image: TempFile<'a>,
-    upload_type: UploadType,
-    filename: &String,
-) -> ThearningResult<String> {
-    let url = env::var("SITE_URL")?;
-    let file_id = generate_random_id().to_string();
-    let current_dir = std::env::current_dir()?;
-    let file = match &upload_type {
-        UploadType::ProfilePhoto => {
-            format!(
-                "{}/{}/profiles/{}-{}",
-                current_dir.display(),
-                MEDIA_URL,
-                &file_id,
-                filename
-            )
-        }
-        UploadType::ClassPicture => {
-            format!(
-                "{}/{}/classes/{}-{}",
-                current_dir.display(),
-                MEDIA_URL,
-                &file_id,
-                filename
-            )
-        }
-        UploadType::AssignmentFile => {
-            todo!()
-        }
-    };
-    let url = match &upload_type {
-        UploadType::ProfilePhoto => {
-            format!(
-                "{}/{}-{}",
-                format!("{}/api/media/img/profiles", url),
-                &file_id,
-                filename
-            )
-        }
-        UploadType::ClassPicture => {
-            format!(
-                "{}/{}-{}",
-                format!("{}/api/media/img/classes", url),
-                &file_id,
-                filename
-            )
-        }
-        UploadType::AssignmentFile => {
-            format!(
-                "{}/{}-{}",
-                format!("{}/api/media/attachments", url),
-                &file_id,
-                filename
-            )
-        }
-    };
-    let db_conn = PgConnection::establish(&database_url())?;
-    UploadedFile::new(
-        &file_id,
-        &filename,
-        &file,
-        &url,
-        &"image".to_string(),
-        &db_conn,
-    )?;
-    image.move_copy_to(&file).await?;
-    Ok(url)
+/// What's being stored.
+pub enum Contents<'a> {
+    Bytes(Vec<u8>),
+    /// Objects already in storage to be joined into one, `size` bytes long,
+    /// which starts with `head`.
+    Joined { parts: &'a [String], head: Vec<u8>, size: u64 },
 }
 
-pub async fn process_attachment<'a>(
-    mut f: TempFile<'a>,
-    name: &str,
-    ext: &str,
-    ft: FileType,
-) -> ThearningResult<UploadedFile> {
-    let url = env::var("SITE_URL").unwrap();
+/// Checks an upload and stores it, see [`inspect`] for what's turned down.
+async fn store(
+    contents: Contents<'_>,
+    declared: Option<&ContentType>,
+    upload_type: &UploadType,
+    filename: impl Fn(FileType) -> String,
+) -> Result<UploadedFile, UploadError> {
+    let filetype = match &contents {
+        Contents::Bytes(bytes) => inspect(bytes, declared, upload_type)?,
+        Contents::Joined { head, size, .. } => inspect_head(head, *size, declared, upload_type)?,
+    };
+
     let file_id = format!(
         "{}{}",
         generate_random_id().to_string(),
         generate_random_id().to_string()
     );
-    let current_dir = std::env::current_dir()?;
-    let file = format!(
-        "{}/{}/attachments/{}-{}.{}",
-        current_dir.display(),
-        MEDIA_URL,
-        &file_id,
-        name,
-        ext
-    );
+    let filename = filename(filetype);
+    let key = storage::key_for(upload_type.folder(), &file_id, &filename);
+    let url = storage::url_for(&key)?;
 
-    let url = format!(
-        "{}/{}-{}.{}",
-        format!("{}/api/media/attachments", url),
-        &file_id,
-        name,
-        ext
-    );
+    let content_type = match declared {
+        Some(t) => t.to_string(),
+        None => ContentType::Binary.to_string(),
+    };
+
+    match contents {
+        Contents::Bytes(bytes) => storage::backend()?.put(&key, bytes, &content_type).await?,
+        Contents::Joined { parts, .. } => storage::backend()?.join(&key, parts, &content_type).await?,
+    }
 
-    let db_conn = PgConnection::establish(&database_url()).unwrap();
+    let db_conn = PgConnection::establish(&database_url()).map_err(ErrorKind::from)?;
 
-    let up = UploadedFile::new(
+    Ok(UploadedFile::new(
         &file_id,
-        &format!("{}.{}", name, ext),
-        &file,
+        &filename,
+        &key,
         &url,
-        &ft.to_string(),
+        &filetype.to_string(),
         &db_conn,
-    )
-    .unwrap();
+    )?)
+}
+
+/// Stores a profile photo or class picture without its EXIF data, along
+/// with the sizes it's served in, see [`images::process`].
+pub async fn process_image<'a>(
+    mut image: TempFile<'a>,
+    upload_type: UploadType,
+    filename: &String,
+) -> Result<String, UploadError> {
+    let bytes = read_upload(&mut image).await?;
+    let filetype = inspect(&bytes, image.content_type(), &upload_type)?;
+    let variants = upload_type.variants();
+
+    // Decoding and resizing would hold up other requests
+    let processed = tokio::task::spawn_blocking(move || images::process(&bytes, filetype, variants))
+        .await
+        .map_err(|e| ErrorKind::StorageError(e.to_string()))??;
+
+    let file_id = format!("{}{}", generate_random_id(), generate_random_id());
+    let filename = sanitize_filename(filename);
+    let key = storage::key_for(upload_type.folder(), &file_id, &filename);
+    let url = storage::url_for(&key)?;
+
+    let content_type = match ContentType::from_extension(filetype.ext()) {
+        Some(t) => t.to_string(),
+        None => ContentType::Binary.to_string(),
+    };
+
+    let variant_keys = processed
+        .variants
+        .iter()
+        .map(|(variant, _)| (*variant, storage::variant_key_for(upload_type.folder(), &file_id, variant.name, filetype.ext())))
+        .collect::<Vec<_>>();
+
+    let mut objects = vec![(key.clone(), processed.bytes)];
+    objects.extend(
+        processed
+            .variants
+            .into_iter()
+            .zip(&variant_keys)
+            .map(|((_, bytes), (_, variant_key))| (variant_key.clone(), bytes)),
+    );
+
+    let backend = storage::backend()?;
+
+    let mut written = Vec::new();
+    let mut put = Ok(());
+
+    for (object_key, bytes) in objects {
+        put = backend.put(&object_key, bytes, &content_type).await;
+
+        if put.is_err() {
+            break;
+        }
+
+        written.push(object_key);
+    }
 
-    f.move_copy_to(&file).await?;
+    let file = put.and_then(|_| record_image(&file_id, &filename, &key, &url, filetype, &variant_keys));
 
-    Ok(up)
+    // Nothing is left in storage without its rows
+    if file.is_err() {
+        for object_key in &written {
+            backend.delete(object_key).await.ok();
+        }
+    }
+
+    Ok(file?.file_url)
+}
+
+/// The rows for a stored picture and its variants, all or none of them.
+fn record_image(
+    file_id: &String,
+    filename: &String,
+    key: &String,
+    url: &String,
+    filetype: FileType,
+    variant_keys: &[(&Variant, String)],
+) -> ThearningResult<UploadedFile> {
+    let db_conn = PgConnection::establish(&database_url())?;
+
+    db_conn.transaction(|| {
+        let file = UploadedFile::new(file_id, filename, key, url, &filetype.to_string(), &db_conn)?;
+
+        for (variant, variant_key) in variant_keys {
+            FileVariant::new(&file, variant, variant_key, &storage::url_for(variant_key)?, &db_conn)?;
+        }
+
+        Ok(file)
+    })
+}
+
+pub async fn process_attachment<'a>(mut f: TempFile<'a>, name: &str) -> Result<UploadedFile, UploadError> {
+    let bytes = read_upload(&mut f).await?;
+
+    store_attachment(Contents::Bytes(bytes), f.content_type(), name, &UploadType::AssignmentFile).await
+}
+
+/// Stores an attachment as `<name>.<extension>`, the extension going by
+/// what the file turned out to be.
+pub async fn store_attachment(
+    contents: Contents<'_>,
+    declared: Option<&ContentType>,
+    name: &str,
+    upload_type: &UploadType,
+) -> Result<UploadedFile, UploadError> {
+    let name = sanitize_filename(name);
+
+    store(contents, declared, upload_type, |filetype| {
+        let ext = format!(".{}", filetype.ext());
+
+        match name.to_lowercase().ends_with(&ext) {
+            true => name.clone(),
+            false => format!("{}{}", name, ext),
+        }
+    })
+    .await
 }
 
 #[derive(FromForm)]
@@ -149,38 +211,22 @@ async fn upload_file<'a>(
     key: ApiKey,
     data: Form<AttachmentData<'a>>,
     conn: db::DbConn,
-) -> Result<Json<JsonValue>, Status> {
+) -> Result<Json<JsonValue>, (Status, Json<JsonValue>)> {
     let user = match User::find_user(&key.0, &conn) {
         Ok(u) => u,
-        Err(_) => return Err(Status::Unauthorized),
+        Err(_) => return Err((Status::Unauthorized, Json(json!({"success": false, "status": 401, "error": "unauthorized"})))),
     };
 
     let data = data.into_inner();
 
-    let file = data.file;
-
-    let file_content_type = &file.content_type();
-
-    let ft = match file_content_type {
-        Some(t) => t.to_string(),
-        None => return Err(Status::BadRequest),
-    };
-
     let name = match data.filename {
         Some(n) => n,
         None => "",
     };
 
-    let filetype = match FileType::from_str(&ft) {
-        Ok(v) => v,
-        Err(_) => return Err(Status::BadRequest),
-    };
-
-    let uploaded_file = process_attachment(file, name, filetype.ext(), filetype).await;
-
-    let new_file = match uploaded_file {
+    let new_file = match process_attachment(data.file, name).await {
         Ok(v) => v,
-        Err(_) => return Err(Status::BadRequest),
+        Err(e) => return Err((e.status(), Json(e.body()))),
     };
 
     let cloned_file = new_file.clone();
@@ -201,19 +247,142 @@ async fn upload_file<'a>(
     Ok(Json(json!({"attachment": &attachment, "file": file})))
 }
 
+/// A stored file as it's sent, streamed from disk rather than read into
+/// memory first.
+enum Media {
+    Whole { content_type: ContentType, file: File, size: u64 },
+    Part { content_type: ContentType, body: Take<File>, start: u64, end: u64, size: u64 },
+    Unsatisfiable { size: u64 },
+    /// Sent by the backend itself.
+    Elsewhere(String),
+}
+
+impl<'r> Responder<'r, 'static> for Media {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        match self {
+            Media::Whole { content_type, file, size } => Response::build()
+                .header(content_type)
+                .raw_header("Accept-Ranges", "bytes")
+                .sized_body(size as usize, file)
+                .ok(),
+            Media::Part { content_type, body, start, end, size } => Response::build()
+                .status(Status::PartialContent)
+                .header(content_type)
+                .raw_header("Accept-Ranges", "bytes")
+                .raw_header("Content-Range", format!("bytes {}-{}/{}", start, end, size))
+                .raw_header("Content-Length", (end - start + 1).to_string())
+                .streamed_body(body)
+                .ok(),
+            Media::Unsatisfiable { size } => Response::build()
+                .status(Status::RangeNotSatisfiable)
+                .raw_header("Content-Range", format!("bytes */{}", size))
+                .ok(),
+            Media::Elsewhere(url) => Redirect::temporary(url).respond_to(request),
+        }
+    }
+}
+
+/// Sends the file, or the part of it asked for, so videos can be seeked.
+async fn serve(key: &str, range: &RangeHeader) -> Result<Media, Status> {
+    let content_type = key
+        .rsplit_once('.')
+        .and_then(|(_, ext)| ContentType::from_extension(ext))
+        .unwrap_or(ContentType::Binary);
+
+    let backend = match storage::backend() {
+        Ok(b) => b,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let (mut file, size) = match backend.serve(key).await {
+        Ok(Served::File { file, size }) => (file, size),
+        Ok(Served::Redirect(url)) => return Ok(Media::Elsewhere(url)),
+        Err(_) => return Err(Status::NotFound),
+    };
+
+    match byte_range(range.0.as_deref(), size) {
+        ByteRange::Whole => Ok(Media::Whole { content_type, file, size }),
+        ByteRange::Part(start, end) => match file.seek(SeekFrom::Start(start)).await {
+            Ok(_) => Ok(Media::Part { content_type, body: file.take(end - start + 1), start, end, size }),
+            Err(_) => Err(Status::InternalServerError),
+        },
+        ByteRange::Unsatisfiable => Ok(Media::Unsatisfiable { size }),
+    }
+}
+
+/// Whether the key is in one of the folders anyone can see. Attachments
+/// and unfinished uploads aren't.
+fn is_public(key: &str) -> bool {
+    [UploadType::ProfilePhoto, UploadType::ClassPicture]
+        .iter()
+        .any(|t| key.starts_with(&format!("{}/", t.folder())))
+}
+
+/// Profile photos and class pictures, which anyone can see.
+#[get("/<key..>")]
+async fn media(key: PathBuf, range: RangeHeader) -> Result<Media, Status> {
+    match key.to_str() {
+        Some(k) if is_public(k) => serve(k, &range).await,
+        _ => Err(Status::NotFound),
+    }
+}
+
+fn viewable(key: &ApiKey, name: &str, conn: &PgConnection) -> Result<UploadedFile, Status> {
+    let file = match UploadedFile::get_by_key(&format!("attachments/{}", name), conn) {
+        Ok(f) => f,
+        Err(_) => return Err(Status::NotFound),
+    };
+
+    match can_view(&key.0, &file, conn) {
+        Ok(true) => Ok(file),
+        Ok(false) => Err(Status::Forbidden),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+/// An attachment, for those allowed to see it or with a signed URL to it.
+#[get("/attachments/<name>?<expires>&<signature>")]
+async fn attachment(
+    key: Option<ApiKey>,
+    name: &str,
+    expires: Option<i64>,
+    signature: Option<&str>,
+    range: RangeHeader,
+    conn: db::DbConn,
+) -> Result<Media, Status> {
+    let storage_key = format!("attachments/{}", name);
+
+    match (expires, signature, key) {
+        (Some(expires), Some(signature), _) => {
+            if expires < Local::now().timestamp() {
+                return Err(Status::Gone);
+            }
+
+            if !verify_signature(&media_payload(&storage_key, expires), signature) {
+                return Err(Status::Unauthorized);
+            }
+        }
+        (_, _, Some(key)) => {
+            viewable(&key, name, &conn)?;
+        }
+        _ => return Err(Status::Unauthorized),
+    }
+
+    serve(&storage_key, &range).await
+}
+
+/// A short-lived URL to the attachment that works without a bearer token.
+#[get("/attachments/<name>/signed")]
+fn sign_attachment(key: ApiKey, name: &str, conn: db::DbConn) -> Result<Json<JsonValue>, Status> {
+    let file = viewable(&key, name, &conn)?;
+
+    let (url, expires) = signed_url(&file);
+
+    Ok(Json(json!({ "url": url, "expires": expires })))
+}
+
 pub fn mount(rocket: rocket::Rocket<rocket::Build>) -> rocket::Rocket<rocket::Build> {
     rocket
         .mount("/api/upload", routes![upload_file])
-        .mount(
-            "/api/media/img/profiles",
-            FileServer::from(relative!("media/profiles")),
-        )
-        .mount(
-            "/api/media/img/classes",
-            FileServer::from(relative!("media/classes")),
-        )
-        .mount(
-            "/api/media/attachments",
-            FileServer::from(relative!("media/attachments")),
-        )
+        .mount("/api/media", routes![media, attachment, sign_attachment])
 }
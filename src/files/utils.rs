@@ -1,12 +1,272 @@
+use std::env;
+
+use chrono::Local;
+use diesel::dsl::any;
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use diesel::query_dsl::QueryDsl;
 use diesel::result::Error;
+use rocket::http::ContentType;
+use rocket::request::{self, FromRequest, Request};
+
+use crate::announcements::models::Announcement;
+use crate::attachments::models::Attachment;
+use crate::assignments::models::Assignment;
+use crate::auth::sign;
+use crate::classes::models::Classroom;
+use crate::errors::ThearningResult;
+use crate::files::models::{FileType, UploadError, UploadType, UploadedFile};
+use crate::schema::{attachments, attempt_files, attempts, files, peer_reviews};
+use crate::submissions::models::Submissions;
+use crate::users::models::{Role, SystemRole, User};
 
-use crate::schema::files;
+/// How long a signed media URL keeps working, in seconds.
+pub const SIGNED_URL_TTL: i64 = 15 * 60;
 
 pub fn get_file_ids(connection: &PgConnection) -> Result<Vec<String>, Error> {
     files::table
         .select(files::file_id)
         .load::<String>(connection)
 }
+
+/// `MAX_UPLOAD_<name>` in MiB, in bytes.
+pub fn max_upload_size(name: &str, default_mib: u64) -> u64 {
+    let mib = env::var(format!("MAX_UPLOAD_{}", name))
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(default_mib);
+
+    mib * 1024 * 1024
+}
+
+/// Keeps the last path component of a client's filename, with anything but
+/// ASCII letters, digits, `-`, `_` and `.` replaced, so it can't climb out of
+/// its folder or break the URL it ends up in.
+pub fn sanitize_filename(name: &str) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or("");
+
+    let cleaned = base
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+
+    let cleaned = cleaned
+        .trim_matches(['.', '_'])
+        .chars()
+        .take(100)
+        .collect::<String>();
+
+    match cleaned.is_empty() {
+        true => "file".to_string(),
+        false => cleaned,
+    }
+}
+
+/// Works out what an upload is from its bytes and holds it to what the
+/// client declared and to the size limits of both the upload and the
+/// file type. Resumable uploads are only held to their own limit. A
+/// declared `application/octet-stream` says nothing.
+pub fn inspect(
+    bytes: &[u8],
+    declared: Option<&ContentType>,
+    upload_type: &UploadType,
+) -> Result<FileType, UploadError> {
+    inspect_head(bytes, bytes.len() as u64, declared, upload_type)
+}
+
+/// [`inspect`] for a file `size` bytes long of which only the start, `head`,
+/// is at hand.
+pub fn inspect_head(
+    head: &[u8],
+    size: u64,
+    declared: Option<&ContentType>,
+    upload_type: &UploadType,
+) -> Result<FileType, UploadError> {
+    // Text can have a character cut off where the head ends
+    let bytes = match std::str::from_utf8(head) {
+        Err(e) if size > head.len() as u64 && e.error_len().is_none() => &head[..e.valid_up_to()],
+        _ => head,
+    };
+
+    if size == 0 {
+        return Err(UploadError::Empty);
+    }
+
+    if size > upload_type.max_size() {
+        return Err(UploadError::TooLarge { size, limit: upload_type.max_size() });
+    }
+
+    let detected = match FileType::sniff(bytes) {
+        Some(t) if upload_type.accepts(t) => t,
+        _ => return Err(UploadError::Unsupported),
+    };
+
+    if let Some(t) = declared.filter(|t| **t != ContentType::Binary) {
+        match FileType::from_str(&format!("{}/{}", t.top(), t.sub())) {
+            Ok(d) if d.admits(detected) => {}
+            Ok(d) => return Err(UploadError::Mismatch { declared: d, detected }),
+            Err(_) => return Err(UploadError::Unsupported),
+        }
+    }
+
+    if !matches!(upload_type, UploadType::ResumableFile) && size > detected.max_size() {
+        return Err(UploadError::TooLarge { size, limit: detected.max_size() });
+    }
+
+    Ok(detected)
+}
+
+/// What part of a file a `Range` header asks for.
+#[derive(Debug, PartialEq)]
+pub enum ByteRange {
+    Whole,
+    /// From and to, both included.
+    Part(u64, u64),
+    Unsatisfiable,
+}
+
+/// The byte range of a file `size` bytes long that `header` asks for. Only
+/// single ranges are served, anything else gets the whole file.
+pub fn byte_range(header: Option<&str>, size: u64) -> ByteRange {
+    let spec = match header.and_then(|h| h.trim().strip_prefix("bytes=")) {
+        Some(s) if !s.contains(',') => s.trim(),
+        _ => return ByteRange::Whole,
+    };
+
+    let (start, end) = match spec.split_once('-') {
+        Some(s) => s,
+        None => return ByteRange::Whole,
+    };
+
+    let parse = |n: &str| n.trim().parse::<u64>().ok();
+
+    match (parse(start), parse(end)) {
+        // The last `n` bytes
+        (None, Some(n)) if start.trim().is_empty() => match n {
+            0 => ByteRange::Unsatisfiable,
+            _ if size == 0 => ByteRange::Unsatisfiable,
+            _ => ByteRange::Part(size.saturating_sub(n), size - 1),
+        },
+        (Some(s), None) if end.trim().is_empty() => match s < size {
+            true => ByteRange::Part(s, size - 1),
+            false => ByteRange::Unsatisfiable,
+        },
+        (Some(s), Some(e)) if s <= e => match s < size {
+            true => ByteRange::Part(s, e.min(size - 1)),
+            false => ByteRange::Unsatisfiable,
+        },
+        _ => ByteRange::Whole,
+    }
+}
+
+/// The request's `Range` header, if it sent one.
+pub struct RangeHeader(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RangeHeader {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        request::Outcome::Success(RangeHeader(request.headers().get_one("Range").map(|v| v.to_string())))
+    }
+}
+
+pub fn media_payload(key: &str, expires: i64) -> String {
+    format!("media:{}:{}", key, expires)
+}
+
+/// A URL to the file that works without a bearer token until it expires,
+/// for `<img>` and `<video>` tags and links in emails.
+pub fn signed_url(file: &UploadedFile) -> (String, i64) {
+    let expires = Local::now().timestamp() + SIGNED_URL_TTL;
+    let signature = sign(&media_payload(&file.file_path, expires));
+
+    (
+        format!("{}?expires={}&signature={}", file.file_url, expires, signature),
+        expires,
+    )
+}
+
+/// Whoever handed the submission in with its owner, the class's teachers,
+/// and the students reviewing it.
+fn can_view_submission(uid: &String, submission_id: &String, conn: &PgConnection) -> ThearningResult<bool> {
+    let submission = Submissions::find_submission(submission_id, conn)?;
+    let team = submission.team(conn)?;
+
+    if team.iter().any(|s| &s.user_id == uid) {
+        return Ok(true);
+    }
+
+    let assignment = Assignment::get_by_id(&submission.assignment_id, conn)?;
+
+    if let Some(class_id) = &assignment.class_id {
+        if let Some(Role::Teacher | Role::Admin) = Classroom::role_of(class_id, uid, conn)? {
+            return Ok(true);
+        }
+    }
+
+    let reviews = peer_reviews::table
+        .filter(peer_reviews::reviewer_id.eq(uid))
+        .filter(peer_reviews::submission_id.eq(any(team.iter().map(|s| &s.submission_id).collect::<Vec<&String>>())))
+        .count()
+        .get_result::<i64>(conn)?;
+
+    Ok(reviews > 0)
+}
+
+fn in_class(uid: &String, class_id: &Option<String>, conn: &PgConnection) -> ThearningResult<bool> {
+    match class_id {
+        Some(c) => Ok(Classroom::role_of(c, uid, conn)?.is_some()),
+        None => Ok(false),
+    }
+}
+
+/// Whether the user may download an uploaded file. Class material is open
+/// to the class, submitted work only to those who handed it in or grade or
+/// review it, and a file not attached to anything only to its uploader.
+pub fn can_view(uid: &String, file: &UploadedFile, conn: &PgConnection) -> ThearningResult<bool> {
+    if let Ok(SystemRole::Admin) = User::get_system_role(uid, conn) {
+        return Ok(true);
+    }
+
+    let attached = attachments::table
+        .filter(attachments::file_id.eq(&file.file_id))
+        .load::<Attachment>(conn)?;
+
+    for a in &attached {
+        let allowed = if &a.uploader == uid {
+            true
+        } else if let Some(id) = &a.assignment_id {
+            in_class(uid, &Assignment::get_by_id(id, conn)?.class_id, conn)?
+        } else if let Some(id) = &a.announcement_id {
+            in_class(uid, &Announcement::find_announcement(conn, id)?.class_id, conn)?
+        } else if let Some(id) = &a.submission_id {
+            can_view_submission(uid, id, conn)?
+        } else {
+            false
+        };
+
+        if allowed {
+            return Ok(true);
+        }
+    }
+
+    // Files taken off a submission live on in its earlier attempts
+    let held_by = attempt_files::table
+        .inner_join(attempts::table)
+        .filter(attempt_files::file_id.eq(&file.file_id))
+        .select(attempts::submission_id)
+        .load::<String>(conn)?;
+
+    for submission_id in &held_by {
+        if can_view_submission(uid, submission_id, conn)? {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
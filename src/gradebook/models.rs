@@ -0,0 +1,474 @@
+use std::collections::HashMap;
+
+use chrono::{Local, NaiveDateTime};
+use diesel::dsl::any;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use kosuzers::creator::{Cell, Style, Workbook};
+use serde::{Deserialize, Serialize};
+
+use crate::assignments::models::Assignment;
+use crate::errors::{ErrorKind, ThearningResult};
+use crate::gradebook::utils::{course_grade, standing, CourseGrade, Standing};
+use crate::schema::{grade_bands, grade_categories, grading_scales, marks, students, submissions, users};
+use crate::submissions::models::{Mark, Submissions};
+use crate::utils::generate_random_id;
+
+/// A share of the course grade, such as "Midterm 30%". `weight` is a percentage.
+#[derive(Serialize, Deserialize, Queryable, Insertable, Clone)]
+#[table_name = "grade_categories"]
+pub struct GradeCategory {
+    pub id: String,
+    pub class_id: String,
+    pub name: String,
+    pub weight: i32,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NewGradeCategory {
+    pub name: String,
+    pub weight: i32,
+}
+
+impl GradeCategory {
+    pub fn find(id: &String, conn: &PgConnection) -> ThearningResult<Self> {
+        Ok(grade_categories::table.find(id).get_result::<Self>(conn)?)
+    }
+
+    pub fn load(class_id: &String, conn: &PgConnection) -> ThearningResult<Vec<Self>> {
+        Ok(grade_categories::table
+            .filter(grade_categories::class_id.eq(class_id))
+            .order(grade_categories::created_at.asc())
+            .load::<Self>(conn)?)
+    }
+
+    pub fn create(class_id: &String, category: NewGradeCategory, conn: &PgConnection) -> ThearningResult<Self> {
+        let new_category = Self {
+            id: format!("{}{}", generate_random_id(), generate_random_id()),
+            class_id: class_id.to_string(),
+            name: category.name,
+            weight: category.weight,
+            created_at: Local::now().naive_local(),
+        };
+
+        diesel::insert_into(grade_categories::table)
+            .values(&new_category)
+            .execute(conn)?;
+
+        Ok(grade_categories::table
+            .find(new_category.id)
+            .get_result::<Self>(conn)?)
+    }
+
+    pub fn delete(&self, conn: &PgConnection) -> ThearningResult<Self> {
+        Ok(diesel::delete(grade_categories::table.find(&self.id)).get_result::<Self>(conn)?)
+    }
+}
+
+#[derive(Queryable, Insertable)]
+#[table_name = "grade_bands"]
+pub struct GradeBand {
+    pub id: String,
+    pub class_id: String,
+    pub min_score: i32,
+    pub label: String,
+    pub description: Option<String>,
+}
+
+/// A letter or predicate given to scores from `min_score` up to the next band.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FillableGradeBand {
+    pub min_score: i32,
+    pub label: String,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+/// How a class interprets its scores: the predicate bands, highest first,
+/// and the KKM every student has to reach.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GradingScale {
+    pub kkm: i32,
+    pub bands: Vec<FillableGradeBand>,
+}
+
+/// A score out of 100 read against the class's scale.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ScaledScore {
+    pub score: f64,
+    pub predicate: Option<String>,
+    pub below_kkm: bool,
+}
+
+fn band(min_score: i32, label: &str, description: &str) -> FillableGradeBand {
+    FillableGradeBand {
+        min_score,
+        label: label.to_string(),
+        description: Some(description.to_string()),
+    }
+}
+
+/// Used by classes that haven't set up their own scale.
+impl Default for GradingScale {
+    fn default() -> Self {
+        Self {
+            kkm: 75,
+            bands: vec![
+                band(90, "A", "Excellent"),
+                band(80, "B", "Good"),
+                band(75, "C", "Sufficient"),
+                band(0, "D", "Needs improvement"),
+            ],
+        }
+    }
+}
+
+impl GradingScale {
+    /// Every score from 0 to 100 has to land in exactly one band.
+    pub fn is_valid(&self) -> bool {
+        let mut scores = self.bands.iter().map(|b| b.min_score).collect::<Vec<i32>>();
+        scores.sort_unstable();
+        scores.dedup();
+
+        (0..=100).contains(&self.kkm)
+            && scores.len() == self.bands.len()
+            && scores.first() == Some(&0)
+            && scores.iter().all(|s| (0..=100).contains(s))
+            && self.bands.iter().all(|b| !b.label.trim().is_empty())
+    }
+
+    pub fn predicate(&self, score: f64) -> Option<&FillableGradeBand> {
+        self.bands.iter().find(|b| score >= b.min_score as f64)
+    }
+
+    pub fn passes(&self, score: f64) -> bool {
+        score >= self.kkm as f64
+    }
+
+    pub fn scale(&self, score: f64) -> ScaledScore {
+        ScaledScore {
+            score,
+            predicate: self.predicate(score).map(|b| b.label.clone()),
+            below_kkm: !self.passes(score),
+        }
+    }
+
+    pub fn load(class_id: &String, conn: &PgConnection) -> ThearningResult<Self> {
+        let kkm = grading_scales::table
+            .find(class_id)
+            .select(grading_scales::kkm)
+            .get_result::<i32>(conn)
+            .optional()?;
+
+        let kkm = match kkm {
+            Some(k) => k,
+            None => return Ok(Self::default()),
+        };
+
+        let bands = grade_bands::table
+            .filter(grade_bands::class_id.eq(class_id))
+            .order(grade_bands::min_score.desc())
+            .load::<GradeBand>(conn)?
+            .into_iter()
+            .map(|b| FillableGradeBand {
+                min_score: b.min_score,
+                label: b.label,
+                description: b.description,
+            })
+            .collect();
+
+        Ok(Self { kkm, bands })
+    }
+
+    /// Replaces the class's scale.
+    pub fn save(&self, class_id: &String, conn: &PgConnection) -> ThearningResult<Self> {
+        conn.transaction::<_, ErrorKind, _>(|| {
+            diesel::delete(grading_scales::table.find(class_id)).execute(conn)?;
+
+            diesel::insert_into(grading_scales::table)
+                .values((
+                    grading_scales::class_id.eq(class_id),
+                    grading_scales::kkm.eq(self.kkm),
+                    grading_scales::created_at.eq(Local::now().naive_local()),
+                ))
+                .execute(conn)?;
+
+            let bands = self
+                .bands
+                .iter()
+                .map(|b| GradeBand {
+                    id: format!("{}{}", generate_random_id(), generate_random_id()),
+                    class_id: class_id.to_string(),
+                    min_score: b.min_score,
+                    label: b.label.clone(),
+                    description: b.description.clone(),
+                })
+                .collect::<Vec<GradeBand>>();
+
+            diesel::insert_into(grade_bands::table)
+                .values(&bands)
+                .execute(conn)?;
+
+            Self::load(class_id, conn)
+        })
+    }
+}
+
+/// One student's line in the gradebook, with an entry per assignment.
+pub struct GradebookRow {
+    pub user_id: String,
+    pub fullname: String,
+    pub marks: Vec<Option<i32>>,
+    pub late: Vec<bool>,
+    pub submitted: Vec<bool>,
+    pub excused: Vec<bool>,
+    /// Whether the student was given the assignment at all
+    pub assigned: Vec<bool>,
+    /// The student's own deadline, extensions included
+    pub due: Vec<Option<NaiveDateTime>>,
+}
+
+pub struct Gradebook {
+    pub scale: GradingScale,
+    pub categories: Vec<GradeCategory>,
+    pub assignments: Vec<Assignment>,
+    pub rows: Vec<GradebookRow>,
+}
+
+fn average(values: &[i32]) -> Option<f64> {
+    match values.is_empty() {
+        true => None,
+        false => Some(values.iter().sum::<i32>() as f64 / values.len() as f64),
+    }
+}
+
+fn number_or_empty(value: Option<f64>) -> Cell {
+    match value {
+        Some(v) => Cell::number((v * 100.0).round() / 100.0),
+        None => Cell::empty(),
+    }
+}
+
+impl GradebookRow {
+    pub fn graded(&self) -> Vec<i32> {
+        self.marks.iter().flatten().copied().collect()
+    }
+
+    pub fn total(&self) -> i32 {
+        self.graded().iter().sum()
+    }
+
+    pub fn average(&self) -> Option<f64> {
+        average(&self.graded())
+    }
+
+    pub fn late_count(&self) -> usize {
+        self.late.iter().filter(|l| **l).count()
+    }
+}
+
+impl Gradebook {
+    /// Collects the marks of every student in the class for its published
+    /// assignments, oldest assignment first.
+    pub fn load(class_id: &String, conn: &PgConnection) -> ThearningResult<Self> {
+        let scale = GradingScale::load(class_id, conn)?;
+        let categories = GradeCategory::load(class_id, conn)?;
+
+        let mut assignments = Assignment::load(class_id, conn)?;
+        assignments.sort_by_key(|a| a.created_at);
+
+        let class_students = students::table
+            .inner_join(users::table)
+            .filter(students::class_id.eq(class_id))
+            .select((users::user_id, users::fullname))
+            .order(users::fullname.asc())
+            .load::<(String, String)>(conn)?;
+
+        let ids = assignments
+            .iter()
+            .map(|a| a.assignment_id.clone())
+            .collect::<Vec<String>>();
+
+        let class_submissions = submissions::table
+            .filter(submissions::assignment_id.eq(any(&ids)))
+            .load::<Submissions>(conn)?;
+
+        let submission_ids = class_submissions
+            .iter()
+            .map(|s| s.submission_id.clone())
+            .collect::<Vec<String>>();
+
+        // Marks that never made it onto the submission row
+        let class_marks = marks::table
+            .filter(marks::submission_id.eq(any(&submission_ids)))
+            .load::<Mark>(conn)?
+            .into_iter()
+            .filter_map(|m| m.submission_id.map(|s| (s, m.value)))
+            .collect::<HashMap<String, i32>>();
+
+        let by_student = class_submissions
+            .iter()
+            .map(|s| ((s.user_id.as_str(), s.assignment_id.as_str()), s))
+            .collect::<HashMap<(&str, &str), &Submissions>>();
+
+        let rows = class_students
+            .into_iter()
+            .map(|(user_id, fullname)| {
+                let found = assignments
+                    .iter()
+                    .map(|a| by_student.get(&(user_id.as_str(), a.assignment_id.as_str())))
+                    .collect::<Vec<_>>();
+
+                GradebookRow {
+                    marks: found
+                        .iter()
+                        .map(|s| {
+                            s.and_then(|s| {
+                                s.marks_allotted
+                                    .or_else(|| class_marks.get(&s.submission_id).copied())
+                            })
+                        })
+                        .collect(),
+                    late: found
+                        .iter()
+                        .map(|s| s.map_or(false, |s| s.handed_in() && s.on_time == Some(false)))
+                        .collect(),
+                    submitted: found.iter().map(|s| s.map_or(false, |s| s.submitted)).collect(),
+                    excused: found.iter().map(|s| s.map_or(false, |s| s.excused)).collect(),
+                    assigned: found.iter().map(|s| s.is_some()).collect(),
+                    due: found
+                        .iter()
+                        .zip(&assignments)
+                        .map(|(s, a)| s.map_or_else(|| a.due_at(), |s| s.due_at(a)))
+                        .collect(),
+                    user_id,
+                    fullname,
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            scale,
+            categories,
+            assignments,
+            rows,
+        })
+    }
+
+    /// Where a student stands on each assignment as of `now`.
+    pub fn standings(&self, row: &GradebookRow, now: NaiveDateTime) -> Vec<Standing> {
+        self.assignments
+            .iter()
+            .enumerate()
+            .map(|(i, a)| match row.assigned[i] {
+                true => standing(a, row.due[i], row.marks[i], row.submitted[i], row.excused[i], now),
+                false => Standing::Unassigned,
+            })
+            .collect()
+    }
+
+    pub fn course_grade(&self, row: &GradebookRow, now: NaiveDateTime) -> CourseGrade {
+        course_grade(&self.categories, &self.assignments, &self.standings(row, now))
+    }
+
+    /// Assignments the student has to redo: marked below KKM, or missing.
+    pub fn remedial(&self, row: &GradebookRow, now: NaiveDateTime) -> Vec<(&Assignment, Standing)> {
+        self.assignments
+            .iter()
+            .zip(self.standings(row, now))
+            .filter(|(_, s)| match s {
+                Standing::Graded(score) => !self.scale.passes(*score),
+                Standing::Missing => true,
+                Standing::Excused | Standing::Pending | Standing::Unassigned => false,
+            })
+            .collect()
+    }
+
+    /// The class average of each assignment, counting graded students only.
+    pub fn assignment_averages(&self) -> Vec<Option<f64>> {
+        (0..self.assignments.len())
+            .map(|i| {
+                let graded = self
+                    .rows
+                    .iter()
+                    .filter_map(|r| r.marks[i])
+                    .collect::<Vec<i32>>();
+
+                average(&graded)
+            })
+            .collect()
+    }
+
+    /// Lays the gradebook out as a single sheet. Late submissions are
+    /// highlighted and the last row holds the class averages. "Grade" is the
+    /// weighted course grade, out of 100, highlighted when below KKM.
+    pub fn to_workbook(&self, sheet_name: &str) -> Workbook {
+        let mut header = vec![Cell::text("NIS"), Cell::text("Full name")];
+
+        header.extend(self.assignments.iter().map(|a| {
+            let name = a.assignment_name.clone().unwrap_or_default();
+
+            match a.total_marks {
+                Some(total) => Cell::text(&format!("{} (/{})", name, total)),
+                None => Cell::text(&name),
+            }
+        }));
+
+        header.extend(vec![
+            Cell::text("Total"),
+            Cell::text("Average"),
+            Cell::text("Grade"),
+            Cell::text("Predicate"),
+            Cell::text("Late"),
+        ]);
+
+        let now = Local::now().naive_local();
+
+        let mut rows = vec![header.into_iter().map(|c| c.style(Style::Bold)).collect::<Vec<Cell>>()];
+
+        for row in &self.rows {
+            let mut cells = vec![Cell::text(&row.user_id), Cell::text(&row.fullname)];
+
+            cells.extend(row.marks.iter().zip(&row.late).map(|(mark, late)| {
+                let cell = match mark {
+                    Some(m) => Cell::number(*m as f64),
+                    None => Cell::empty(),
+                };
+
+                match late {
+                    true => cell.style(Style::Highlight),
+                    false => cell,
+                }
+            }));
+
+            cells.push(Cell::number(row.total() as f64));
+            cells.push(number_or_empty(row.average()));
+            match self.course_grade(row, now).grade {
+                Some(grade) => {
+                    let scaled = self.scale.scale(grade);
+
+                    let style = match scaled.below_kkm {
+                        true => Style::Highlight,
+                        false => Style::Plain,
+                    };
+
+                    cells.push(number_or_empty(Some(grade)).style(style));
+                    cells.push(Cell::text(&scaled.predicate.unwrap_or_default()));
+                }
+                None => cells.extend(vec![Cell::empty(), Cell::empty()]),
+            }
+            cells.push(Cell::number(row.late_count() as f64));
+
+            rows.push(cells);
+        }
+
+        let mut footer = vec![Cell::empty(), Cell::text("Average").style(Style::Bold)];
+
+        footer.extend(self.assignment_averages().into_iter().map(number_or_empty));
+
+        rows.push(footer);
+
+        Workbook::new().sheet(sheet_name, rows)
+    }
+}
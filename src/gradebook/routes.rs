@@ -0,0 +1,256 @@
+use chrono::Local;
+use rocket::http::{ContentType, Header, Status};
+use rocket::serde::json::serde_json::json;
+use rocket::serde::json::Json;
+use rocket_dyn_templates::handlebars::JsonValue;
+
+use crate::classes::models::Classroom;
+use crate::db;
+use crate::gradebook::models::{Gradebook, GradeCategory, GradingScale, NewGradeCategory};
+use crate::gradebook::utils::Standing;
+use crate::policy::{Can, ManageGrading, ViewClass, ViewGradebook};
+use crate::users::models::Role;
+
+#[derive(Responder)]
+pub struct XlsxFile(Vec<u8>, ContentType, Header<'static>);
+
+#[get("/<class_id>/gradebook.xlsx")]
+pub fn gradebook(
+    key: Can<ViewGradebook>,
+    class_id: &str,
+    conn: db::DbConn,
+) -> Result<XlsxFile, Status> {
+    let class = match Classroom::find(&class_id.to_string(), &conn) {
+        Ok(c) => c,
+        Err(_) => return Err(Status::NotFound),
+    };
+
+    let gradebook = match Gradebook::load(&class.class_id, &conn) {
+        Ok(g) => g,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let bytes = match gradebook.to_workbook(&class.class_name).to_bytes() {
+        Ok(b) => b,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let filename = format!("gradebook-{}.xlsx", class.class_id);
+
+    Ok(XlsxFile(
+        bytes,
+        ContentType::new(
+            "application",
+            "vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        ),
+        Header::new(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", filename),
+        ),
+    ))
+}
+
+#[get("/<class_id>/categories")]
+pub fn categories(
+    key: Can<ViewClass>,
+    class_id: &str,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    match GradeCategory::load(&class_id.to_string(), &conn) {
+        Ok(c) => Ok(Json(json!({ "categories": c }))),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+/// Weights are percentages and a class's categories can't add up to more
+/// than 100.
+#[post("/<class_id>/categories", data = "<category>")]
+pub fn create_category(
+    key: Can<ManageGrading>,
+    class_id: &str,
+    category: Json<NewGradeCategory>,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let category = category.into_inner();
+
+    let existing = match GradeCategory::load(&class_id.to_string(), &conn) {
+        Ok(c) => c,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let used = existing.iter().map(|c| c.weight).sum::<i32>();
+
+    if category.name.trim().is_empty() || category.weight < 1 || used + category.weight > 100 {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    match GradeCategory::create(&class_id.to_string(), category, &conn) {
+        Ok(c) => Ok(Json(json!({ "category": c }))),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+/// Assignments in the category become uncategorized.
+#[delete("/<class_id>/categories/<category_id>")]
+pub fn delete_category(
+    key: Can<ManageGrading>,
+    class_id: &str,
+    category_id: &str,
+    conn: db::DbConn,
+) -> Result<Status, Status> {
+    let category = match GradeCategory::find(&category_id.to_string(), &conn) {
+        Ok(c) if c.class_id == class_id => c,
+        _ => return Err(Status::NotFound),
+    };
+
+    match category.delete(&conn) {
+        Ok(_) => Ok(Status::Ok),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+/// Running course grades. Students only get their own.
+#[get("/<class_id>/grades")]
+pub fn grades(
+    key: Can<ViewClass>,
+    class_id: &str,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let gradebook = match Gradebook::load(&class_id.to_string(), &conn) {
+        Ok(g) => g,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let now = Local::now().naive_local();
+
+    let grades = gradebook
+        .rows
+        .iter()
+        .filter(|r| key.1 != Role::Student || r.user_id == key.0)
+        .map(|row| {
+            let course = gradebook.course_grade(row, now);
+
+            let assignments = gradebook
+                .assignments
+                .iter()
+                .zip(gradebook.standings(row, now))
+                .map(|(a, s)| {
+                    let below_kkm = match s {
+                        Standing::Graded(score) => !gradebook.scale.passes(score),
+                        _ => false,
+                    };
+
+                    json!({
+                        "assignment_id": a.assignment_id,
+                        "category_id": a.category_id,
+                        "standing": s,
+                        "below_kkm": below_kkm,
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            let scaled = course.grade.map(|g| gradebook.scale.scale(g));
+
+            json!({
+                "user_id": row.user_id,
+                "fullname": row.fullname,
+                "grade": course.grade,
+                "predicate": scaled.as_ref().and_then(|s| s.predicate.clone()),
+                "below_kkm": scaled.map_or(false, |s| s.below_kkm),
+                "categories": course.categories,
+                "assignments": assignments,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    Ok(Json(json!({
+        "scale": gradebook.scale,
+        "categories": gradebook.categories,
+        "grades": grades,
+    })))
+}
+
+#[get("/<class_id>/scale")]
+pub fn scale(
+    key: Can<ViewClass>,
+    class_id: &str,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    match GradingScale::load(&class_id.to_string(), &conn) {
+        Ok(s) => Ok(Json(json!({ "scale": s }))),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+#[patch("/<class_id>/scale", data = "<scale>")]
+pub fn update_scale(
+    key: Can<ManageGrading>,
+    class_id: &str,
+    scale: Json<GradingScale>,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let scale = scale.into_inner();
+
+    if !scale.is_valid() {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    match scale.save(&class_id.to_string(), &conn) {
+        Ok(s) => Ok(Json(json!({ "scale": s }))),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+/// Students who need remedial work: those below KKM overall, or on any
+/// marked assignment, or with work past due.
+#[get("/<class_id>/remedial")]
+pub fn remedial(
+    key: Can<ViewGradebook>,
+    class_id: &str,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let gradebook = match Gradebook::load(&class_id.to_string(), &conn) {
+        Ok(g) => g,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let now = Local::now().naive_local();
+
+    let students = gradebook
+        .rows
+        .iter()
+        .filter_map(|row| {
+            let grade = gradebook.course_grade(row, now).grade;
+            let below_kkm = grade.map_or(false, |g| !gradebook.scale.passes(g));
+            let assignments = gradebook.remedial(row, now);
+
+            if !below_kkm && assignments.is_empty() {
+                return None;
+            }
+
+            let assignments = assignments
+                .into_iter()
+                .map(|(a, s)| {
+                    json!({
+                        "assignment_id": a.assignment_id,
+                        "assignment_name": a.assignment_name,
+                        "standing": s,
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            Some(json!({
+                "user_id": row.user_id,
+                "fullname": row.fullname,
+                "grade": grade,
+                "below_kkm": below_kkm,
+                "assignments": assignments,
+            }))
+        })
+        .collect::<Vec<_>>();
+
+    Ok(Json(json!({
+        "kkm": gradebook.scale.kkm,
+        "students": students,
+    })))
+}
@@ -0,0 +1,126 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+
+use crate::assignments::models::Assignment;
+use crate::gradebook::models::GradeCategory;
+
+/// Where a student stands on one assignment. Scores are percentages of the
+/// assignment's `total_marks`, or of 100 when it has none.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "status", content = "score", rename_all = "lowercase")]
+pub enum Standing {
+    Graded(f64),
+    /// Past due and never handed in, counts as zero
+    Missing,
+    /// Left out of the grade by the teacher
+    Excused,
+    /// Handed in but unmarked, or not due yet
+    Pending,
+    /// Given to other students only
+    Unassigned,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CategoryGrade {
+    pub category_id: Option<String>,
+    pub name: String,
+    pub weight: i32,
+    pub average: Option<f64>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CourseGrade {
+    pub categories: Vec<CategoryGrade>,
+    pub grade: Option<f64>,
+}
+
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+/// A mark as a score out of 100.
+pub fn percentage(assignment: &Assignment, mark: i32) -> f64 {
+    let total = match assignment.total_marks {
+        Some(t) if t > 0 => t,
+        _ => 100,
+    };
+
+    mark as f64 * 100.0 / total as f64
+}
+
+pub fn standing(
+    assignment: &Assignment,
+    due: Option<NaiveDateTime>,
+    mark: Option<i32>,
+    submitted: bool,
+    excused: bool,
+    now: NaiveDateTime,
+) -> Standing {
+    if excused {
+        return Standing::Excused;
+    }
+
+    if let Some(m) = mark {
+        return Standing::Graded(percentage(assignment, m));
+    }
+
+    match due {
+        Some(due) if !submitted && now > due => Standing::Missing,
+        _ => Standing::Pending,
+    }
+}
+
+/// Averages each category, then weighs the categories into a course grade.
+/// Categories with nothing graded yet are left out and the remaining weights
+/// scaled up, so the running grade is always out of 100. A class without
+/// categories weighs every assignment equally; once it has some,
+/// uncategorized assignments don't count.
+pub fn course_grade(categories: &[GradeCategory], assignments: &[Assignment], standings: &[Standing]) -> CourseGrade {
+    let buckets = match categories.is_empty() {
+        true => vec![(None, "All work".to_string(), 100)],
+        false => categories
+            .iter()
+            .map(|c| (Some(c.id.clone()), c.name.clone(), c.weight))
+            .collect(),
+    };
+
+    let categories = buckets
+        .into_iter()
+        .map(|(category_id, name, weight)| {
+            let scores = assignments
+                .iter()
+                .zip(standings)
+                .filter(|(a, _)| category_id.is_none() || a.category_id == category_id)
+                .filter_map(|(_, s)| match s {
+                    Standing::Graded(score) => Some(*score),
+                    Standing::Missing => Some(0.0),
+                    Standing::Excused | Standing::Pending | Standing::Unassigned => None,
+                })
+                .collect::<Vec<f64>>();
+
+            let average = match scores.is_empty() {
+                true => None,
+                false => Some(scores.iter().sum::<f64>() / scores.len() as f64),
+            };
+
+            CategoryGrade {
+                category_id,
+                name,
+                weight,
+                average: average.map(round),
+            }
+        })
+        .collect::<Vec<CategoryGrade>>();
+
+    let (weighted, weights) = categories
+        .iter()
+        .filter_map(|c| c.average.map(|a| (a * c.weight as f64, c.weight as f64)))
+        .fold((0.0, 0.0), |(sum, total), (a, w)| (sum + a, total + w));
+
+    let grade = match weights > 0.0 {
+        true => Some(round(weighted / weights)),
+        false => None,
+    };
+
+    CourseGrade { categories, grade }
+}
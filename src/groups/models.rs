@@ -0,0 +1,155 @@
+use chrono::{Local, NaiveDateTime};
+use diesel::dsl::any;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::ThearningResult;
+use crate::schema::{assignment_targets, student_group_members, student_groups};
+use crate::utils::generate_random_id;
+
+/// A named set of students in a class that work can be assigned to.
+#[derive(Serialize, Deserialize, Queryable, Insertable, Clone)]
+#[table_name = "student_groups"]
+pub struct StudentGroup {
+    pub id: String,
+    pub class_id: String,
+    pub name: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, Queryable, Insertable, Clone)]
+#[table_name = "student_group_members"]
+pub struct GroupMember {
+    pub group_id: String,
+    pub user_id: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FillableGroup {
+    pub name: String,
+    #[serde(default)]
+    pub members: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct GroupResponse {
+    #[serde(flatten)]
+    pub group: StudentGroup,
+    pub members: Vec<String>,
+}
+
+impl StudentGroup {
+    pub fn find(id: &String, conn: &PgConnection) -> ThearningResult<Self> {
+        Ok(student_groups::table.find(id).get_result::<Self>(conn)?)
+    }
+
+    pub fn load(class_id: &String, conn: &PgConnection) -> ThearningResult<Vec<Self>> {
+        Ok(student_groups::table
+            .filter(student_groups::class_id.eq(class_id))
+            .order(student_groups::name.asc())
+            .load::<Self>(conn)?)
+    }
+
+    pub fn name_taken(class_id: &String, name: &String, except: Option<&String>, conn: &PgConnection) -> ThearningResult<bool> {
+        let found = student_groups::table
+            .filter(student_groups::class_id.eq(class_id))
+            .filter(student_groups::name.eq(name))
+            .select(student_groups::id)
+            .load::<String>(conn)?;
+
+        Ok(found.iter().any(|id| Some(id) != except))
+    }
+
+    pub fn create(class_id: &String, data: FillableGroup, conn: &PgConnection) -> ThearningResult<Self> {
+        let group = Self {
+            id: format!("{}{}", generate_random_id(), generate_random_id()),
+            class_id: class_id.clone(),
+            name: data.name.trim().to_string(),
+            created_at: Local::now().naive_local(),
+        };
+
+        let group = diesel::insert_into(student_groups::table)
+            .values(&group)
+            .get_result::<Self>(conn)?;
+
+        group.set_members(&data.members, conn)?;
+
+        Ok(group)
+    }
+
+    pub fn update(&self, data: FillableGroup, conn: &PgConnection) -> ThearningResult<Self> {
+        let group = diesel::update(student_groups::table.find(&self.id))
+            .set(student_groups::name.eq(data.name.trim()))
+            .get_result::<Self>(conn)?;
+
+        group.set_members(&data.members, conn)?;
+
+        Ok(group)
+    }
+
+    pub fn delete(&self, conn: &PgConnection) -> ThearningResult<usize> {
+        Ok(diesel::delete(student_groups::table.find(&self.id)).execute(conn)?)
+    }
+
+    fn set_members(&self, members: &[String], conn: &PgConnection) -> ThearningResult<()> {
+        diesel::delete(student_group_members::table.filter(student_group_members::group_id.eq(&self.id)))
+            .execute(conn)?;
+
+        let mut members = members.to_vec();
+        members.sort();
+        members.dedup();
+
+        let rows = members
+            .into_iter()
+            .map(|user_id| GroupMember {
+                group_id: self.id.clone(),
+                user_id,
+            })
+            .collect::<Vec<GroupMember>>();
+
+        if !rows.is_empty() {
+            diesel::insert_into(student_group_members::table)
+                .values(&rows)
+                .execute(conn)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn members(&self, conn: &PgConnection) -> ThearningResult<Vec<String>> {
+        Self::members_of(&[self.id.clone()], conn)
+    }
+
+    /// Everyone in any of the groups, each once.
+    pub fn members_of(group_ids: &[String], conn: &PgConnection) -> ThearningResult<Vec<String>> {
+        Ok(student_group_members::table
+            .filter(student_group_members::group_id.eq(any(group_ids)))
+            .select(student_group_members::user_id)
+            .distinct()
+            .order(student_group_members::user_id.asc())
+            .load::<String>(conn)?)
+    }
+
+    /// Who is in which of the groups, by group then student.
+    pub fn memberships(group_ids: &[String], conn: &PgConnection) -> ThearningResult<Vec<GroupMember>> {
+        Ok(student_group_members::table
+            .filter(student_group_members::group_id.eq(any(group_ids)))
+            .order((student_group_members::group_id.asc(), student_group_members::user_id.asc()))
+            .load::<GroupMember>(conn)?)
+    }
+
+    /// Assignments that are given to this group.
+    pub fn assignment_ids(&self, conn: &PgConnection) -> ThearningResult<Vec<String>> {
+        Ok(assignment_targets::table
+            .filter(assignment_targets::group_id.eq(&self.id))
+            .select(assignment_targets::assignment_id)
+            .load::<String>(conn)?)
+    }
+
+    pub fn response(self, conn: &PgConnection) -> ThearningResult<GroupResponse> {
+        let members = self.members(conn)?;
+
+        Ok(GroupResponse { group: self, members })
+    }
+}
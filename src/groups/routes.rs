@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+
+use diesel::prelude::*;
+use rocket::http::Status;
+use rocket::serde::json::serde_json::json;
+use rocket::serde::json::Json;
+use rocket_dyn_templates::handlebars::JsonValue;
+
+use crate::assignments::models::{Assignment, Retargeted};
+use crate::assignments::utils::shared_members;
+use crate::db;
+use crate::errors::ErrorKind;
+use crate::groups::models::{FillableGroup, GroupMember, StudentGroup};
+use crate::policy::{Can, UpdateClass, ViewSubmissions};
+use crate::traits::ClassUser;
+use crate::users::models::Student;
+
+fn class_group(class_id: &str, group_id: &str, conn: &PgConnection) -> Result<StudentGroup, Status> {
+    match StudentGroup::find(&group_id.to_string(), conn) {
+        Ok(g) if g.class_id == class_id => Ok(g),
+        _ => Err(Status::NotFound),
+    }
+}
+
+/// A group needs a name of its own in the class, and only students of the
+/// class can be in it.
+fn check_group(class_id: &str, group: &FillableGroup, except: Option<&String>, conn: &PgConnection) -> Result<(), Status> {
+    if group.name.trim().is_empty() {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let enrolled = match Student::load_in_class(&class_id.to_string(), conn) {
+        Ok(s) => s.into_iter().map(|s| s.user_id).collect::<Vec<String>>(),
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    if group.members.iter().any(|m| !enrolled.contains(m)) {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    match StudentGroup::name_taken(&class_id.to_string(), &group.name.trim().to_string(), except, conn) {
+        Ok(false) => Ok(()),
+        Ok(true) => Err(Status::Conflict),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+#[get("/<class_id>/groups")]
+pub fn groups(
+    key: Can<ViewSubmissions>,
+    class_id: &str,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let groups = StudentGroup::load(&class_id.to_string(), &conn)
+        .and_then(|gs| gs.into_iter().map(|g| g.response(&conn)).collect::<Result<Vec<_>, _>>());
+
+    match groups {
+        Ok(g) => Ok(Json(json!({ "groups": g }))),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+#[post("/<class_id>/groups", data = "<group>")]
+pub fn create_group(
+    key: Can<UpdateClass>,
+    class_id: &str,
+    group: Json<FillableGroup>,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let group = group.into_inner();
+
+    check_group(class_id, &group, None, &conn)?;
+
+    let created = conn.transaction::<_, ErrorKind, _>(|| {
+        StudentGroup::create(&class_id.to_string(), group, &conn)?.response(&conn)
+    });
+
+    match created {
+        Ok(g) => Ok(Json(json!({ "group": g }))),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+/// Nobody can join the group while they're in another team for group work
+/// given to it.
+fn check_teams(group: &StudentGroup, members: &[String], conn: &PgConnection) -> Result<(), Status> {
+    let ids = match group.assignment_ids(conn) {
+        Ok(ids) => ids,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    for assignment_id in ids {
+        let assignment = match Assignment::get_by_id(&assignment_id, conn) {
+            Ok(a) if a.group_work => a,
+            Ok(_) => continue,
+            Err(_) => return Err(Status::InternalServerError),
+        };
+
+        let teams = assignment
+            .targets(conn)
+            .and_then(|t| StudentGroup::memberships(&t.groups, conn));
+
+        let mut teams = match teams {
+            Ok(t) => t,
+            Err(_) => return Err(Status::InternalServerError),
+        };
+
+        teams.retain(|m| m.group_id != group.id);
+
+        teams.extend(members.iter().map(|user_id| GroupMember {
+            group_id: group.id.clone(),
+            user_id: user_id.clone(),
+        }));
+
+        if !shared_members(&teams).is_empty() {
+            return Err(Status::UnprocessableEntity);
+        }
+    }
+
+    Ok(())
+}
+
+/// Renames the group and replaces its members. Work given to the group
+/// follows its members: newcomers get a submission, and leavers lose theirs
+/// unless they've started on it.
+#[patch("/<class_id>/groups/<group_id>", data = "<group>")]
+pub fn update_group(
+    key: Can<UpdateClass>,
+    class_id: &str,
+    group_id: &str,
+    group: Json<FillableGroup>,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let existing = class_group(class_id, group_id, &conn)?;
+
+    let group = group.into_inner();
+
+    check_group(class_id, &group, Some(&existing.id), &conn)?;
+
+    check_teams(&existing, &group.members, &conn)?;
+
+    let updated = conn.transaction::<_, ErrorKind, _>(|| {
+        let updated = existing.update(group, &conn)?;
+
+        let mut retargeted = HashMap::<String, Retargeted>::new();
+
+        for assignment_id in updated.assignment_ids(&conn)? {
+            let assignment = Assignment::get_by_id(&assignment_id, &conn)?;
+
+            if !assignment.draft {
+                retargeted.insert(assignment_id, assignment.sync_submissions(&conn)?);
+            }
+        }
+
+        Ok((updated.response(&conn)?, retargeted))
+    });
+
+    match updated {
+        Ok((g, r)) => Ok(Json(json!({ "group": g, "retargeted": r }))),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+/// A group that work is still assigned to can't go.
+#[delete("/<class_id>/groups/<group_id>")]
+pub fn delete_group(
+    key: Can<UpdateClass>,
+    class_id: &str,
+    group_id: &str,
+    conn: db::DbConn,
+) -> Result<Status, Status> {
+    let group = class_group(class_id, group_id, &conn)?;
+
+    match group.assignment_ids(&conn) {
+        Ok(ids) if ids.is_empty() => {}
+        Ok(_) => return Err(Status::Conflict),
+        Err(_) => return Err(Status::InternalServerError),
+    }
+
+    match group.delete(&conn) {
+        Ok(_) => Ok(Status::Ok),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
@@ -0,0 +1,194 @@
+use std::env;
+
+use chrono::{Duration, Local, NaiveDateTime};
+use diesel;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::ThearningResult;
+use crate::schema::{account_locks, login_attempts};
+use crate::users::models::User;
+use crate::utils::generate_random_id;
+
+const MAX_BACKOFF_SECONDS: i64 = 60;
+
+#[derive(Serialize, Deserialize, Queryable, Insertable, Clone)]
+#[table_name = "login_attempts"]
+pub struct LoginAttempt {
+    pub id: String,
+    pub user_id: Option<String>,
+    pub ip: String,
+    pub success: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, Queryable, Insertable, AsChangeset, Associations, Clone)]
+#[belongs_to(User)]
+#[table_name = "account_locks"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct AccountLock {
+    pub user_id: String,
+    pub failed_attempts: i32,
+    pub last_failed_at: Option<NaiveDateTime>,
+    pub locked_until: Option<NaiveDateTime>,
+}
+
+/// Whether a login may be attempted right now.
+pub enum Throttle {
+    Open,
+    Backoff(i64),
+    Locked(NaiveDateTime),
+}
+
+fn env_number(name: &str, default: i64) -> i64 {
+    match env::var(name) {
+        Ok(v) => v.parse::<i64>().unwrap_or(default),
+        Err(_) => default,
+    }
+}
+
+/// Failed attempts in a row before an account gets locked, `LOGIN_LOCKOUT_THRESHOLD`.
+pub fn lockout_threshold() -> i64 {
+    env_number("LOGIN_LOCKOUT_THRESHOLD", 5)
+}
+
+/// How long a lockout lasts, `LOGIN_LOCKOUT_MINUTES`. Also the window failures
+/// per IP are counted in.
+pub fn lockout_minutes() -> i64 {
+    env_number("LOGIN_LOCKOUT_MINUTES", 15)
+}
+
+/// Failed attempts from one IP before it gets throttled, `LOGIN_IP_THRESHOLD`.
+pub fn ip_threshold() -> i64 {
+    env_number("LOGIN_IP_THRESHOLD", 20)
+}
+
+/// 1, 2, 4, 8... seconds after each failure, capped at a minute.
+pub fn backoff_seconds(failures: i64) -> i64 {
+    match failures {
+        f if f <= 0 => 0,
+        f if f > 6 => MAX_BACKOFF_SECONDS,
+        f => (1i64 << (f - 1)).min(MAX_BACKOFF_SECONDS),
+    }
+}
+
+fn remaining(since: NaiveDateTime, seconds: i64) -> Throttle {
+    let until = since + Duration::seconds(seconds);
+    let now = Local::now().naive_local();
+
+    if until > now {
+        Throttle::Backoff((until - now).num_seconds().max(1))
+    } else {
+        Throttle::Open
+    }
+}
+
+impl LoginAttempt {
+    pub fn record(
+        uid: Option<&String>,
+        ip: &str,
+        success: bool,
+        conn: &PgConnection,
+    ) -> ThearningResult<Self> {
+        let attempt = Self {
+            id: format!("{}{}", generate_random_id(), generate_random_id()),
+            user_id: uid.map(|u| u.to_string()),
+            ip: ip.to_string(),
+            success,
+            created_at: Local::now().naive_local(),
+        };
+
+        Ok(diesel::insert_into(login_attempts::table)
+            .values(&attempt)
+            .get_result::<Self>(conn)?)
+    }
+
+    /// Throttles an IP once it has failed too often within the lockout window.
+    pub fn ip_throttle(ip: &str, conn: &PgConnection) -> ThearningResult<Throttle> {
+        let since = Local::now().naive_local() - Duration::minutes(lockout_minutes());
+
+        let recent = login_attempts::table
+            .filter(login_attempts::ip.eq(ip))
+            .filter(login_attempts::success.eq(false))
+            .filter(login_attempts::created_at.gt(since));
+
+        let failures = recent.count().get_result::<i64>(conn)?;
+
+        let last = recent
+            .select(diesel::dsl::max(login_attempts::created_at))
+            .get_result::<Option<NaiveDateTime>>(conn)?;
+
+        let over = failures - ip_threshold() + 1;
+
+        match last {
+            Some(l) if over > 0 => Ok(remaining(l, backoff_seconds(over))),
+            _ => Ok(Throttle::Open),
+        }
+    }
+}
+
+impl AccountLock {
+    pub fn find(uid: &String, conn: &PgConnection) -> ThearningResult<Option<Self>> {
+        Ok(account_locks::table
+            .find(uid)
+            .get_result::<Self>(conn)
+            .optional()?)
+    }
+
+    pub fn throttle(&self) -> Throttle {
+        if let Some(until) = self.locked_until {
+            if until > Local::now().naive_local() {
+                return Throttle::Locked(until);
+            }
+        }
+
+        match self.last_failed_at {
+            Some(last) => remaining(last, backoff_seconds(self.failed_attempts as i64)),
+            None => Throttle::Open,
+        }
+    }
+
+    /// Counts a failed login. Returns the lock when this failure locked the account.
+    pub fn register_failure(uid: &String, conn: &PgConnection) -> ThearningResult<Option<Self>> {
+        let now = Local::now().naive_local();
+
+        let failed_attempts = match Self::find(uid, conn)? {
+            Some(l) => l.failed_attempts + 1,
+            None => 1,
+        };
+
+        let lock = if failed_attempts as i64 >= lockout_threshold() {
+            Self {
+                user_id: uid.to_string(),
+                failed_attempts: 0,
+                last_failed_at: None,
+                locked_until: Some(now + Duration::minutes(lockout_minutes())),
+            }
+        } else {
+            Self {
+                user_id: uid.to_string(),
+                failed_attempts,
+                last_failed_at: Some(now),
+                locked_until: None,
+            }
+        };
+
+        let res = diesel::insert_into(account_locks::table)
+            .values(&lock)
+            .on_conflict(account_locks::user_id)
+            .do_update()
+            .set(&lock)
+            .get_result::<Self>(conn)?;
+
+        match res.locked_until {
+            Some(_) => Ok(Some(res)),
+            None => Ok(None),
+        }
+    }
+
+    /// Clears failures and any lock, after a successful login or an admin unlock.
+    pub fn reset(uid: &String, conn: &PgConnection) -> ThearningResult<usize> {
+        Ok(diesel::delete(account_locks::table.find(uid)).execute(conn)?)
+    }
+}
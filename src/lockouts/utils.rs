@@ -0,0 +1,79 @@
+use std::net::IpAddr;
+
+use chrono::NaiveDateTime;
+use diesel::pg::PgConnection;
+
+use crate::audit::models::{AuditLog, FillableAuditLog};
+use crate::errors::ThearningResult;
+use crate::lockouts::models::{AccountLock, LoginAttempt};
+use crate::users::models::User;
+use crate::utils::send_mail;
+
+pub fn client_ip(ip: Option<IpAddr>) -> String {
+    match ip {
+        Some(i) => i.to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Records a failed login for the key. When it locks the account, the lockout
+/// goes to the audit log and the locked user and lock expiry are returned so
+/// they can be emailed.
+pub fn register_failed_login(
+    key: &String,
+    ip: &str,
+    conn: &PgConnection,
+) -> ThearningResult<Option<(User, NaiveDateTime)>> {
+    // Only existing accounts can be locked
+    let user = match User::find_user(key, conn) {
+        Ok(u) => u,
+        Err(_) => {
+            LoginAttempt::record(None, ip, false, conn)?;
+            return Ok(None);
+        }
+    };
+
+    LoginAttempt::record(Some(&user.user_id), ip, false, conn)?;
+
+    let lock = match AccountLock::register_failure(&user.user_id, conn)? {
+        Some(l) => l,
+        None => return Ok(None),
+    };
+
+    let until = lock.locked_until.unwrap();
+
+    AuditLog::create(
+        FillableAuditLog {
+            action: "account_locked",
+            user_id: Some(&user.user_id),
+            actor_id: None,
+            ip: Some(ip),
+            detail: Some(format!("Too many failed logins, locked until {}", until)),
+        },
+        conn,
+    )?;
+
+    Ok(Some((user, until)))
+}
+
+pub async fn send_lockout_mail(user: User, until: NaiveDateTime) {
+    let html = format!(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Account Locked</title>
+</head>
+<body>
+    <div style="display: block; align-items: center;">
+        <h2 style="font-family: Arial, Helvetica, sans-serif;">Hi {}, your account has been locked after too many failed logins.</h2>
+        <br>
+        <h4 style="font-family: Arial, Helvetica, sans-serif;">You can try again after {}. If this wasn't you, reset your password or contact an admin.</h4>
+    </div>
+</body>
+</html>"#, &user.fullname, until.format("%Y-%m-%d %H:%M"));
+
+    let emails = vec![user.email.clone()];
+
+    send_mail(user, emails, html, "Account Locked").await;
+}
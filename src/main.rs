@@ -18,10 +18,13 @@ use rocket_cors::{AllowedHeaders, AllowedOrigins, Cors, CorsOptions};
 use crate::db::database_url;
 use assignments::routes as assignment_routes;
 use attachments::routes as att_routes;
+use audit::routes as audit_routes;
 use classes::routes as class_routes;
 use errors::mount as error_routes;
 use files::routes as file_routes;
 use links::routes as link_routes;
+use twofactor::routes as twofactor_routes;
+use uploads::routes as upload_routes;
 use users::routes as user_routes;
 
 mod classes;
@@ -29,17 +32,29 @@ mod users;
 
 mod assignments;
 mod attachments;
+mod attempts;
+mod audit;
 pub mod auth;
 mod comments;
 pub mod db;
 mod errors;
 mod files;
+mod gradebook;
+mod groups;
 mod links;
+mod lockouts;
 mod pagination;
+mod peer_reviews;
+mod policy;
+mod rubrics;
 pub mod schema;
+mod sessions;
+mod storage;
 mod submissions;
 mod tests;
 mod traits;
+mod twofactor;
+mod uploads;
 mod utils;
 mod announcements;
 
@@ -92,11 +107,14 @@ fn rocket() -> rocket::Rocket<rocket::Build> {
         .manage(db::init_pool())
         .mount("/", routes![handle_cors]);
     rocket = user_routes::mount(rocket);
+    rocket = twofactor_routes::mount(rocket);
     rocket = class_routes::mount(rocket);
     // rocket = assignment_routes::mount(rocket);
     rocket = file_routes::mount(rocket);
+    rocket = upload_routes::mount(rocket);
     rocket = link_routes::mount(rocket);
     rocket = error_routes(rocket).attach(make_cors());
     rocket = att_routes::mount(rocket);
+    rocket = audit_routes::mount(rocket);
     rocket
 }
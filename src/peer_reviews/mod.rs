@@ -0,0 +1,3 @@
+pub mod models;
+pub mod routes;
+pub(crate) mod utils;
@@ -0,0 +1,261 @@
+use chrono::{Local, NaiveDateTime};
+use diesel::dsl::any;
+use diesel::prelude::*;
+use diesel::result::Error;
+use diesel::PgConnection;
+use serde::{Deserialize, Serialize};
+
+use crate::comments::models::{FillablePrivateComment, PrivateComment};
+use crate::errors::{ErrorKind, ThearningResult};
+use crate::rubrics::models::{CriterionFeedback, CriterionScore, FillableCriterionScore, Rubric};
+use crate::schema::{peer_review_scores, peer_reviews, submissions};
+use crate::submissions::models::{Mark, Submissions};
+use crate::traits::Manipulable;
+use crate::utils::generate_random_id;
+
+/// One student's review of another's work. `score` stays empty until the
+/// reviewer hands the review in.
+#[derive(Serialize, Deserialize, Queryable, Insertable, Clone)]
+#[table_name = "peer_reviews"]
+pub struct PeerReview {
+    pub id: String,
+    pub submission_id: String,
+    pub reviewer_id: String,
+    pub score: Option<i32>,
+    pub comment_id: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub reviewed_at: Option<NaiveDateTime>,
+}
+
+/// The level a reviewer picked for one criterion.
+#[derive(Serialize, Deserialize, Queryable, Insertable, Clone)]
+#[table_name = "peer_review_scores"]
+pub struct PeerReviewScore {
+    pub id: String,
+    pub review_id: String,
+    pub criterion_id: String,
+    pub level_id: String,
+    pub points: i32,
+    pub comment: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FillableReview {
+    /// Ignored when the review comes with rubric scores, their total is used
+    #[serde(default)]
+    pub value: i32,
+    #[serde(default)]
+    pub rubric: Vec<FillableCriterionScore>,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+/// What the student whose work was reviewed gets to see, which doesn't
+/// include who reviewed it.
+#[derive(Serialize, Deserialize)]
+pub struct AnonymousReview {
+    pub score: i32,
+    pub rubric_feedback: Vec<CriterionFeedback>,
+    pub comment: Option<String>,
+    pub reviewed_at: Option<NaiveDateTime>,
+}
+
+impl PeerReviewScore {
+    /// Lets the rubric lay the scores out the way it does a mark's.
+    fn as_criterion_score(&self) -> CriterionScore {
+        CriterionScore {
+            id: self.id.clone(),
+            mark_id: self.review_id.clone(),
+            criterion_id: self.criterion_id.clone(),
+            level_id: self.level_id.clone(),
+            points: self.points,
+            comment: self.comment.clone(),
+        }
+    }
+}
+
+impl PeerReview {
+    pub fn find(id: &String, conn: &PgConnection) -> ThearningResult<Self> {
+        Ok(peer_reviews::table.find(id).get_result::<Self>(conn)?)
+    }
+
+    pub fn load_by_assignment(assignment_id: &String, conn: &PgConnection) -> ThearningResult<Vec<Self>> {
+        Ok(peer_reviews::table
+            .inner_join(submissions::table)
+            .filter(submissions::assignment_id.eq(assignment_id))
+            .select(peer_reviews::all_columns)
+            .order(peer_reviews::created_at.asc())
+            .load::<Self>(conn)?)
+    }
+
+    /// The reviews handed to the student, with the assignment each is for.
+    pub fn load_by_reviewer(reviewer_id: &String, conn: &PgConnection) -> ThearningResult<Vec<(Self, String)>> {
+        Ok(peer_reviews::table
+            .inner_join(submissions::table)
+            .filter(peer_reviews::reviewer_id.eq(reviewer_id))
+            .select((peer_reviews::all_columns, submissions::assignment_id))
+            .order(peer_reviews::created_at.asc())
+            .load::<(Self, String)>(conn)?)
+    }
+
+    /// Reviews handed in on any of the submissions.
+    pub fn load_done(submission_ids: &[String], conn: &PgConnection) -> ThearningResult<Vec<Self>> {
+        Ok(peer_reviews::table
+            .filter(peer_reviews::submission_id.eq(any(submission_ids)))
+            .filter(peer_reviews::score.is_not_null())
+            .order(peer_reviews::reviewed_at.asc())
+            .load::<Self>(conn)?)
+    }
+
+    pub fn exists_for(assignment_id: &String, conn: &PgConnection) -> ThearningResult<bool> {
+        let count = peer_reviews::table
+            .inner_join(submissions::table)
+            .filter(submissions::assignment_id.eq(assignment_id))
+            .count()
+            .get_result::<i64>(conn)?;
+
+        Ok(count > 0)
+    }
+
+    /// The average score reviewers gave the work, none before any review is
+    /// in. Team work is reviewed once for the whole team, so pass every
+    /// member's submission.
+    pub fn average(submission_ids: &[String], conn: &PgConnection) -> ThearningResult<Option<f64>> {
+        let scores = Self::load_done(submission_ids, conn)?
+            .into_iter()
+            .filter_map(|r| r.score)
+            .collect::<Vec<i32>>();
+
+        match scores.is_empty() {
+            true => Ok(None),
+            false => Ok(Some(scores.iter().sum::<i32>() as f64 / scores.len() as f64)),
+        }
+    }
+
+    /// Opens a review for every `(submission_id, reviewer)` pair.
+    pub fn allocate(pairs: &[(String, String)], conn: &PgConnection) -> ThearningResult<Vec<Self>> {
+        let now = Local::now().naive_local();
+
+        let rows = pairs
+            .iter()
+            .map(|(submission_id, reviewer_id)| Self {
+                id: format!("{}{}", generate_random_id(), generate_random_id()),
+                submission_id: submission_id.clone(),
+                reviewer_id: reviewer_id.clone(),
+                score: None,
+                comment_id: None,
+                created_at: now,
+                reviewed_at: None,
+            })
+            .collect::<Vec<Self>>();
+
+        if rows.is_empty() {
+            return Ok(rows);
+        }
+
+        Ok(diesel::insert_into(peer_reviews::table)
+            .values(&rows)
+            .get_results::<Self>(conn)?)
+    }
+
+    pub fn scores(&self, conn: &PgConnection) -> ThearningResult<Vec<PeerReviewScore>> {
+        Ok(peer_review_scores::table
+            .filter(peer_review_scores::review_id.eq(&self.id))
+            .load::<PeerReviewScore>(conn)?)
+    }
+
+    /// Saves the reviewer's score, rubric levels and comment, replacing any
+    /// they gave before. The comment goes on the reviewed submission as a
+    /// private comment. A mark already given to the work is worked out again
+    /// with the new average.
+    pub fn hand_in(
+        &self,
+        review: FillableReview,
+        score: i32,
+        rubric: &Rubric,
+        conn: &PgConnection,
+    ) -> ThearningResult<Self> {
+        diesel::delete(peer_review_scores::table.filter(peer_review_scores::review_id.eq(&self.id)))
+            .execute(conn)?;
+
+        let rows = review
+            .rubric
+            .iter()
+            .map(|s| PeerReviewScore {
+                id: format!("{}{}", generate_random_id(), generate_random_id()),
+                review_id: self.id.clone(),
+                criterion_id: s.criterion_id.clone(),
+                level_id: s.level_id.clone(),
+                points: rubric
+                    .level(&s.criterion_id, &s.level_id)
+                    .map(|l| l.points)
+                    .unwrap_or(0),
+                comment: s.comment.clone(),
+            })
+            .collect::<Vec<PeerReviewScore>>();
+
+        if !rows.is_empty() {
+            diesel::insert_into(peer_review_scores::table)
+                .values(&rows)
+                .execute(conn)?;
+        }
+
+        if let Some(id) = &self.comment_id {
+            PrivateComment::find_comment(id, conn)?.delete(conn)?;
+        }
+
+        let comment = match review.comment.filter(|c| !c.trim().is_empty()) {
+            Some(body) => Some(PrivateComment::create(
+                FillablePrivateComment {
+                    user_id: Some(self.reviewer_id.clone()),
+                    submission_id: Some(self.submission_id.clone()),
+                    body,
+                },
+                conn,
+            )?),
+            None => None,
+        };
+
+        let reviewed = diesel::update(peer_reviews::table.find(&self.id))
+            .set((
+                peer_reviews::score.eq(score),
+                peer_reviews::comment_id.eq(comment.map(|c| c.id)),
+                peer_reviews::reviewed_at.eq(Local::now().naive_local()),
+            ))
+            .get_result::<Self>(conn)?;
+
+        let submission = Submissions::find_submission(&self.submission_id, conn)?;
+
+        for member in submission.team(conn)? {
+            match Mark::get_by_submission_id(&member.submission_id, conn) {
+                Ok(mark) => {
+                    member.mark(&mark.value, conn)?;
+                }
+                Err(ErrorKind::QueryError(Error::NotFound)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(reviewed)
+    }
+
+    pub fn anonymous(&self, rubric: &Rubric, conn: &PgConnection) -> ThearningResult<AnonymousReview> {
+        let scores = self
+            .scores(conn)?
+            .iter()
+            .map(|s| s.as_criterion_score())
+            .collect::<Vec<CriterionScore>>();
+
+        let comment = match &self.comment_id {
+            Some(id) => Some(PrivateComment::find_comment(id, conn)?.body),
+            None => None,
+        };
+
+        Ok(AnonymousReview {
+            score: self.score.unwrap_or(0),
+            rubric_feedback: rubric.feedback(&scores),
+            comment,
+            reviewed_at: self.reviewed_at,
+        })
+    }
+}
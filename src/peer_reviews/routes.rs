@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+
+use chrono::Local;
+use diesel::dsl::any;
+use diesel::prelude::*;
+use rand::seq::SliceRandom;
+use rocket::http::Status;
+use rocket::serde::json::serde_json::json;
+use rocket::serde::json::Json;
+use rocket_dyn_templates::handlebars::JsonValue;
+
+use crate::assignments::models::Assignment;
+use crate::attachments::models::Attachment;
+use crate::db;
+use crate::errors::ErrorKind;
+use crate::files::models::UploadedFile;
+use crate::links::models::Link;
+use crate::peer_reviews::models::{FillableReview, PeerReview};
+use crate::peer_reviews::utils::{allocate_reviews, Work};
+use crate::policy::{published_assignment, Can, CreateAssignment, ReviewPeers, ViewSubmissions};
+use crate::rubrics::models::Rubric;
+use crate::schema::attachments;
+use crate::submissions::models::Submissions;
+
+/// The work handed in for an assignment, a team's counted once.
+fn handed_in(assignment: &Assignment, conn: &PgConnection) -> Result<Vec<Work>, ErrorKind> {
+    let mut work = Vec::<Work>::new();
+    let mut teams = HashMap::<String, usize>::new();
+
+    for s in Submissions::load_by_assignment(&assignment.assignment_id, conn)? {
+        if !s.submitted {
+            continue;
+        }
+
+        match s.group_id.as_ref().and_then(|g| teams.get(g)) {
+            Some(i) => work[*i].authors.push(s.user_id),
+            None => {
+                if let Some(g) = &s.group_id {
+                    teams.insert(g.clone(), work.len());
+                }
+
+                work.push(Work {
+                    submission_id: s.submission_id,
+                    authors: vec![s.user_id],
+                });
+            }
+        }
+    }
+
+    Ok(work)
+}
+
+/// Hands out the work handed in to reviewers once the assignment is due.
+/// Reviews only go out once, and only to students who handed in.
+#[post("/<class_id>/assignments/<assignment_id>/peer_reviews")]
+pub fn assign_peer_reviews(
+    key: Can<CreateAssignment>,
+    class_id: &str,
+    assignment_id: &str,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let assignment = published_assignment(class_id, assignment_id, &conn)?;
+
+    let reviewers = match assignment.peer_reviewers {
+        Some(n) => n as usize,
+        None => return Err(Status::UnprocessableEntity),
+    };
+
+    match assignment.due_at() {
+        Some(due) if Local::now().naive_local() >= due => {}
+        _ => return Err(Status::Conflict),
+    }
+
+    match PeerReview::exists_for(&assignment.assignment_id, &conn) {
+        Ok(false) => {}
+        Ok(true) => return Err(Status::Conflict),
+        Err(_) => return Err(Status::InternalServerError),
+    }
+
+    let allocated = conn.transaction::<_, ErrorKind, _>(|| {
+        let mut work = handed_in(&assignment, &conn)?;
+
+        // Who reviews whom shouldn't follow from the order work came in
+        work.shuffle(&mut rand::thread_rng());
+
+        PeerReview::allocate(&allocate_reviews(&work, reviewers), &conn)
+    });
+
+    match allocated {
+        Ok(r) => Ok(Json(json!({ "peer_reviews": r }))),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+/// Every review of the assignment's work with its reviewer, and the average
+/// each piece of work got.
+#[get("/<class_id>/assignments/teachers/<assignment_id>/peer_reviews")]
+pub fn peer_reviews(
+    key: Can<ViewSubmissions>,
+    class_id: &str,
+    assignment_id: &str,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let assignment = published_assignment(class_id, assignment_id, &conn)?;
+
+    let reviews = match PeerReview::load_by_assignment(&assignment.assignment_id, &conn) {
+        Ok(r) => r,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let mut averages = HashMap::<String, Option<f64>>::new();
+
+    for r in &reviews {
+        if !averages.contains_key(&r.submission_id) {
+            match PeerReview::average(&[r.submission_id.clone()], &conn) {
+                Ok(a) => averages.insert(r.submission_id.clone(), a),
+                Err(_) => return Err(Status::InternalServerError),
+            };
+        }
+    }
+
+    Ok(Json(json!({
+        "peer_reviews": reviews,
+        "averages": averages,
+        "peer_weight": assignment.peer_weight,
+    })))
+}
+
+/// A review as its reviewer sees it: the work without the uploaders of its
+/// attachments, and the rubric to score it on.
+fn review_task(review: &PeerReview, assignment: &Assignment, conn: &PgConnection) -> Result<JsonValue, ErrorKind> {
+    let submission = Submissions::find_submission(&review.submission_id, conn)?;
+
+    let work = attachments::table
+        .filter(attachments::submission_id.eq(any(submission.shared_ids(conn)?)))
+        .load::<Attachment>(conn)?
+        .into_iter()
+        .map(|a| {
+            let file = a.file_id.and_then(|id| UploadedFile::receive(&id, conn).ok());
+            let link = a.link_id.and_then(|id| Link::receive(&id, conn).ok());
+
+            json!({ "file": file, "link": link })
+        })
+        .collect::<Vec<JsonValue>>();
+
+    Ok(json!({
+        "id": review.id,
+        "assignment": assignment,
+        "attachments": work,
+        "rubric": Rubric::load(&assignment.assignment_id, conn)?,
+        "score": review.score,
+        "scores": review.scores(conn)?,
+        "reviewed_at": review.reviewed_at,
+    }))
+}
+
+/// The work handed to the student to review in this class. Nothing in it
+/// says whose work it is.
+#[get("/<class_id>/peer_reviews")]
+pub fn my_peer_reviews(
+    key: Can<ReviewPeers>,
+    class_id: &str,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let reviews = match PeerReview::load_by_reviewer(&key.0, &conn) {
+        Ok(r) => r,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let mut res = Vec::new();
+
+    for (review, assignment_id) in reviews {
+        let assignment = match published_assignment(class_id, &assignment_id, &conn) {
+            Ok(a) => a,
+            Err(_) => continue,
+        };
+
+        match review_task(&review, &assignment, &conn) {
+            Ok(t) => res.push(t),
+            Err(_) => return Err(Status::InternalServerError),
+        }
+    }
+
+    Ok(Json(json!({ "peer_reviews": res })))
+}
+
+/// Hands in a review, or changes one handed in before. With rubric scores
+/// the score is their total, otherwise `value`, which can't go over the
+/// assignment's total.
+#[patch("/<class_id>/peer_reviews/<review_id>", data = "<review>")]
+pub fn review_peer(
+    key: Can<ReviewPeers>,
+    class_id: &str,
+    review_id: &str,
+    review: Json<FillableReview>,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let existing = match PeerReview::find(&review_id.to_string(), &conn) {
+        Ok(r) if r.reviewer_id == key.0 => r,
+        _ => return Err(Status::NotFound),
+    };
+
+    let submission = match Submissions::find_submission(&existing.submission_id, &conn) {
+        Ok(s) => s,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let assignment = published_assignment(class_id, &submission.assignment_id, &conn)?;
+
+    let review = review.into_inner();
+
+    let rubric = match Rubric::load(&assignment.assignment_id, &conn) {
+        Ok(r) => r,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let score = match review.rubric.is_empty() {
+        true => review.value,
+        false => match rubric.total(&review.rubric) {
+            Some(total) => total,
+            None => return Err(Status::UnprocessableEntity),
+        },
+    };
+
+    if score < 0 || matches!(assignment.total_marks, Some(t) if score > t) {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    match conn.transaction::<_, ErrorKind, _>(|| existing.hand_in(review, score, &rubric, &conn)) {
+        Ok(r) => Ok(Json(json!({
+            "id": r.id,
+            "score": r.score,
+            "reviewed_at": r.reviewed_at,
+        }))),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
@@ -0,0 +1,42 @@
+/// A piece of work up for review and the students who did it, one of them
+/// unless it's team work.
+pub struct Work {
+    pub submission_id: String,
+    pub authors: Vec<String>,
+}
+
+/// Hands each piece of work to up to `reviewers` students who did other
+/// work, as `(submission_id, reviewer)` pairs. Reviewers are picked going
+/// round from the authors after this work's, so everyone reviews about as
+/// much as they're reviewed. Shuffle `work` beforehand to keep it anonymous.
+pub fn allocate_reviews(work: &[Work], reviewers: usize) -> Vec<(String, String)> {
+    let pool = work.iter().flat_map(|w| w.authors.iter()).collect::<Vec<&String>>();
+
+    let mut pairs = Vec::new();
+    let mut next = 0;
+
+    for w in work {
+        next += w.authors.len();
+
+        let picked = (0..pool.len())
+            .map(|k| pool[(next + k) % pool.len()])
+            .filter(|r| !w.authors.contains(r))
+            .take(reviewers)
+            .map(|r| (w.submission_id.clone(), r.clone()));
+
+        pairs.extend(picked);
+    }
+
+    pairs
+}
+
+/// The teacher's mark with `weight` percent of it replaced by the average
+/// the reviewers gave. Without reviews the teacher's mark stands.
+pub fn blend(teacher: i32, peer: Option<f64>, weight: i32) -> i32 {
+    let weight = weight.clamp(0, 100) as f64;
+
+    match peer {
+        Some(p) if weight > 0.0 => ((teacher as f64 * (100.0 - weight) + p * weight) / 100.0).round() as i32,
+        _ => teacher,
+    }
+}
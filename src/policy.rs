@@ -0,0 +1,140 @@
+use std::marker::PhantomData;
+
+use diesel::PgConnection;
+use rocket::http::Status;
+use rocket::outcome::try_outcome;
+use rocket::request::{self, FromRequest, Request};
+
+use crate::assignments::models::Assignment;
+use crate::auth::ClassGuard;
+use crate::errors::ErrorKind;
+use crate::submissions::models::Submissions;
+use crate::users::models::Role;
+
+/// Something a user may do inside a class, granted to a fixed set of class roles.
+pub trait Permission: Send + Sync + 'static {
+    const ROLES: &'static [Role];
+
+    fn granted(role: Role) -> bool {
+        Self::ROLES.contains(&role)
+    }
+}
+
+macro_rules! permission {
+    ($name:ident => [$($role:ident),*]) => {
+        pub struct $name;
+
+        impl Permission for $name {
+            const ROLES: &'static [Role] = &[$(Role::$role),*];
+        }
+    };
+}
+
+permission!(ViewClass => [Student, Teacher, Admin]);
+permission!(UpdateClass => [Teacher, Admin]);
+permission!(CreateTopic => [Teacher, Admin]);
+permission!(CreateAssignment => [Teacher, Admin]);
+permission!(DeleteAssignment => [Teacher, Admin]);
+permission!(ViewSubmissions => [Teacher, Admin]);
+permission!(SubmitAssignment => [Student]);
+permission!(ReviewPeers => [Student]);
+permission!(GradeSubmission => [Teacher, Admin]);
+permission!(GrantExtension => [Teacher, Admin]);
+permission!(ViewGradebook => [Teacher, Admin]);
+permission!(ManageGrading => [Teacher, Admin]);
+permission!(PostComment => [Student, Teacher, Admin]);
+permission!(CreateAnnouncement => [Teacher, Admin]);
+permission!(DeleteAnnouncement => [Teacher, Admin]);
+
+/// A class member holding permission `P` in the class of the route's first
+/// parameter. Members without it get a 403, outsiders fail in `ClassGuard`.
+pub struct Can<P: Permission>(pub String, pub Role, PhantomData<P>);
+
+#[rocket::async_trait]
+impl<'r, P: Permission> FromRequest<'r> for Can<P> {
+    type Error = ErrorKind;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ErrorKind> {
+        let member = try_outcome!(request.guard::<ClassGuard>().await);
+
+        match P::granted(member.1) {
+            true => request::Outcome::Success(Self(member.0, member.1, PhantomData)),
+            false => request::Outcome::Failure((Status::Forbidden, ErrorKind::InvalidValue)),
+        }
+    }
+}
+
+/// Drafts can be thrown away by any teacher, but a published assignment only
+/// by whoever created it or a class admin.
+pub fn can_delete_assignment(uid: &String, role: Role, assignment: &Assignment) -> bool {
+    match role {
+        Role::Admin => true,
+        Role::Teacher => assignment.draft || assignment.creator.as_ref() == Some(uid),
+        Role::Student => false,
+    }
+}
+
+/// The assignment, as long as it's in the class `Can` was checked against.
+/// Drafts don't get their class until they're published, so a draft only
+/// goes to whoever started it.
+pub fn class_assignment(
+    uid: &String,
+    class_id: &str,
+    assignment_id: &str,
+    conn: &PgConnection,
+) -> Result<Assignment, Status> {
+    match Assignment::get_by_id(&assignment_id.to_string(), conn) {
+        Ok(a) if a.class_id.as_deref() == Some(class_id) => Ok(a),
+        Ok(a) if a.class_id.is_none() && a.creator.as_ref() == Some(uid) => Ok(a),
+        _ => Err(Status::NotFound),
+    }
+}
+
+/// The assignment, as long as it's published in the class `Can` was checked
+/// against.
+pub fn published_assignment(class_id: &str, assignment_id: &str, conn: &PgConnection) -> Result<Assignment, Status> {
+    match Assignment::get_by_id(&assignment_id.to_string(), conn) {
+        Ok(a) if !a.draft && a.class_id.as_deref() == Some(class_id) => Ok(a),
+        _ => Err(Status::NotFound),
+    }
+}
+
+/// The submission and its assignment, as long as the assignment is in the
+/// class `Can` was checked against.
+pub fn class_submission(
+    class_id: &str,
+    submission_id: &str,
+    conn: &PgConnection,
+) -> Result<(Submissions, Assignment), Status> {
+    let submission = match Submissions::find_submission(&submission_id.to_string(), conn) {
+        Ok(s) => s,
+        Err(_) => return Err(Status::NotFound),
+    };
+
+    match Assignment::get_by_id(&submission.assignment_id, conn) {
+        Ok(a) if a.class_id.as_deref() == Some(class_id) => Ok((submission, a)),
+        _ => Err(Status::NotFound),
+    }
+}
+
+/// The submission and its assignment as [`class_submission`] finds them.
+/// Students only get to their own work, or their team's.
+pub fn member_submission(
+    uid: &String,
+    role: Role,
+    class_id: &str,
+    submission_id: &str,
+    conn: &PgConnection,
+) -> Result<(Submissions, Assignment), Status> {
+    let (submission, assignment) = class_submission(class_id, submission_id, conn)?;
+
+    if role != Role::Student || &submission.user_id == uid {
+        return Ok((submission, assignment));
+    }
+
+    match submission.team(conn) {
+        Ok(team) if team.iter().any(|s| &s.user_id == uid) => Ok((submission, assignment)),
+        Ok(_) => Err(Status::Forbidden),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
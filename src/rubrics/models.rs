@@ -0,0 +1,301 @@
+use std::collections::HashSet;
+
+use diesel::dsl::any;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{ErrorKind, ThearningResult};
+use crate::schema::{criterion_scores, rubric_criteria, rubric_levels};
+use crate::utils::generate_random_id;
+
+#[derive(Serialize, Deserialize, Queryable, Insertable, Clone)]
+#[table_name = "rubric_criteria"]
+pub struct Criterion {
+    pub id: String,
+    pub assignment_id: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub position: i32,
+}
+
+#[derive(Serialize, Deserialize, Queryable, Insertable, Clone)]
+#[table_name = "rubric_levels"]
+pub struct Level {
+    pub id: String,
+    pub criterion_id: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub points: i32,
+}
+
+/// The level a marker picked for one criterion of a mark.
+#[derive(Serialize, Deserialize, Queryable, Insertable, Clone)]
+#[table_name = "criterion_scores"]
+pub struct CriterionScore {
+    pub id: String,
+    pub mark_id: String,
+    pub criterion_id: String,
+    pub level_id: String,
+    pub points: i32,
+    pub comment: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FillableLevel {
+    pub title: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub points: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FillableCriterion {
+    pub title: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub levels: Vec<FillableLevel>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FillableRubric {
+    pub criteria: Vec<FillableCriterion>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FillableCriterionScore {
+    pub criterion_id: String,
+    pub level_id: String,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RubricCriterion {
+    #[serde(flatten)]
+    pub criterion: Criterion,
+    pub levels: Vec<Level>,
+}
+
+/// What a student sees of one scored criterion.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CriterionFeedback {
+    pub criterion_id: String,
+    pub criterion: String,
+    pub level: String,
+    pub level_description: Option<String>,
+    pub points: i32,
+    pub max_points: i32,
+    pub comment: Option<String>,
+}
+
+/// An assignment's criteria, in order, each with its levels from the most
+/// points down. An assignment without criteria has no rubric.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Rubric {
+    pub criteria: Vec<RubricCriterion>,
+}
+
+impl FillableRubric {
+    pub fn is_valid(&self) -> bool {
+        !self.criteria.is_empty()
+            && self.criteria.iter().all(|c| {
+                !c.title.trim().is_empty()
+                    && !c.levels.is_empty()
+                    && c.levels.iter().all(|l| !l.title.trim().is_empty() && l.points >= 0)
+            })
+    }
+}
+
+impl Rubric {
+    pub fn load(assignment_id: &String, conn: &PgConnection) -> ThearningResult<Self> {
+        let criteria = rubric_criteria::table
+            .filter(rubric_criteria::assignment_id.eq(assignment_id))
+            .order(rubric_criteria::position.asc())
+            .load::<Criterion>(conn)?;
+
+        let ids = criteria.iter().map(|c| c.id.clone()).collect::<Vec<String>>();
+
+        let levels = rubric_levels::table
+            .filter(rubric_levels::criterion_id.eq(any(&ids)))
+            .order(rubric_levels::points.desc())
+            .load::<Level>(conn)?;
+
+        let criteria = criteria
+            .into_iter()
+            .map(|criterion| RubricCriterion {
+                levels: levels
+                    .iter()
+                    .filter(|l| l.criterion_id == criterion.id)
+                    .cloned()
+                    .collect(),
+                criterion,
+            })
+            .collect();
+
+        Ok(Self { criteria })
+    }
+
+    pub fn delete(assignment_id: &String, conn: &PgConnection) -> ThearningResult<usize> {
+        Ok(diesel::delete(rubric_criteria::table.filter(rubric_criteria::assignment_id.eq(assignment_id)))
+            .execute(conn)?)
+    }
+
+    /// Replaces the assignment's rubric.
+    pub fn save(assignment_id: &String, rubric: FillableRubric, conn: &PgConnection) -> ThearningResult<Self> {
+        conn.transaction::<_, ErrorKind, _>(|| {
+            Self::delete(assignment_id, conn)?;
+
+            for (position, fillable) in rubric.criteria.into_iter().enumerate() {
+                let criterion = Criterion {
+                    id: format!("{}{}", generate_random_id(), generate_random_id()),
+                    assignment_id: assignment_id.to_string(),
+                    title: fillable.title,
+                    description: fillable.description,
+                    position: position as i32,
+                };
+
+                diesel::insert_into(rubric_criteria::table)
+                    .values(&criterion)
+                    .execute(conn)?;
+
+                let levels = fillable
+                    .levels
+                    .into_iter()
+                    .map(|l| Level {
+                        id: format!("{}{}", generate_random_id(), generate_random_id()),
+                        criterion_id: criterion.id.clone(),
+                        title: l.title,
+                        description: l.description,
+                        points: l.points,
+                    })
+                    .collect::<Vec<Level>>();
+
+                diesel::insert_into(rubric_levels::table)
+                    .values(&levels)
+                    .execute(conn)?;
+            }
+
+            Self::load(assignment_id, conn)
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.criteria.is_empty()
+    }
+
+    /// The best possible total, the top level of every criterion.
+    pub fn max_points(&self) -> i32 {
+        self.criteria
+            .iter()
+            .map(|c| c.levels.iter().map(|l| l.points).max().unwrap_or(0))
+            .sum()
+    }
+
+    pub fn level(&self, criterion_id: &str, level_id: &str) -> Option<&Level> {
+        self.criteria
+            .iter()
+            .find(|c| c.criterion.id == criterion_id)?
+            .levels
+            .iter()
+            .find(|l| l.id == level_id)
+    }
+
+    /// The scores of a mark laid out in the rubric's order.
+    pub fn feedback(&self, scores: &[CriterionScore]) -> Vec<CriterionFeedback> {
+        self.criteria
+            .iter()
+            .filter_map(|c| {
+                let score = scores.iter().find(|s| s.criterion_id == c.criterion.id)?;
+                let level = c.levels.iter().find(|l| l.id == score.level_id)?;
+
+                Some(CriterionFeedback {
+                    criterion_id: c.criterion.id.clone(),
+                    criterion: c.criterion.title.clone(),
+                    level: level.title.clone(),
+                    level_description: level.description.clone(),
+                    points: score.points,
+                    max_points: c.levels.iter().map(|l| l.points).max().unwrap_or(0),
+                    comment: score.comment.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// The total of the picked levels, as long as every criterion got
+    /// exactly one level of its own.
+    pub fn total(&self, scores: &[FillableCriterionScore]) -> Option<i32> {
+        let scored = scores
+            .iter()
+            .map(|s| s.criterion_id.as_str())
+            .collect::<HashSet<&str>>();
+
+        if scored.len() != scores.len() || scores.len() != self.criteria.len() {
+            return None;
+        }
+
+        scores
+            .iter()
+            .map(|s| self.level(&s.criterion_id, &s.level_id).map(|l| l.points))
+            .sum()
+    }
+}
+
+impl CriterionScore {
+    pub fn load_by_mark(mark_id: &String, conn: &PgConnection) -> ThearningResult<Vec<Self>> {
+        Ok(criterion_scores::table
+            .filter(criterion_scores::mark_id.eq(mark_id))
+            .load::<Self>(conn)?)
+    }
+
+    pub fn exists_for(assignment_id: &String, conn: &PgConnection) -> ThearningResult<bool> {
+        let count = criterion_scores::table
+            .inner_join(rubric_criteria::table)
+            .filter(rubric_criteria::assignment_id.eq(assignment_id))
+            .count()
+            .get_result::<i64>(conn)?;
+
+        Ok(count > 0)
+    }
+
+    pub fn clear(mark_id: &String, conn: &PgConnection) -> ThearningResult<usize> {
+        Ok(diesel::delete(criterion_scores::table.filter(criterion_scores::mark_id.eq(mark_id)))
+            .execute(conn)?)
+    }
+
+    /// Replaces the rubric scores of a mark. `scores` must already have been
+    /// checked with `Rubric::total`.
+    pub fn save(
+        mark_id: &String,
+        rubric: &Rubric,
+        scores: &[FillableCriterionScore],
+        conn: &PgConnection,
+    ) -> ThearningResult<Vec<Self>> {
+        Self::clear(mark_id, conn)?;
+
+        if scores.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let rows = scores
+            .iter()
+            .map(|s| Self {
+                id: format!("{}{}", generate_random_id(), generate_random_id()),
+                mark_id: mark_id.to_string(),
+                criterion_id: s.criterion_id.clone(),
+                level_id: s.level_id.clone(),
+                points: rubric
+                    .level(&s.criterion_id, &s.level_id)
+                    .map(|l| l.points)
+                    .unwrap_or(0),
+                comment: s.comment.clone(),
+            })
+            .collect::<Vec<Self>>();
+
+        diesel::insert_into(criterion_scores::table)
+            .values(&rows)
+            .execute(conn)?;
+
+        Self::load_by_mark(mark_id, conn)
+    }
+}
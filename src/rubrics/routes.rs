@@ -0,0 +1,86 @@
+use diesel::prelude::*;
+use rocket::http::Status;
+use rocket::serde::json::serde_json::json;
+use rocket::serde::json::Json;
+use rocket_dyn_templates::handlebars::JsonValue;
+
+use crate::assignments::models::Assignment;
+use crate::db;
+use crate::errors::ErrorKind;
+use crate::policy::{class_assignment, Can, CreateAssignment, ViewClass};
+use crate::rubrics::models::{CriterionScore, FillableRubric, Rubric};
+
+#[get("/<class_id>/rubrics/<assignment_id>")]
+pub fn rubric(
+    key: Can<ViewClass>,
+    class_id: &str,
+    assignment_id: &str,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let assignment = class_assignment(&key.0, class_id, assignment_id, &conn)?;
+
+    match Rubric::load(&assignment.assignment_id, &conn) {
+        Ok(r) => Ok(Json(json!({ "rubric": r, "max_points": r.max_points() }))),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+/// Replaces the rubric of an assignment, whose `total_marks` becomes the
+/// rubric's best possible total. Once any submission has been marked with
+/// it, the rubric can't change.
+#[patch("/<class_id>/rubrics/<assignment_id>", data = "<rubric>")]
+pub fn update_rubric(
+    key: Can<CreateAssignment>,
+    class_id: &str,
+    assignment_id: &str,
+    rubric: Json<FillableRubric>,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let assignment = class_assignment(&key.0, class_id, assignment_id, &conn)?;
+
+    let rubric = rubric.into_inner();
+
+    if !rubric.is_valid() {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    match CriterionScore::exists_for(&assignment.assignment_id, &conn) {
+        Ok(false) => {}
+        Ok(true) => return Err(Status::Conflict),
+        Err(_) => return Err(Status::InternalServerError),
+    }
+
+    let saved = conn.transaction::<_, ErrorKind, _>(|| {
+        let rubric = Rubric::save(&assignment.assignment_id, rubric, &conn)?;
+
+        assignment.set_total_marks(Some(rubric.max_points()), &conn)?;
+
+        Ok(rubric)
+    });
+
+    match saved {
+        Ok(r) => Ok(Json(json!({ "rubric": r, "max_points": r.max_points() }))),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+#[delete("/<class_id>/rubrics/<assignment_id>")]
+pub fn delete_rubric(
+    key: Can<CreateAssignment>,
+    class_id: &str,
+    assignment_id: &str,
+    conn: db::DbConn,
+) -> Result<Status, Status> {
+    let assignment = class_assignment(&key.0, class_id, assignment_id, &conn)?;
+
+    match CriterionScore::exists_for(&assignment.assignment_id, &conn) {
+        Ok(false) => {}
+        Ok(true) => return Err(Status::Conflict),
+        Err(_) => return Err(Status::InternalServerError),
+    }
+
+    match Rubric::delete(&assignment.assignment_id, &conn) {
+        Ok(_) => Ok(Status::Ok),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
@@ -1,3 +1,12 @@
+table! {
+    account_locks (user_id) {
+        user_id -> Varchar,
+        failed_attempts -> Int4,
+        last_failed_at -> Nullable<Timestamp>,
+        locked_until -> Nullable<Timestamp>,
+    }
+}
+
 table! {
     admins (id) {
         id -> Int4,
@@ -19,6 +28,15 @@ table! {
     }
 }
 
+table! {
+    assignment_targets (id) {
+        id -> Varchar,
+        assignment_id -> Varchar,
+        user_id -> Nullable<Varchar>,
+        group_id -> Nullable<Varchar>,
+    }
+}
+
 table! {
     assignments (assignment_id) {
         assignment_id -> Varchar,
@@ -33,6 +51,14 @@ table! {
         created_at -> Timestamp,
         creator -> Nullable<Varchar>,
         draft -> Bool,
+        category_id -> Nullable<Varchar>,
+        late_policy -> Varchar,
+        late_penalty -> Nullable<Int4>,
+        close_date -> Nullable<Timestamp>,
+        max_attempts -> Nullable<Int4>,
+        group_work -> Bool,
+        peer_reviewers -> Nullable<Int4>,
+        peer_weight -> Int4,
     }
 }
 
@@ -49,6 +75,40 @@ table! {
     }
 }
 
+table! {
+    attempt_files (id) {
+        id -> Varchar,
+        attempt_id -> Varchar,
+        file_id -> Nullable<Varchar>,
+        link_id -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    attempts (id) {
+        id -> Varchar,
+        submission_id -> Varchar,
+        number -> Int4,
+        state -> Varchar,
+        on_time -> Nullable<Bool>,
+        submitted_at -> Timestamp,
+        mark -> Nullable<Int4>,
+        marker_id -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    audit_logs (id) {
+        id -> Varchar,
+        action -> Varchar,
+        user_id -> Nullable<Varchar>,
+        actor_id -> Nullable<Varchar>,
+        ip -> Nullable<Varchar>,
+        detail -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     classes (class_id) {
         class_id -> Varchar,
@@ -72,6 +132,42 @@ table! {
     }
 }
 
+table! {
+    criterion_scores (id) {
+        id -> Varchar,
+        mark_id -> Varchar,
+        criterion_id -> Varchar,
+        level_id -> Varchar,
+        points -> Int4,
+        comment -> Nullable<Text>,
+    }
+}
+
+table! {
+    extensions (id) {
+        id -> Varchar,
+        submission_id -> Varchar,
+        granted_by -> Nullable<Varchar>,
+        due_date -> Nullable<Date>,
+        due_time -> Nullable<Time>,
+        reason -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    file_variants (id) {
+        id -> Varchar,
+        file_id -> Varchar,
+        variant -> Varchar,
+        width -> Int4,
+        height -> Int4,
+        file_path -> Varchar,
+        file_url -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     files (file_id) {
         file_id -> Varchar,
@@ -83,6 +179,34 @@ table! {
     }
 }
 
+table! {
+    grade_bands (id) {
+        id -> Varchar,
+        class_id -> Varchar,
+        min_score -> Int4,
+        label -> Varchar,
+        description -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    grade_categories (id) {
+        id -> Varchar,
+        class_id -> Varchar,
+        name -> Varchar,
+        weight -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    grading_scales (class_id) {
+        class_id -> Varchar,
+        kkm -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     links (id) {
         id -> Varchar,
@@ -94,6 +218,16 @@ table! {
     }
 }
 
+table! {
+    login_attempts (id) {
+        id -> Varchar,
+        user_id -> Nullable<Varchar>,
+        ip -> Varchar,
+        success -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     marks (id) {
         id -> Varchar,
@@ -102,6 +236,41 @@ table! {
         student_id -> Nullable<Varchar>,
         value -> Int4,
         created_at -> Timestamp,
+        as_of -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    password_resets (id) {
+        id -> Varchar,
+        user_id -> Varchar,
+        token -> Varchar,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    peer_review_scores (id) {
+        id -> Varchar,
+        review_id -> Varchar,
+        criterion_id -> Varchar,
+        level_id -> Varchar,
+        points -> Int4,
+        comment -> Nullable<Text>,
+    }
+}
+
+table! {
+    peer_reviews (id) {
+        id -> Varchar,
+        submission_id -> Varchar,
+        reviewer_id -> Varchar,
+        score -> Nullable<Int4>,
+        comment_id -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        reviewed_at -> Nullable<Timestamp>,
     }
 }
 
@@ -115,6 +284,89 @@ table! {
     }
 }
 
+table! {
+    recovery_codes (id) {
+        id -> Varchar,
+        user_id -> Varchar,
+        code -> Varchar,
+        used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    resumable_upload_chunks (id) {
+        id -> Varchar,
+        upload_id -> Varchar,
+        chunk_offset -> Int8,
+        size -> Int8,
+    }
+}
+
+table! {
+    resumable_uploads (id) {
+        id -> Varchar,
+        uploader -> Varchar,
+        upload_length -> Int8,
+        upload_offset -> Int8,
+        filename -> Varchar,
+        filetype -> Nullable<Varchar>,
+        assignment_id -> Nullable<Varchar>,
+        announcement_id -> Nullable<Varchar>,
+        submission_id -> Nullable<Varchar>,
+        attachment_id -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+    }
+}
+
+table! {
+    rubric_criteria (id) {
+        id -> Varchar,
+        assignment_id -> Varchar,
+        title -> Varchar,
+        description -> Nullable<Varchar>,
+        position -> Int4,
+    }
+}
+
+table! {
+    rubric_levels (id) {
+        id -> Varchar,
+        criterion_id -> Varchar,
+        title -> Varchar,
+        description -> Nullable<Varchar>,
+        points -> Int4,
+    }
+}
+
+table! {
+    sessions (id) {
+        id -> Varchar,
+        user_id -> Varchar,
+        refresh_token -> Varchar,
+        expires_at -> Timestamp,
+        revoked -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    student_group_members (group_id, user_id) {
+        group_id -> Varchar,
+        user_id -> Varchar,
+    }
+}
+
+table! {
+    student_groups (id) {
+        id -> Varchar,
+        class_id -> Varchar,
+        name -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     students (id) {
         id -> Int4,
@@ -124,6 +376,18 @@ table! {
     }
 }
 
+table! {
+    submission_events (id) {
+        id -> Varchar,
+        submission_id -> Varchar,
+        from_state -> Nullable<Varchar>,
+        to_state -> Varchar,
+        actor_id -> Nullable<Varchar>,
+        note -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     submissions (submission_id) {
         submission_id -> Varchar,
@@ -135,6 +399,13 @@ table! {
         marks_allotted -> Nullable<Int4>,
         submitted -> Bool,
         created_at -> Timestamp,
+        excused -> Bool,
+        due_date -> Nullable<Date>,
+        due_time -> Nullable<Time>,
+        state -> Varchar,
+        resubmit_allowed -> Bool,
+        group_id -> Nullable<Varchar>,
+        mark_adjustment -> Int4,
     }
 }
 
@@ -156,6 +427,16 @@ table! {
     }
 }
 
+table! {
+    two_factor (user_id) {
+        user_id -> Varchar,
+        secret -> Varchar,
+        enabled -> Bool,
+        last_step -> Nullable<Int8>,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     users (user_id) {
         user_id -> Varchar,
@@ -166,15 +447,30 @@ table! {
         birth_place -> Varchar,
         birth_date -> Date,
         bio -> Text,
-        status -> Varchar,
+        system_role -> Varchar,
+        created_at -> Timestamp,
+        verified_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    verification_resends (id) {
+        id -> Varchar,
+        user_id -> Nullable<Varchar>,
+        ip -> Varchar,
         created_at -> Timestamp,
     }
 }
 
+joinable!(account_locks -> users (user_id));
 joinable!(admins -> classes (class_id));
 joinable!(admins -> users (user_id));
 joinable!(announcements -> classes (class_id));
+joinable!(assignment_targets -> assignments (assignment_id));
+joinable!(assignment_targets -> student_groups (group_id));
+joinable!(assignment_targets -> users (user_id));
 joinable!(assignments -> classes (class_id));
+joinable!(assignments -> grade_categories (category_id));
 joinable!(assignments -> topics (topic_id));
 joinable!(assignments -> users (creator));
 joinable!(attachments -> announcements (announcement_id));
@@ -183,35 +479,100 @@ joinable!(attachments -> files (file_id));
 joinable!(attachments -> links (link_id));
 joinable!(attachments -> submissions (submission_id));
 joinable!(attachments -> users (uploader));
+joinable!(attempt_files -> attempts (attempt_id));
+joinable!(attempt_files -> files (file_id));
+joinable!(attempt_files -> links (link_id));
+joinable!(attempts -> submissions (submission_id));
+joinable!(attempts -> users (marker_id));
 joinable!(classes -> users (class_creator));
 joinable!(comments -> announcements (announcement_id));
 joinable!(comments -> assignments (assignment_id));
 joinable!(comments -> users (user_id));
+joinable!(criterion_scores -> marks (mark_id));
+joinable!(criterion_scores -> rubric_criteria (criterion_id));
+joinable!(criterion_scores -> rubric_levels (level_id));
+joinable!(extensions -> submissions (submission_id));
+joinable!(extensions -> users (granted_by));
+joinable!(file_variants -> files (file_id));
+joinable!(grade_bands -> grading_scales (class_id));
+joinable!(grade_categories -> classes (class_id));
+joinable!(grading_scales -> classes (class_id));
 joinable!(marks -> submissions (submission_id));
+joinable!(password_resets -> users (user_id));
+joinable!(peer_review_scores -> peer_reviews (review_id));
+joinable!(peer_review_scores -> rubric_criteria (criterion_id));
+joinable!(peer_review_scores -> rubric_levels (level_id));
+joinable!(peer_reviews -> private_comments (comment_id));
+joinable!(peer_reviews -> submissions (submission_id));
+joinable!(peer_reviews -> users (reviewer_id));
 joinable!(private_comments -> submissions (submission_id));
 joinable!(private_comments -> users (user_id));
+joinable!(recovery_codes -> users (user_id));
+joinable!(resumable_upload_chunks -> resumable_uploads (upload_id));
+joinable!(resumable_uploads -> announcements (announcement_id));
+joinable!(resumable_uploads -> assignments (assignment_id));
+joinable!(resumable_uploads -> attachments (attachment_id));
+joinable!(resumable_uploads -> submissions (submission_id));
+joinable!(resumable_uploads -> users (uploader));
+joinable!(rubric_criteria -> assignments (assignment_id));
+joinable!(rubric_levels -> rubric_criteria (criterion_id));
+joinable!(sessions -> users (user_id));
+joinable!(student_group_members -> student_groups (group_id));
+joinable!(student_group_members -> users (user_id));
+joinable!(student_groups -> classes (class_id));
 joinable!(students -> classes (class_id));
 joinable!(students -> users (user_id));
+joinable!(submission_events -> submissions (submission_id));
+joinable!(submission_events -> users (actor_id));
 joinable!(submissions -> assignments (assignment_id));
+joinable!(submissions -> student_groups (group_id));
 joinable!(submissions -> users (user_id));
 joinable!(teachers -> classes (class_id));
 joinable!(teachers -> users (user_id));
 joinable!(topics -> classes (classroom_id));
+joinable!(two_factor -> users (user_id));
+joinable!(verification_resends -> users (user_id));
 
 allow_tables_to_appear_in_same_query!(
+    account_locks,
     admins,
     announcements,
+    assignment_targets,
     assignments,
     attachments,
+    attempt_files,
+    attempts,
+    audit_logs,
     classes,
     comments,
+    criterion_scores,
+    extensions,
+    file_variants,
     files,
+    grade_bands,
+    grade_categories,
+    grading_scales,
     links,
+    login_attempts,
     marks,
+    password_resets,
+    peer_review_scores,
+    peer_reviews,
     private_comments,
+    recovery_codes,
+    resumable_upload_chunks,
+    resumable_uploads,
+    rubric_criteria,
+    rubric_levels,
+    sessions,
+    student_group_members,
+    student_groups,
     students,
+    submission_events,
     submissions,
     teachers,
     topics,
+    two_factor,
     users,
+    verification_resends,
 );
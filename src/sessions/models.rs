@@ -0,0 +1,123 @@
+use chrono::{Duration, Local, NaiveDateTime};
+use diesel;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{ErrorKind, JWTCError, ThearningResult};
+use crate::schema::sessions;
+use crate::users::models::User;
+use crate::utils::{generate_random_id, generate_secret, hash_secret, split_token};
+
+pub const REFRESH_TOKEN_DAYS: i64 = 30;
+
+#[derive(Serialize, Deserialize, Queryable, Insertable, Associations, Clone)]
+#[belongs_to(User)]
+#[table_name = "sessions"]
+pub struct Session {
+    pub id: String,
+    pub user_id: String,
+    pub refresh_token: String,
+    pub expires_at: NaiveDateTime,
+    pub revoked: bool,
+    pub created_at: NaiveDateTime,
+}
+
+impl Session {
+    /// Opens a new session for the user and returns it with its refresh token.
+    pub fn create(uid: &String, conn: &PgConnection) -> ThearningResult<(Self, String)> {
+        let secret = generate_secret();
+
+        let now = Local::now().naive_local();
+
+        let session = Self {
+            id: format!("{}{}", generate_random_id(), generate_random_id()),
+            user_id: uid.to_string(),
+            refresh_token: hash_secret(&secret),
+            expires_at: now + Duration::days(REFRESH_TOKEN_DAYS),
+            revoked: false,
+            created_at: now,
+        };
+
+        diesel::insert_into(sessions::table)
+            .values(&session)
+            .execute(conn)?;
+
+        let res = sessions::table
+            .find(&session.id)
+            .get_result::<Self>(conn)?;
+
+        let token = format!("{}.{}", res.id, secret);
+
+        Ok((res, token))
+    }
+
+    pub fn find(id: &str, conn: &PgConnection) -> ThearningResult<Self> {
+        Ok(sessions::table.find(id).get_result::<Self>(conn)?)
+    }
+
+    /// Refresh tokens are handed out as `<session id>.<secret>` and only the
+    /// hash of the secret is stored. Revoked, expired or mismatching tokens
+    /// are all rejected.
+    pub fn from_refresh_token(token: &str, conn: &PgConnection) -> ThearningResult<Self> {
+        let (id, secret) = split_token(token)?;
+
+        let session = Self::find(id, conn)?;
+
+        if session.refresh_token != hash_secret(secret) {
+            return Err(ErrorKind::InvalidValue);
+        }
+
+        if !session.is_active() {
+            return Err(ErrorKind::from(JWTCError::SessionRevoked));
+        }
+
+        Ok(session)
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.revoked && self.expires_at > Local::now().naive_local()
+    }
+
+    /// Checks whether the session an access token was issued for is still alive.
+    pub fn is_valid(id: &str, conn: &PgConnection) -> bool {
+        match Self::find(id, conn) {
+            Ok(session) => session.is_active(),
+            Err(_) => false,
+        }
+    }
+
+    /// Replaces the refresh token with a fresh one, invalidating the old token.
+    pub fn rotate(&self, conn: &PgConnection) -> ThearningResult<(Self, String)> {
+        let secret = generate_secret();
+
+        let res = diesel::update(sessions::table.find(&self.id))
+            .set((
+                sessions::refresh_token.eq(hash_secret(&secret)),
+                sessions::expires_at
+                    .eq(Local::now().naive_local() + Duration::days(REFRESH_TOKEN_DAYS)),
+            ))
+            .get_result::<Self>(conn)?;
+
+        let token = format!("{}.{}", res.id, secret);
+
+        Ok((res, token))
+    }
+
+    pub fn revoke(&self, conn: &PgConnection) -> ThearningResult<Self> {
+        Ok(diesel::update(sessions::table.find(&self.id))
+            .set(sessions::revoked.eq(true))
+            .get_result::<Self>(conn)?)
+    }
+
+    /// Revokes every session of a user, signing them out on all devices.
+    pub fn revoke_all(uid: &String, conn: &PgConnection) -> ThearningResult<usize> {
+        Ok(diesel::update(
+            sessions::table
+                .filter(sessions::user_id.eq(uid))
+                .filter(sessions::revoked.eq(false)),
+        )
+        .set(sessions::revoked.eq(true))
+        .execute(conn)?)
+    }
+}
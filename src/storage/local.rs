@@ -0,0 +1,79 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use tokio::io::AsyncWriteExt;
+
+use crate::errors::{ErrorKind, ThearningResult};
+use crate::storage::{Served, Storage};
+
+/// Files kept in a directory on this machine, each key a path under `root`.
+pub struct LocalStorage {
+    pub root: PathBuf,
+}
+
+impl LocalStorage {
+    /// Keys never leave `root`.
+    fn path(&self, key: &str) -> ThearningResult<PathBuf> {
+        if key.split('/').any(|s| s.is_empty() || s == "." || s == "..") {
+            return Err(ErrorKind::InvalidValue);
+        }
+
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, bytes: Vec<u8>, _content_type: &str) -> ThearningResult<()> {
+        let path = self.path(key)?;
+
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+
+        Ok(tokio::fs::write(path, bytes).await?)
+    }
+
+    async fn get(&self, key: &str) -> ThearningResult<Vec<u8>> {
+        Ok(tokio::fs::read(self.path(key)?).await?)
+    }
+
+    async fn delete(&self, key: &str) -> ThearningResult<()> {
+        Ok(tokio::fs::remove_file(self.path(key)?).await?)
+    }
+
+    async fn join(&self, key: &str, parts: &[String], _content_type: &str) -> ThearningResult<()> {
+        let path = self.path(key)?;
+
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+
+        let mut joined = tokio::fs::File::create(&path).await?;
+
+        let copied: ThearningResult<()> = async {
+            for part in parts {
+                let mut file = tokio::fs::File::open(self.path(part)?).await?;
+
+                tokio::io::copy(&mut file, &mut joined).await?;
+            }
+
+            Ok(joined.flush().await?)
+        }
+        .await;
+
+        // No half-joined files
+        if copied.is_err() {
+            tokio::fs::remove_file(&path).await.ok();
+        }
+
+        copied
+    }
+
+    async fn serve(&self, key: &str) -> ThearningResult<Served> {
+        let file = tokio::fs::File::open(self.path(key)?).await?;
+        let size = file.metadata().await?.len();
+
+        Ok(Served::File { file, size })
+    }
+}
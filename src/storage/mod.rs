@@ -0,0 +1,80 @@
+use std::env;
+
+use async_trait::async_trait;
+use tokio::fs::File;
+
+use crate::errors::ThearningResult;
+use crate::storage::local::LocalStorage;
+use crate::storage::s3::S3Storage;
+use crate::MEDIA_URL;
+
+pub(crate) mod local;
+pub(crate) mod s3;
+
+/// Where uploaded files live. Files are addressed by keys like
+/// `attachments/<id>-<name>` which mean the same thing whichever backend
+/// holds them, so moving between backends is a matter of copying the files.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> ThearningResult<()>;
+
+    async fn get(&self, key: &str) -> ThearningResult<Vec<u8>>;
+
+    async fn delete(&self, key: &str) -> ThearningResult<()>;
+
+    /// Joins the objects at `parts`, in order, into one at `key`, a piece at
+    /// a time rather than all in memory. The parts stay.
+    async fn join(&self, key: &str, parts: &[String], content_type: &str) -> ThearningResult<()>;
+
+    /// How the file is to be sent to a client, see [`Served`].
+    async fn serve(&self, key: &str) -> ThearningResult<Served>;
+}
+
+/// A stored file on its way to a client.
+pub enum Served {
+    /// Read from disk a piece at a time, `size` bytes long, so it needn't
+    /// all be in memory and ranges of it can be sent.
+    File { file: File, size: u64 },
+    /// Fetched straight from the backend at this short-lived URL, which
+    /// handles ranges itself.
+    Redirect(String),
+}
+
+/// The backend picked by `STORAGE_BACKEND`. `s3` stores files in an
+/// S3-compatible bucket so several app servers can share them, anything else
+/// keeps them on local disk under `MEDIA_ROOT`, `media` by default.
+pub fn backend() -> ThearningResult<Box<dyn Storage>> {
+    match env::var("STORAGE_BACKEND").as_deref() {
+        Ok("s3") => Ok(Box::new(S3Storage {
+            endpoint: env::var("S3_ENDPOINT")?,
+            bucket: env::var("S3_BUCKET")?,
+            region: env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+            access_key: env::var("S3_ACCESS_KEY")?,
+            secret_key: env::var("S3_SECRET_KEY")?,
+        })),
+        _ => {
+            let root = match env::var("MEDIA_ROOT") {
+                Ok(r) => r.into(),
+                Err(_) => env::current_dir()?.join(MEDIA_URL),
+            };
+
+            Ok(Box::new(LocalStorage { root }))
+        }
+    }
+}
+
+/// The key an upload is stored under, `<folder>/<file id>-<filename>`.
+pub fn key_for(folder: &str, file_id: &str, filename: &str) -> String {
+    format!("{}/{}-{}", folder, file_id, filename)
+}
+
+/// The key a resized copy of an upload is stored under,
+/// `<folder>/<file id>/<variant>.<extension>`.
+pub fn variant_key_for(folder: &str, file_id: &str, variant: &str, ext: &str) -> String {
+    format!("{}/{}/{}.{}", folder, file_id, variant, ext)
+}
+
+/// Where the app serves the file with the key, whichever backend holds it.
+pub fn url_for(key: &str) -> ThearningResult<String> {
+    Ok(format!("{}/api/media/{}", env::var("SITE_URL")?, key))
+}
@@ -0,0 +1,315 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::{Client, Method, Response, Url};
+use sha2::{Digest, Sha256};
+
+use crate::errors::{ErrorKind, ThearningResult};
+use crate::files::utils::SIGNED_URL_TTL;
+use crate::storage::{Served, Storage};
+
+/// Parts of a multipart upload can't be smaller, the last one aside.
+const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// Files kept in a bucket on S3 or anything speaking its API, like MinIO.
+/// Objects are addressed path-style, `<endpoint>/<bucket>/<key>`, which is
+/// what MinIO expects.
+pub struct S3Storage {
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Percent-encodes a path the way SigV4 wants it, leaving the slashes.
+pub fn encode_path(path: &str) -> String {
+    path.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Like `encode_path`, for a query string value, slashes included.
+fn encode_query(value: &str) -> String {
+    encode_path(value).replace('/', "%2F")
+}
+
+/// Signs a SigV4 canonical request made at `at`.
+pub fn sign_request(canonical_request: &str, at: DateTime<Utc>, region: &str, secret_key: &str) -> String {
+    let date = at.format("%Y%m%d").to_string();
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        at.format("%Y%m%dT%H%M%SZ"),
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let key = hmac(format!("AWS4{}", secret_key).as_bytes(), &date);
+    let key = hmac(&key, region);
+    let key = hmac(&key, "s3");
+    let key = hmac(&key, "aws4_request");
+
+    hex(&hmac(&key, &string_to_sign))
+}
+
+/// The AWS Signature Version 4 canonical request. `query` is the canonical
+/// query string, its parameters encoded and sorted by name. `headers` are
+/// the lowercase name and value of every header to sign, sorted by name,
+/// and must include `host`, `x-amz-content-sha256` and `x-amz-date`.
+pub fn canonical_request(
+    method: &str,
+    path: &str,
+    query: &str,
+    headers: &[(&str, &str)],
+    payload_hash: &str,
+) -> String {
+    let canonical_headers = headers
+        .iter()
+        .map(|(k, v)| format!("{}:{}\n", k, v.trim()))
+        .collect::<String>();
+
+    let signed_headers = headers.iter().map(|(k, _)| *k).collect::<Vec<&str>>().join(";");
+
+    format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method, path, query, canonical_headers, signed_headers, payload_hash
+    )
+}
+
+/// The query string of a presigned GET of `path` on `host`, good for
+/// `expires` seconds from `at`. Only the host header is signed.
+pub fn presigned_query(
+    path: &str,
+    host: &str,
+    at: DateTime<Utc>,
+    expires: i64,
+    region: &str,
+    access_key: &str,
+    secret_key: &str,
+) -> String {
+    let credential = format!("{}/{}/{}/s3/aws4_request", access_key, at.format("%Y%m%d"), region);
+
+    let query = format!(
+        "X-Amz-Algorithm=AWS4-HMAC-SHA256&X-Amz-Credential={}&X-Amz-Date={}&X-Amz-Expires={}&X-Amz-SignedHeaders=host",
+        encode_query(&credential),
+        at.format("%Y%m%dT%H%M%SZ"),
+        expires
+    );
+
+    let canonical_request = format!("GET\n{}\n{}\nhost:{}\n\nhost\nUNSIGNED-PAYLOAD", path, query, host);
+
+    format!("{}&X-Amz-Signature={}", query, sign_request(&canonical_request, at, region, secret_key))
+}
+
+impl S3Storage {
+    /// The object's URL, its encoded path and the host it's on.
+    fn locate(&self, key: &str) -> ThearningResult<(Url, String, String)> {
+        let path = encode_path(&format!("/{}/{}", self.bucket, key));
+
+        let url = Url::parse(&format!("{}{}", self.endpoint.trim_end_matches('/'), path))
+            .map_err(|e| ErrorKind::StorageError(e.to_string()))?;
+
+        let host = match (url.host_str(), url.port()) {
+            (Some(h), Some(p)) => format!("{}:{}", h, p),
+            (Some(h), None) => h.to_string(),
+            (None, _) => return Err(ErrorKind::InvalidValue),
+        };
+
+        Ok((url, path, host))
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        key: &str,
+        query: &str,
+        body: Vec<u8>,
+        content_type: Option<&str>,
+    ) -> ThearningResult<Response> {
+        let (mut url, path, host) = self.locate(key)?;
+
+        if !query.is_empty() {
+            url.set_query(Some(query));
+        }
+
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let payload_hash = hex(&Sha256::digest(&body));
+
+        let mut headers = vec![
+            ("host", host.as_str()),
+            ("x-amz-content-sha256", payload_hash.as_str()),
+            ("x-amz-date", amz_date.as_str()),
+        ];
+
+        if let Some(t) = content_type {
+            headers.insert(0, ("content-type", t));
+        }
+
+        let canonical = canonical_request(method.as_str(), &path, query, &headers, &payload_hash);
+        let signature = sign_request(&canonical, now, &self.region, &self.secret_key);
+
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}/{}/s3/aws4_request, SignedHeaders={}, Signature={}",
+            self.access_key,
+            now.format("%Y%m%d"),
+            self.region,
+            headers.iter().map(|(k, _)| *k).collect::<Vec<&str>>().join(";"),
+            signature
+        );
+
+        let mut request = Client::new()
+            .request(method, url)
+            .header("authorization", authorization)
+            .body(body);
+
+        // reqwest sets the host itself
+        for (k, v) in headers.iter().filter(|(k, _)| *k != "host") {
+            request = request.header(*k, *v);
+        }
+
+        let response = request.send().await?;
+
+        if !response.status().is_success() {
+            return Err(ErrorKind::StorageError(format!("{} {}", response.status(), key)));
+        }
+
+        Ok(response)
+    }
+
+    async fn send(&self, method: Method, key: &str, query: &str, body: Vec<u8>, content_type: Option<&str>) -> ThearningResult<Vec<u8>> {
+        let response = self.request(method, key, query, body, content_type).await?;
+
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    /// Sends the objects at `parts` to a multipart upload, gathered into
+    /// parts S3 takes, and returns the ETags of the parts sent.
+    async fn upload_parts(&self, key: &str, upload_id: &str, parts: &[String]) -> ThearningResult<Vec<String>> {
+        let mut etags = Vec::new();
+        let mut buffer = Vec::new();
+
+        for (i, part) in parts.iter().enumerate() {
+            buffer.extend(self.get(part).await?);
+
+            if buffer.len() < MIN_PART_SIZE && i + 1 < parts.len() {
+                continue;
+            }
+
+            let query = format!("partNumber={}&uploadId={}", etags.len() + 1, upload_id);
+            let response = self.request(Method::PUT, key, &query, std::mem::take(&mut buffer), None).await?;
+
+            match response.headers().get("etag").and_then(|e| e.to_str().ok()) {
+                Some(etag) => etags.push(etag.to_string()),
+                None => return Err(ErrorKind::StorageError(format!("no ETag for part {} of {}", etags.len() + 1, key))),
+            }
+        }
+
+        Ok(etags)
+    }
+
+    /// Puts the uploaded parts together. S3 can answer 200 and still have
+    /// failed, the error is in the body then.
+    async fn complete(&self, key: &str, upload_id: &str, etags: &[String]) -> ThearningResult<()> {
+        let parts = etags
+            .iter()
+            .enumerate()
+            .map(|(i, etag)| format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", i + 1, etag))
+            .collect::<String>();
+
+        let body = format!("<CompleteMultipartUpload>{}</CompleteMultipartUpload>", parts);
+
+        let completed = self
+            .send(Method::POST, key, &format!("uploadId={}", upload_id), body.into_bytes(), Some("application/xml"))
+            .await?;
+
+        match xml_value(&completed, "Code") {
+            Some(code) => Err(ErrorKind::StorageError(format!("{} {}", code, key))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The text of the first `tag` element in an S3 XML response.
+pub fn xml_value(body: &[u8], tag: &str) -> Option<String> {
+    let body = std::str::from_utf8(body).ok()?;
+    let start = body.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let end = start + body[start..].find(&format!("</{}>", tag))?;
+
+    Some(body[start..end].to_string())
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> ThearningResult<()> {
+        self.send(Method::PUT, key, "", bytes, Some(content_type)).await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> ThearningResult<Vec<u8>> {
+        self.send(Method::GET, key, "", Vec::new(), None).await
+    }
+
+    async fn delete(&self, key: &str) -> ThearningResult<()> {
+        self.send(Method::DELETE, key, "", Vec::new(), None).await?;
+
+        Ok(())
+    }
+
+    /// A multipart upload, aborted if any of it fails so the bucket isn't
+    /// left holding the parts.
+    async fn join(&self, key: &str, parts: &[String], content_type: &str) -> ThearningResult<()> {
+        let created = self.send(Method::POST, key, "uploads=", Vec::new(), Some(content_type)).await?;
+
+        let upload_id = match xml_value(&created, "UploadId") {
+            Some(id) => encode_query(&id),
+            None => return Err(ErrorKind::StorageError(format!("no upload id for {}", key))),
+        };
+
+        let joined = match self.upload_parts(key, &upload_id, parts).await {
+            Ok(etags) => self.complete(key, &upload_id, &etags).await,
+            Err(e) => Err(e),
+        };
+
+        if joined.is_err() {
+            self.send(Method::DELETE, key, &format!("uploadId={}", upload_id), Vec::new(), None).await.ok();
+        }
+
+        joined
+    }
+
+    /// Objects are sent from the bucket, not through the app.
+    async fn serve(&self, key: &str) -> ThearningResult<Served> {
+        let (url, path, host) = self.locate(key)?;
+
+        let query = presigned_query(
+            &path,
+            &host,
+            Utc::now(),
+            SIGNED_URL_TTL,
+            &self.region,
+            &self.access_key,
+            &self.secret_key,
+        );
+
+        Ok(Served::Redirect(format!("{}?{}", url, query)))
+    }
+}
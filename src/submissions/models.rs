@@ -1,11 +1,21 @@
+use std::collections::HashMap;
+use std::fmt;
+
 use chrono::{Local, NaiveDate, NaiveDateTime, NaiveTime};
 use diesel::prelude::*;
+use diesel::result::Error;
+use rocket::fs::TempFile;
 use diesel::{PgConnection, QueryDsl, RunQueryDsl};
 use serde::{Deserialize, Serialize};
 
 use crate::assignments::models::Assignment;
-use crate::errors::ThearningResult;
-use crate::schema::{marks, submissions};
+use crate::assignments::utils::{deadline, on_time};
+use crate::attempts::models::Attempt;
+use crate::errors::{ErrorKind, ThearningResult};
+use crate::peer_reviews::models::PeerReview;
+use crate::peer_reviews::utils::blend;
+use crate::rubrics::models::FillableCriterionScore;
+use crate::schema::{attachments, extensions, marks, private_comments, submission_events, submissions};
 use crate::traits::Manipulable;
 use crate::utils::generate_random_id;
 
@@ -22,10 +32,118 @@ pub struct Submissions {
     pub marks_allotted: Option<i32>,
     pub submitted: bool,
     pub created_at: NaiveDateTime,
+    pub excused: bool,
+    pub due_date: Option<NaiveDate>,
+    pub due_time: Option<NaiveTime>,
+    pub state: String,
+    pub resubmit_allowed: bool,
+    pub group_id: Option<String>,
+    pub mark_adjustment: i32,
+}
+
+/// Where a piece of work is in its life. `submitted` on the row is true
+/// exactly while the state is one of the handed-in ones.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionState {
+    Assigned,
+    TurnedIn,
+    Late,
+    Returned,
+    Resubmitted,
+    Missing,
+}
+
+/// Something that moves a submission from one state to another. `Recheck`
+/// sorts work into on time or late again after its deadline moved.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transition {
+    TurnIn,
+    Unsubmit,
+    Return,
+    Recheck,
+}
+
+impl TryFrom<&str> for SubmissionState {
+    type Error = ErrorKind;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "assigned" => Ok(Self::Assigned),
+            "turned_in" => Ok(Self::TurnedIn),
+            "late" => Ok(Self::Late),
+            "returned" => Ok(Self::Returned),
+            "resubmitted" => Ok(Self::Resubmitted),
+            "missing" => Ok(Self::Missing),
+            _ => Err(ErrorKind::InvalidValue),
+        }
+    }
+}
+
+impl fmt::Display for SubmissionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            Self::Assigned => "assigned",
+            Self::TurnedIn => "turned_in",
+            Self::Late => "late",
+            Self::Returned => "returned",
+            Self::Resubmitted => "resubmitted",
+            Self::Missing => "missing",
+        };
+
+        write!(f, "{}", state)
+    }
+}
+
+impl SubmissionState {
+    pub fn is_submitted(&self) -> bool {
+        matches!(self, Self::TurnedIn | Self::Late | Self::Resubmitted)
+    }
+
+    /// The state `transition` leads to, `None` when it isn't allowed from
+    /// here. `late` is whether the deadline has passed for the work, and
+    /// returned work can only be handed in again with `resubmit_allowed`.
+    pub fn after(&self, transition: Transition, late: bool, resubmit_allowed: bool) -> Option<Self> {
+        let on_time = |yes, no| if late { no } else { yes };
+
+        match (transition, self) {
+            (Transition::TurnIn, Self::Assigned | Self::Missing) => Some(on_time(Self::TurnedIn, Self::Late)),
+            (Transition::TurnIn, Self::Returned) if resubmit_allowed => Some(Self::Resubmitted),
+            (Transition::Unsubmit, Self::TurnedIn | Self::Late) => Some(on_time(Self::Assigned, Self::Missing)),
+            (Transition::Unsubmit, Self::Resubmitted) => Some(Self::Returned),
+            (Transition::Return, _) => Some(Self::Returned),
+            (Transition::Recheck, Self::TurnedIn | Self::Late) => Some(on_time(Self::TurnedIn, Self::Late)),
+            (Transition::Recheck, Self::Assigned | Self::Missing) => Some(on_time(Self::Assigned, Self::Missing)),
+            (Transition::Recheck, Self::Returned | Self::Resubmitted) => Some(*self),
+            _ => None,
+        }
+    }
+}
+
+/// One step in a submission's state history. `from_state` is empty for the
+/// step that assigned the work.
+#[derive(Serialize, Deserialize, Queryable, Insertable)]
+#[table_name = "submission_events"]
+pub struct SubmissionEvent {
+    pub id: String,
+    pub submission_id: String,
+    pub from_state: Option<String>,
+    pub to_state: String,
+    pub actor_id: Option<String>,
+    pub note: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReturnedWork {
+    pub feedback: Option<String>,
+    #[serde(default)]
+    pub allow_resubmit: bool,
 }
 
 #[derive(Serialize, Deserialize, Insertable, AsChangeset, Clone, Queryable)]
 #[table_name = "marks"]
+#[changeset_options(treat_none_as_null = "true")]
 pub struct Mark {
     pub id: String,
     pub submission_id: Option<String>,
@@ -33,6 +151,8 @@ pub struct Mark {
     pub student_id: Option<String>,
     pub value: i32,
     pub created_at: NaiveDateTime,
+    /// When the marked attempt was handed in, if it wasn't the latest one
+    pub as_of: Option<NaiveDateTime>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -40,13 +160,70 @@ pub struct FillableMark {
     pub submission_id: Option<String>,
     pub marker_id: Option<String>,
     pub student_id: Option<String>,
+    /// Ignored when the mark comes with rubric scores, their total is used
+    #[serde(default)]
     pub value: i32,
+    #[serde(default)]
+    pub rubric: Vec<FillableCriterionScore>,
+    /// Marks added to or taken off the team's mark for some of its members,
+    /// by student
+    #[serde(default)]
+    pub adjustments: HashMap<String, i32>,
+    #[serde(skip)]
+    pub as_of: Option<NaiveDateTime>,
+}
+
+impl FillableMark {
+    /// A mark on its own, without rubric scores or adjustments.
+    pub fn plain(value: i32) -> Self {
+        Self {
+            submission_id: None,
+            marker_id: None,
+            student_id: None,
+            value,
+            rubric: Vec::new(),
+            adjustments: HashMap::new(),
+            as_of: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Excusal {
+    pub excused: bool,
+}
+
+/// One entry in a submission's extension history. An entry without a
+/// `due_date` takes the student back to the assignment's deadline.
+#[derive(Serialize, Deserialize, Queryable, Insertable)]
+#[table_name = "extensions"]
+pub struct Extension {
+    pub id: String,
+    pub submission_id: String,
+    pub granted_by: Option<String>,
+    pub due_date: Option<NaiveDate>,
+    pub due_time: Option<NaiveTime>,
+    pub reason: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FillableExtension {
+    pub due_date: Option<NaiveDate>,
+    pub due_time: Option<NaiveTime>,
+    pub reason: Option<String>,
+}
+
+#[derive(FromForm)]
+pub struct MarkUpload<'a> {
+    pub file: TempFile<'a>,
 }
 
 #[derive(Clone)]
 pub struct FillableSubmissions {
     pub assignment_id: String,
     pub user_id: String,
+    pub group_id: Option<String>,
 }
 
 impl Submissions {
@@ -61,9 +238,31 @@ impl Submissions {
             .get_result::<Self>(conn)?)
     }
 
+    /// Sets `marks_allotted` to the mark, blended with the peer review
+    /// average by the assignment's weight, with the student's own adjustment
+    /// on team work and less any late penalty. The penalty goes by the
+    /// attempt the mark was given for, see [`Mark::as_of`].
     pub fn mark(&self, value: &i32, conn: &PgConnection) -> ThearningResult<Self> {
+        let assignment = Assignment::get_by_id(&self.assignment_id, conn)?;
+
+        let at = match Mark::get_by_submission_id(&self.submission_id, conn) {
+            Ok(Mark { as_of: Some(at), .. }) => Some(at),
+            Ok(_) | Err(ErrorKind::QueryError(Error::NotFound)) => self.handed_in_at(conn)?,
+            Err(e) => return Err(e),
+        };
+
+        let value = blend(*value, PeerReview::average(&self.shared_ids(conn)?, conn)?, assignment.peer_weight);
+
+        let allotted = (value + self.mark_adjustment - self.penalty_at(&assignment, at)).max(0);
+
         Ok(diesel::update(submissions::table.filter(submissions::submission_id.eq(&self.submission_id)))
-            .set(submissions::marks_allotted.eq(value))
+            .set(submissions::marks_allotted.eq(allotted))
+            .get_result::<Self>(conn)?)
+    }
+
+    pub fn excuse(&self, excused: bool, conn: &PgConnection) -> ThearningResult<Self> {
+        Ok(diesel::update(submissions::table.find(&self.submission_id))
+            .set(submissions::excused.eq(excused))
             .get_result::<Self>(conn)?)
     }
 
@@ -92,100 +291,319 @@ impl Submissions {
             .load::<Self>(conn)?)
     }
 
-    pub fn unsubmit(&self, conn: &PgConnection) -> ThearningResult<Self> {
-        diesel::update(
-            submissions::table.filter(submissions::submission_id.eq(&self.submission_id)),
-        )
-        .set(submissions::submitted.eq(false))
-        .execute(conn)?;
+    /// Nothing handed in, attached, marked or said about it yet, so it can
+    /// go without losing anything.
+    pub fn is_untouched(&self, conn: &PgConnection) -> ThearningResult<bool> {
+        if self.submitted || self.marks_allotted.is_some() || self.state() == SubmissionState::Returned {
+            return Ok(false);
+        }
+
+        let attached = attachments::table
+            .filter(attachments::submission_id.eq(&self.submission_id))
+            .count()
+            .get_result::<i64>(conn)?;
+
+        let commented = private_comments::table
+            .filter(private_comments::submission_id.eq(&self.submission_id))
+            .count()
+            .get_result::<i64>(conn)?;
+
+        let marked = marks::table
+            .filter(marks::submission_id.eq(&self.submission_id))
+            .count()
+            .get_result::<i64>(conn)?;
+
+        Ok(attached + commented + marked == 0 && Attempt::count(&self.submission_id, conn)? == 0)
+    }
 
-        Ok(submissions::dsl::submissions
-            .find(&self.submission_id)
+    /// Everyone's submissions for the team this one is in, or just this one
+    /// for work done alone.
+    pub fn team(&self, conn: &PgConnection) -> ThearningResult<Vec<Self>> {
+        match &self.group_id {
+            Some(group_id) => Ok(submissions::table
+                .filter(submissions::assignment_id.eq(&self.assignment_id))
+                .filter(submissions::group_id.eq(group_id))
+                .order(submissions::user_id.asc())
+                .load::<Self>(conn)?),
+            None => Ok(vec![Self::find_submission(&self.submission_id, conn)?]),
+        }
+    }
+
+    fn teammates(&self, conn: &PgConnection) -> ThearningResult<Vec<Self>> {
+        Ok(self
+            .team(conn)?
+            .into_iter()
+            .filter(|s| s.submission_id != self.submission_id)
+            .collect())
+    }
+
+    /// The submissions the team's work is attached to.
+    pub fn shared_ids(&self, conn: &PgConnection) -> ThearningResult<Vec<String>> {
+        Ok(self.team(conn)?.into_iter().map(|s| s.submission_id).collect())
+    }
+
+    pub fn join_team(&self, group_id: Option<&String>, conn: &PgConnection) -> ThearningResult<Self> {
+        Ok(diesel::update(submissions::table.find(&self.submission_id))
+            .set(submissions::group_id.eq(group_id))
+            .get_result::<Self>(conn)?)
+    }
+
+    pub fn adjust(&self, adjustment: i32, conn: &PgConnection) -> ThearningResult<Self> {
+        Ok(diesel::update(submissions::table.find(&self.submission_id))
+            .set(submissions::mark_adjustment.eq(adjustment))
             .get_result::<Self>(conn)?)
     }
 
-    pub fn submit(&self, conn: &PgConnection) -> ThearningResult<Self> {
+    pub fn remove(&self, conn: &PgConnection) -> ThearningResult<usize> {
+        Ok(diesel::delete(submissions::table.find(&self.submission_id)).execute(conn)?)
+    }
+
+    pub fn state(&self) -> SubmissionState {
+        SubmissionState::try_from(self.state.as_str()).unwrap_or(SubmissionState::Assigned)
+    }
+
+    /// Whether the work is, or would be if handed in now, past this
+    /// student's deadline.
+    fn is_late(&self, assignment: &Assignment, now: NaiveDateTime) -> bool {
+        let at = self.submitted_at().unwrap_or(now);
+
+        on_time(self.due_at(assignment), at) == Some(false)
+    }
+
+    /// Moves the submission along `transition` and writes the step to its
+    /// history. Fails with `InvalidValue` when the move isn't allowed. A
+    /// recheck that changes nothing isn't recorded, work returned again is,
+    /// since it comes with new feedback.
+    pub fn transition(
+        &self,
+        transition: Transition,
+        actor_id: Option<&String>,
+        note: Option<String>,
+        conn: &PgConnection,
+    ) -> ThearningResult<Self> {
         let assignment = Assignment::get_by_id(&self.assignment_id, conn)?;
 
         let now = Local::now().naive_local();
 
-        let now_date = Local::today().naive_local();
+        let handed_in = on_time(self.due_at(&assignment), now);
 
-        let now_time = now.time();
+        let late = match transition {
+            Transition::TurnIn => handed_in == Some(false),
+            _ => self.is_late(&assignment, now),
+        };
 
-        let submitted = NaiveDateTime::new(now_date, now_time);
+        let from = self.state();
 
-        let due = match (assignment.due_date, assignment.due_time) {
-            (Some(a), Some(b)) => Some(NaiveDateTime::new(a, b)),
-            (Some(a), None) => Some(NaiveDateTime::new(a, NaiveTime::from_hms(23, 59, 59))),
-            (None, Some(b)) => Some(NaiveDateTime::new(Local::today().naive_local(), b)),
-            (None, None) => None,
+        let to = match from.after(transition, late, self.resubmit_allowed) {
+            Some(s) => s,
+            None => return Err(ErrorKind::InvalidValue),
         };
 
-        let on_time = match due {
-            Some(d) => {
-                if submitted < d {
-                    Some(true)
-                } else {
-                    Some(false)
-                }
-            }
-            None => None,
+        let moved = match transition {
+            Transition::TurnIn => diesel::update(submissions::table.find(&self.submission_id))
+                .set((
+                    submissions::state.eq(to.to_string()),
+                    submissions::submitted.eq(true),
+                    submissions::submitted_date.eq(now.date()),
+                    submissions::submitted_time.eq(now.time()),
+                    submissions::on_time.eq(handed_in),
+                ))
+                .get_result::<Self>(conn)?,
+            // The date it was handed in stays for the record
+            _ => diesel::update(submissions::table.find(&self.submission_id))
+                .set((
+                    submissions::state.eq(to.to_string()),
+                    submissions::submitted.eq(to.is_submitted()),
+                ))
+                .get_result::<Self>(conn)?,
         };
 
-        diesel::update(
-            submissions::table.filter(submissions::submission_id.eq(&self.submission_id)),
-        )
-        .set((
-            submissions::submitted.eq(!self.submitted),
-            submissions::submitted_date.eq(&now_date),
-            submissions::submitted_time.eq(&now_time),
-            submissions::on_time.eq(on_time),
-        ))
-        .execute(conn)?;
+        if from != to || transition == Transition::Return {
+            SubmissionEvent::record(&moved, Some(from), actor_id, note, conn)?;
+        }
 
-        Ok(submissions::dsl::submissions
-            .find(&self.submission_id)
-            .get_result::<Self>(conn)?)
+        if transition == Transition::TurnIn {
+            Attempt::snapshot(&moved, conn)?;
+        }
+
+        Ok(moved)
     }
 
-    pub fn update_on_time(&self, assignment: &Assignment, conn: &PgConnection) -> ThearningResult<Self> {
+    /// Takes the work back, along with the rest of the team's, on behalf of
+    /// `actor_id`.
+    pub fn unsubmit(&self, actor_id: &String, conn: &PgConnection) -> ThearningResult<Self> {
+        let unsubmitted = self.transition(Transition::Unsubmit, Some(actor_id), None, conn)?;
 
-        let now = Local::now().naive_local();
+        for mate in self.teammates(conn)?.iter().filter(|s| s.submitted) {
+            mate.transition(Transition::Unsubmit, Some(actor_id), None, conn)?;
+        }
 
-        let now_date = Local::today().naive_local();
+        Ok(unsubmitted)
+    }
 
-        let now_time = now.time();
+    /// Hands the work in, for the whole team on group work. Teammates who
+    /// already handed in, or can't again, are left as they are. `actor_id`
+    /// is whoever on the team handed it in.
+    pub fn submit(&self, actor_id: &String, conn: &PgConnection) -> ThearningResult<Self> {
+        let submitted = self.transition(Transition::TurnIn, Some(actor_id), None, conn)?;
 
-        let submitted = NaiveDateTime::new(now_date, now_time);
+        let pending = self
+            .teammates(conn)?
+            .into_iter()
+            .filter(|s| !s.submitted && s.state().after(Transition::TurnIn, false, s.resubmit_allowed).is_some());
 
-        let due = match (assignment.due_date, assignment.due_time) {
-            (Some(a), Some(b)) => Some(NaiveDateTime::new(a, b)),
-            (Some(a), None) => Some(NaiveDateTime::new(a, NaiveTime::from_hms(23, 59, 59))),
-            (None, Some(b)) => Some(NaiveDateTime::new(Local::today().naive_local(), b)),
-            (None, None) => None,
-        };
+        for mate in pending {
+            mate.transition(Transition::TurnIn, Some(actor_id), None, conn)?;
+        }
 
-        let on_time = match due {
-            Some(d) => {
-                if submitted < d {
-                    Some(true)
-                } else {
-                    Some(false)
-                }
-            }
-            None => None,
-        };
+        Ok(submitted)
+    }
+
+    /// Sends the work back to the student, or to the whole team. They may
+    /// hand it in again only when `allow_resubmit` is set.
+    pub fn give_back(
+        &self,
+        returned: ReturnedWork,
+        teacher_id: &String,
+        conn: &PgConnection,
+    ) -> ThearningResult<Self> {
+        for mate in self.teammates(conn)? {
+            mate.transition(Transition::Return, Some(teacher_id), returned.feedback.clone(), conn)?;
+
+            diesel::update(submissions::table.find(&mate.submission_id))
+                .set(submissions::resubmit_allowed.eq(returned.allow_resubmit))
+                .execute(conn)?;
+        }
+
+        let given_back = self.transition(Transition::Return, Some(teacher_id), returned.feedback, conn)?;
+
+        Ok(diesel::update(submissions::table.find(&given_back.submission_id))
+            .set(submissions::resubmit_allowed.eq(returned.allow_resubmit))
+            .get_result::<Self>(conn)?)
+    }
+
+    /// Brings `on_time` up to date for work not handed in yet, which turns
+    /// work nobody handed in by the deadline into missing work. Work handed
+    /// back keeps going by when it was handed in.
+    pub fn update_on_time(&self, assignment: &Assignment, conn: &PgConnection) -> ThearningResult<Self> {
+        let at = self.timed_at(Local::now().naive_local(), conn)?;
 
         diesel::update(
             submissions::table.filter(submissions::submission_id.eq(&self.submission_id)),
         )
-        .set(submissions::on_time.eq(on_time))
+        .set(submissions::on_time.eq(on_time(self.due_at(assignment), at)))
         .execute(conn)?;
 
-        Ok(submissions::dsl::submissions
+        let updated = submissions::table
             .find(&self.submission_id)
-            .get_result::<Self>(conn)?)
+            .get_result::<Self>(conn)?;
+
+        updated.transition(Transition::Recheck, None, None, conn)
+    }
+
+    /// When this student's work is due. An extension without its own time
+    /// keeps the assignment's time of day.
+    pub fn due_at(&self, assignment: &Assignment) -> Option<NaiveDateTime> {
+        match self.due_date {
+            Some(date) => deadline(Some(date), self.due_time.or(assignment.due_time)),
+            None => assignment.due_at(),
+        }
+    }
+
+    /// Moves this student's deadline and records it in the history, then
+    /// works out `on_time` and any late penalty again against it.
+    pub fn extend(
+        &self,
+        extension: FillableExtension,
+        granted_by: &String,
+        conn: &PgConnection,
+    ) -> ThearningResult<Self> {
+        let assignment = Assignment::get_by_id(&self.assignment_id, conn)?;
+
+        let entry = Extension {
+            id: format!("{}{}", generate_random_id(), generate_random_id()),
+            submission_id: self.submission_id.clone(),
+            granted_by: Some(granted_by.clone()),
+            due_date: extension.due_date,
+            due_time: extension.due_date.and(extension.due_time),
+            reason: extension.reason,
+            created_at: Local::now().naive_local(),
+        };
+
+        diesel::insert_into(extensions::table)
+            .values(&entry)
+            .execute(conn)?;
+
+        let extended = diesel::update(submissions::table.find(&self.submission_id))
+            .set((
+                submissions::due_date.eq(entry.due_date),
+                submissions::due_time.eq(entry.due_time),
+            ))
+            .get_result::<Self>(conn)?;
+
+        let at = extended.timed_at(Local::now().naive_local(), conn)?;
+
+        let extended = diesel::update(submissions::table.find(&self.submission_id))
+            .set(submissions::on_time.eq(on_time(extended.due_at(&assignment), at)))
+            .get_result::<Self>(conn)?
+            .transition(Transition::Recheck, Some(granted_by), Some("Deadline changed".to_string()), conn)?;
+
+        match Mark::get_by_submission_id(&self.submission_id, conn) {
+            Ok(mark) => extended.mark(&mark.value, conn),
+            Err(ErrorKind::QueryError(Error::NotFound)) => Ok(extended),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Still to hand in, excused work aside, and not yet past this student's
+    /// own deadline.
+    pub fn needs_reminder(&self, assignment: &Assignment, now: NaiveDateTime) -> bool {
+        let due = self.due_at(assignment);
+
+        !self.submitted
+            && !self.excused
+            && due.map_or(true, |d| now < d)
+            && assignment.accepts_at(due, now)
+    }
+
+    pub fn submitted_at(&self) -> Option<NaiveDateTime> {
+        match (self.submitted, self.submitted_date, self.submitted_time) {
+            (true, Some(d), Some(t)) => Some(NaiveDateTime::new(d, t)),
+            _ => None,
+        }
+    }
+
+    /// When the work was last handed in, going by its attempts, so work
+    /// that's been returned or taken back since still has it.
+    pub fn handed_in_at(&self, conn: &PgConnection) -> ThearningResult<Option<NaiveDateTime>> {
+        Attempt::latest_at(&self.submission_id, conn)
+    }
+
+    /// Handed in, or handed back after it was.
+    pub fn handed_in(&self) -> bool {
+        self.submitted || self.state() == SubmissionState::Returned
+    }
+
+    /// What `on_time` goes by: the time the work was handed in while it's in
+    /// or has been handed back, otherwise `now`.
+    fn timed_at(&self, now: NaiveDateTime, conn: &PgConnection) -> ThearningResult<NaiveDateTime> {
+        match self.handed_in() {
+            true => Ok(self.handed_in_at(conn)?.unwrap_or(now)),
+            false => Ok(now),
+        }
+    }
+
+    /// Marks lost to the assignment's late policy. Work that was never
+    /// handed in, like paper exams marked offline, isn't late.
+    pub fn penalty(&self, assignment: &Assignment, conn: &PgConnection) -> ThearningResult<i32> {
+        Ok(self.penalty_at(assignment, self.handed_in_at(conn)?))
+    }
+
+    pub fn penalty_at(&self, assignment: &Assignment, at: Option<NaiveDateTime>) -> i32 {
+        match at {
+            Some(at) => assignment.penalty(self.due_at(assignment), at),
+            None => 0,
+        }
     }
 }
 
@@ -196,40 +614,23 @@ impl Manipulable<FillableSubmissions> for Submissions {
 
         let now = Local::now().naive_local();
 
-        let now_date = Local::today().naive_local();
-
-        let now_time = now.time();
-
-        let submitted = NaiveDateTime::new(now_date, now_time);
-
-        let due = match (assignment.due_date, assignment.due_time) {
-            (Some(a), Some(b)) => Some(NaiveDateTime::new(a, b)),
-            (Some(a), None) => Some(NaiveDateTime::new(a, NaiveTime::from_hms(23, 59, 59))),
-            (None, Some(b)) => Some(NaiveDateTime::new(Local::today().naive_local(), b)),
-            (None, None) => None,
-        };
-
-        let on_time = match due {
-            Some(d) => {
-                if submitted < d {
-                    Some(true)
-                } else {
-                    Some(false)
-                }
-            }
-            None => None,
-        };
-        
         let submission = Submissions {
             submission_id: format!("{}{}", generate_random_id(), generate_random_id()),
             assignment_id: new_data.assignment_id,
             user_id: new_data.user_id,
-            submitted_date: Some(Local::today().naive_local()),
-            submitted_time: Some(Local::now().naive_local().time()),
-            on_time,
+            submitted_date: Some(now.date()),
+            submitted_time: Some(now.time()),
+            on_time: on_time(assignment.due_at(), now),
             marks_allotted: None,
             submitted: false,
             created_at: Local::now().naive_local(),
+            excused: false,
+            due_date: None,
+            due_time: None,
+            state: SubmissionState::Assigned.to_string(),
+            resubmit_allowed: false,
+            group_id: new_data.group_id,
+            mark_adjustment: 0,
         };
 
         diesel::insert_into(submissions::table)
@@ -240,6 +641,8 @@ impl Manipulable<FillableSubmissions> for Submissions {
             .find(submission.submission_id)
             .get_result::<Self>(conn)?;
 
+        SubmissionEvent::record(&res, None, None, None, conn)?;
+
         Ok(res)
     }
 
@@ -256,6 +659,48 @@ impl Manipulable<FillableSubmissions> for Submissions {
     }
 }
 
+impl Extension {
+    /// The submission's extensions, oldest first.
+    pub fn load_by_submission(submission_id: &String, conn: &PgConnection) -> ThearningResult<Vec<Self>> {
+        Ok(extensions::table
+            .filter(extensions::submission_id.eq(submission_id))
+            .order(extensions::created_at.asc())
+            .load::<Self>(conn)?)
+    }
+}
+
+impl SubmissionEvent {
+    fn record(
+        submission: &Submissions,
+        from: Option<SubmissionState>,
+        actor_id: Option<&String>,
+        note: Option<String>,
+        conn: &PgConnection,
+    ) -> ThearningResult<Self> {
+        let event = Self {
+            id: format!("{}{}", generate_random_id(), generate_random_id()),
+            submission_id: submission.submission_id.clone(),
+            from_state: from.map(|s| s.to_string()),
+            to_state: submission.state.clone(),
+            actor_id: actor_id.cloned(),
+            note,
+            created_at: Local::now().naive_local(),
+        };
+
+        Ok(diesel::insert_into(submission_events::table)
+            .values(&event)
+            .get_result::<Self>(conn)?)
+    }
+
+    /// The submission's history, oldest first.
+    pub fn load_by_submission(submission_id: &String, conn: &PgConnection) -> ThearningResult<Vec<Self>> {
+        Ok(submission_events::table
+            .filter(submission_events::submission_id.eq(submission_id))
+            .order(submission_events::created_at.asc())
+            .load::<Self>(conn)?)
+    }
+}
+
 impl Mark {
     pub fn get_by_id(id: &str, conn: &PgConnection) -> ThearningResult<Self> {
         Ok(marks::table
@@ -284,6 +729,7 @@ impl Manipulable<FillableMark> for Mark {
             student_id: Option::from(submission.user_id),
             value: new_data.value,
             created_at: Local::now().naive_local(),
+            as_of: new_data.as_of,
         };
 
         diesel::insert_into(marks::table)
@@ -306,6 +752,7 @@ impl Manipulable<FillableMark> for Mark {
             student_id: self.student_id.clone(),
             value: update.value,
             created_at: self.created_at,
+            as_of: update.as_of,
         };
 
         diesel::update(marks::table.find(&mark.id))
@@ -1,4 +1,4 @@
-use chrono::Local;
+use chrono::{Local, NaiveDateTime};
 use diesel::dsl::any;
 use diesel::prelude::*;
 use diesel::{QueryDsl, RunQueryDsl};
@@ -8,42 +8,58 @@ use rocket::serde::json::serde_json::json;
 use rocket::serde::json::Json;
 use rocket::{self, routes};
 use rocket_dyn_templates::handlebars::JsonValue;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
 
-use crate::auth::{ApiKey, ClassGuard};
+use kosuzers::extractor::{extract_marks, read_table, MarkRow, RowError};
+
+use crate::attempts::models::Attempt;
+use crate::audit::models::{AuditLog, FillableAuditLog};
+use crate::auth::ApiKey;
+use crate::policy::{class_submission, member_submission, published_assignment, Can, GradeSubmission, GrantExtension, SubmitAssignment, ViewClass, ViewSubmissions};
+use crate::rubrics::models::{CriterionScore, Rubric};
 use crate::db;
+use crate::errors::ErrorKind;
+use crate::lockouts::utils::client_ip;
 use crate::schema::submissions::dsl::submissions;
-use crate::submissions::models::{FillableMark, FillableSubmissions, Mark, Submissions};
-use crate::traits::Manipulable;
-use crate::users::models::User;
-use crate::utils::update;
+use crate::submissions::models::{
+    Excusal, Extension, FillableExtension, FillableMark, Mark, MarkUpload,
+    ReturnedWork, SubmissionEvent, SubmissionState, Submissions,
+};
+use crate::traits::{ClassUser, Manipulable};
+use crate::users::models::{Student, User};
+use crate::utils::read_upload;
 
 #[post("/<class_id>/submissions/<submission_id>/submit")]
 pub fn submit_submission(
-    key: ClassGuard,
+    key: Can<SubmitAssignment>,
     class_id: &str,
     submission_id: &str,
     conn: db::DbConn,
 ) -> Result<Status, Status> {
-    let user = match User::find_user(&key.0, &conn) {
-        Ok(user) => user,
-        Err(_) => return Err(Status::NotFound),
-    };
-
-    let submission = match Submissions::find_submission(&submission_id.to_string(), &conn) {
-        Ok(sub) => sub,
-        Err(_) => return Err(Status::NotFound),
-    };
-
-    match Submissions::get_by_id(&submission.assignment_id, &user.user_id, &conn) {
-        Ok(_) => (),
-        Err(_) => return Err(Status::Unauthorized),
-    };
+    let (submission, assignment) = member_submission(&key.0, key.1, class_id, submission_id, &conn)?;
 
     if submission.submitted {
         return Err(Status::BadRequest);
     }
 
-    match submission.submit(&conn) {
+    if submission.state() == SubmissionState::Returned && !submission.resubmit_allowed {
+        return Err(Status::Forbidden);
+    }
+
+    if !assignment.accepts_at(submission.due_at(&assignment), Local::now().naive_local()) {
+        return Err(Status::Gone);
+    }
+
+    if let Some(max) = assignment.max_attempts {
+        match Attempt::count(&submission.submission_id, &conn) {
+            Ok(n) if n >= max as i64 => return Err(Status::Conflict),
+            Ok(_) => {}
+            Err(_) => return Err(Status::InternalServerError),
+        }
+    }
+
+    match conn.transaction::<_, ErrorKind, _>(|| submission.submit(&key.0, &conn)) {
         Ok(_) => Ok(Status::Ok),
         Err(_) => Err(Status::InternalServerError),
     }
@@ -51,39 +67,109 @@ pub fn submit_submission(
 
 #[post("/<class_id>/submissions/<submission_id>/unsubmit")]
 pub fn unsubmit_submission(
-    key: ClassGuard,
+    key: Can<SubmitAssignment>,
     class_id: &str,
     submission_id: &str,
     conn: db::DbConn,
 ) -> Result<Status, Status> {
-    let user = match User::find_user(&key.0, &conn) {
-        Ok(user) => user,
-        Err(_) => return Err(Status::NotFound),
-    };
-
-    let submission = match Submissions::find_submission(&submission_id.to_string(), &conn) {
-        Ok(sub) => sub,
-        Err(_) => return Err(Status::NotFound),
-    };
-
-    match Submissions::get_by_id(&submission.assignment_id, &user.user_id, &conn) {
-        Ok(_) => (),
-        Err(_) => return Err(Status::Unauthorized),
-    };
+    let (submission, _) = member_submission(&key.0, key.1, class_id, submission_id, &conn)?;
 
     if !submission.submitted {
         return Err(Status::BadRequest);
     }
 
-    match submission.submit(&conn) {
+    match conn.transaction::<_, ErrorKind, _>(|| submission.unsubmit(&key.0, &conn)) {
         Ok(_) => Ok(Status::Ok),
         Err(_) => Err(Status::InternalServerError),
     }
 }
 
+/// With rubric scores the mark is their total, `value` only counts for a
+/// plain mark.
+fn marked_value(submission: &Submissions, mark: &FillableMark, conn: &PgConnection) -> Result<(Rubric, i32), Status> {
+    let rubric = match Rubric::load(&submission.assignment_id, conn) {
+        Ok(r) => r,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    if mark.rubric.is_empty() {
+        return Ok((rubric, mark.value));
+    }
+
+    match rubric.total(&mark.rubric) {
+        Some(total) => Ok((rubric, total)),
+        None => Err(Status::UnprocessableEntity),
+    }
+}
+
+/// Gives the mark to everyone on the team, after setting the members'
+/// adjustments, and returns the mark given on `submission`. Work done alone
+/// is a team of one. `as_of` is when the marked attempt was handed in, if
+/// it wasn't the latest one.
+pub fn give_team_mark(
+    submission: &Submissions,
+    marker_id: &String,
+    mark: &FillableMark,
+    value: i32,
+    rubric: &Rubric,
+    as_of: Option<NaiveDateTime>,
+    conn: &PgConnection,
+) -> Result<(Mark, Vec<CriterionScore>, Vec<Submissions>), ErrorKind> {
+    for member in submission.team(conn)? {
+        if let Some(adjustment) = mark.adjustments.get(&member.user_id) {
+            member.adjust(*adjustment, conn)?;
+        }
+    }
+
+    let mut given = None;
+
+    for member in submission.team(conn)? {
+        let data = FillableMark {
+            submission_id: Some(member.submission_id.clone()),
+            marker_id: Some(marker_id.clone()),
+            student_id: Some(member.user_id.clone()),
+            value,
+            rubric: Vec::new(),
+            adjustments: HashMap::new(),
+            as_of,
+        };
+
+        let member_mark = match Mark::get_by_submission_id(&member.submission_id, conn) {
+            Ok(m) => m.update(data, conn)?,
+            Err(ErrorKind::QueryError(diesel::result::Error::NotFound)) => Mark::create(data, conn)?,
+            Err(e) => return Err(e),
+        };
+
+        let scores = CriterionScore::save(&member_mark.id, rubric, &mark.rubric, conn)?;
+
+        member.mark(&member_mark.value, conn)?;
+
+        if member.submission_id == submission.submission_id {
+            given = Some((member_mark, scores));
+        }
+    }
+
+    let (given, scores) = given.ok_or(ErrorKind::InvalidValue)?;
+
+    Ok((given, scores, submission.team(conn)?))
+}
+
+/// Adjustments can only go to members of the team.
+fn check_adjustments(submission: &Submissions, mark: &FillableMark, conn: &PgConnection) -> Result<(), Status> {
+    let members = match submission.team(conn) {
+        Ok(t) => t.into_iter().map(|s| s.user_id).collect::<HashSet<String>>(),
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    match mark.adjustments.keys().all(|u| members.contains(u)) {
+        true => Ok(()),
+        false => Err(Status::UnprocessableEntity),
+    }
+}
+
 #[post("/<class_id>/submissions/<submission_id>/mark", data = "<mark>")]
 pub fn mark_submission(
-    key: ClassGuard,
+    key: Can<GradeSubmission>,
     class_id: &str,
     mark: Json<FillableMark>,
     submission_id: &str,
@@ -97,37 +183,36 @@ pub fn mark_submission(
         Err(_) => return Err(Status::NotFound),
     };
 
-    if user.is_student() {
-        return Err(Status::Unauthorized);
-    }
-
-    let submission = match Submissions::find_submission(&submission_id.to_string(), &conn) {
-        Ok(sub) => sub,
-        Err(_) => return Err(Status::NotFound),
-    };
+    let (submission, _) = class_submission(class_id, submission_id, &conn)?;
 
     if submission.marks_allotted.is_some() {
         return Err(Status::Conflict);
     }
 
-    let new_mark = FillableMark {
-        marker_id: Option::from(user.user_id),
-        ..mark
-    };
+    let (rubric, value) = marked_value(&submission, &mark, &conn)?;
+
+    check_adjustments(&submission, &mark, &conn)?;
 
-    let mark = Mark::create(new_mark, &conn).unwrap();
+    let marked = conn.transaction::<_, ErrorKind, _>(|| {
+        give_team_mark(&submission, &user.user_id, &mark, value, &rubric, None, &conn)
+    });
 
-    submission.mark(&mark.value, &conn).unwrap();
+    let (mark, scores, team) = match marked {
+        Ok(m) => m,
+        Err(_) => return Err(Status::InternalServerError),
+    };
 
     Ok(Json(json!({
         "mark": mark,
+        "rubric": scores,
+        "team": team,
     })))
 
 }
 
 #[patch("/<class_id>/submissions/<submission_id>/mark", data = "<mark>")]
 pub fn update_mark(
-    key: ClassGuard,
+    key: Can<GradeSubmission>,
     class_id: &str,
     mark: Json<FillableMark>,
     submission_id: &str,
@@ -141,17 +226,354 @@ pub fn update_mark(
         Err(_) => return Err(Status::NotFound),
     };
 
-    if user.is_student() {
-        return Err(Status::Unauthorized);
+    let (submission, _) = class_submission(class_id, submission_id, &conn)?;
+
+    if Mark::get_by_submission_id(&submission.submission_id, &conn).is_err() {
+        return Err(Status::NotFound);
     }
 
-    let this_mark = Mark::get_by_submission_id(&submission_id.to_string(), &conn).unwrap();
+    let (rubric, value) = marked_value(&submission, &mark, &conn)?;
 
-    let submission = Submissions::find_submission(&submission_id.to_string(), &conn).unwrap();
+    check_adjustments(&submission, &mark, &conn)?;
 
-    let up = update(this_mark, mark, &conn).unwrap();
+    let updated = conn.transaction::<_, ErrorKind, _>(|| {
+        give_team_mark(&submission, &user.user_id, &mark, value, &rubric, None, &conn)
+    });
 
-    submission.mark(&up.value, &conn).unwrap();
+    match updated {
+        Ok(_) => Ok(Status::Ok),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
 
-    Ok(Status::Ok)
+/// Excused work doesn't count towards the student's course grade.
+#[patch("/<class_id>/submissions/<submission_id>/excused", data = "<excusal>")]
+pub fn excuse_submission(
+    key: Can<GradeSubmission>,
+    class_id: &str,
+    submission_id: &str,
+    excusal: Json<Excusal>,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let (submission, _) = class_submission(class_id, submission_id, &conn)?;
+
+    match submission.excuse(excusal.excused, &conn) {
+        Ok(s) => Ok(Json(json!({ "submission": s }))),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+/// Hands the work back with feedback, which lands in the submission's
+/// history. The student can hand it in again only with `allow_resubmit`.
+#[post("/<class_id>/submissions/<submission_id>/return", data = "<returned>")]
+pub fn return_submission(
+    key: Can<GradeSubmission>,
+    class_id: &str,
+    submission_id: &str,
+    returned: Json<ReturnedWork>,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let (submission, _) = class_submission(class_id, submission_id, &conn)?;
+
+    let returned = returned.into_inner();
+
+    match conn.transaction::<_, ErrorKind, _>(|| submission.give_back(returned, &key.0, &conn)) {
+        Ok(s) => Ok(Json(json!({ "submission": s }))),
+        Err(ErrorKind::InvalidValue) => Err(Status::Conflict),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+/// Every state the submission went through. Students only see their own.
+#[get("/<class_id>/submissions/<submission_id>/history")]
+pub fn submission_history(
+    key: Can<ViewClass>,
+    class_id: &str,
+    submission_id: &str,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let (submission, _) = member_submission(&key.0, key.1, class_id, submission_id, &conn)?;
+
+    match SubmissionEvent::load_by_submission(&submission.submission_id, &conn) {
+        Ok(h) => Ok(Json(json!({ "state": submission.state, "history": h }))),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+/// Gives one student their own deadline, or takes them back to the
+/// assignment's when `due_date` is left out. Every change is kept in the
+/// submission's extension history.
+#[patch("/<class_id>/submissions/<submission_id>/extension", data = "<extension>")]
+pub fn extend_submission(
+    key: Can<GrantExtension>,
+    class_id: &str,
+    submission_id: &str,
+    extension: Json<FillableExtension>,
+    ip: Option<IpAddr>,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let extension = extension.into_inner();
+
+    let (submission, _) = class_submission(class_id, submission_id, &conn)?;
+
+    if extension.due_date.is_none() && extension.due_time.is_some() {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let detail = match extension.due_date {
+        Some(d) => format!("submission {} due {}", submission.submission_id, d),
+        None => format!("submission {} back to the assignment's deadline", submission.submission_id),
+    };
+
+    let ip = client_ip(ip);
+
+    let extended = conn.transaction::<_, ErrorKind, _>(|| {
+        let extended = submission.extend(extension, &key.0, &conn)?;
+
+        AuditLog::create(
+            FillableAuditLog {
+                action: "extension_granted",
+                user_id: Some(&extended.user_id),
+                actor_id: Some(&key.0),
+                ip: Some(&ip),
+                detail: Some(detail),
+            },
+            &conn,
+        )?;
+
+        Ok(extended)
+    });
+
+    let extended = match extended {
+        Ok(s) => s,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let history = match Extension::load_by_submission(&extended.submission_id, &conn) {
+        Ok(h) => h,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    Ok(Json(json!({ "submission": extended, "extensions": history })))
+}
+
+#[get("/<class_id>/submissions/<submission_id>/extensions")]
+pub fn extension_history(
+    key: Can<ViewSubmissions>,
+    class_id: &str,
+    submission_id: &str,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let (submission, _) = class_submission(class_id, submission_id, &conn)?;
+
+    match Extension::load_by_submission(&submission.submission_id, &conn) {
+        Ok(h) => Ok(Json(json!({ "extensions": h }))),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+fn mark_row(row: &MarkRow, previous: Option<i32>) -> JsonValue {
+    json!({
+        "row": row.row,
+        "user_id": row.user_id,
+        "mark": row.mark,
+        "previous": previous,
+    })
+}
+
+/// Marks an assignment from an .xlsx or CSV sheet of `user_id, mark` pairs,
+/// for students the assignment was given to.
+/// A mark on group work goes to the whole team, so teammates can't be given
+/// different ones. With `dry_run` nothing is saved and the response previews
+/// which marks would replace existing ones. Nothing is saved either while
+/// any row is invalid.
+#[post("/<class_id>/assignments/<assignment_id>/marks/import?<dry_run>", data = "<upload>")]
+pub async fn import_marks<'a>(
+    key: Can<GradeSubmission>,
+    class_id: &str,
+    assignment_id: &str,
+    dry_run: Option<bool>,
+    upload: Form<MarkUpload<'a>>,
+    ip: Option<IpAddr>,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let dry_run = dry_run.unwrap_or(false);
+
+    let assignment = published_assignment(class_id, assignment_id, &conn)?;
+
+    let mut upload = upload.into_inner();
+
+    let bytes = match read_upload(&mut upload.file).await {
+        Ok(b) => b,
+        Err(_) => return Err(Status::BadRequest),
+    };
+
+    let sheet = match read_table(bytes) {
+        Ok(s) => s,
+        Err(_) => return Err(Status::UnprocessableEntity),
+    };
+
+    let parsed = match extract_marks(&sheet) {
+        Ok(m) => m,
+        Err(e) => {
+            return Ok(Json(json!({
+                "dry_run": dry_run,
+                "imported": 0,
+                "marks": [],
+                "conflicts": [],
+                "errors": [{"row": 1, "message": e.to_string()}],
+            })))
+        }
+    };
+
+    let enrolled = match Student::load_in_class(&class_id.to_string(), &conn) {
+        Ok(s) => s.into_iter().map(|s| s.user_id).collect::<HashSet<String>>(),
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let assignees = match assignment.assignees(&conn) {
+        Ok(a) => a.into_iter().collect::<HashSet<String>>(),
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let teams = match assignment.teams(&conn) {
+        Ok(t) => t,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let existing = match Submissions::load_by_assignment(&assignment.assignment_id, &conn) {
+        Ok(s) => s
+            .into_iter()
+            .map(|s| (s.user_id.clone(), s))
+            .collect::<HashMap<String, Submissions>>(),
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let mut errors = parsed.errors.clone();
+
+    // The first mark in the sheet for each team
+    let mut team_marks = HashMap::<&String, &MarkRow>::new();
+
+    for row in &parsed.rows {
+        if let Some(group_id) = teams.get(&row.user_id) {
+            match team_marks.get(group_id) {
+                Some(first) if first.mark != row.mark => errors.push(RowError {
+                    row: row.row,
+                    message: format!(
+                        "mark {} differs from the {} given to NIS {} on the same team",
+                        row.mark, first.mark, first.user_id
+                    ),
+                }),
+                Some(_) => {}
+                None => {
+                    team_marks.insert(group_id, row);
+                }
+            }
+        }
+
+        if !enrolled.contains(&row.user_id) {
+            errors.push(RowError {
+                row: row.row,
+                message: format!("NIS {} is not a student of this class", row.user_id),
+            });
+        } else if !assignees.contains(&row.user_id) {
+            errors.push(RowError {
+                row: row.row,
+                message: format!("NIS {} wasn't given this assignment", row.user_id),
+            });
+        }
+
+        if let Some(total) = assignment.total_marks {
+            if row.mark > total {
+                errors.push(RowError {
+                    row: row.row,
+                    message: format!("mark {} is over the total of {}", row.mark, total),
+                });
+            }
+        }
+    }
+
+    errors.sort_by_key(|e| e.row);
+
+    let previous = |row: &MarkRow| existing.get(&row.user_id).and_then(|s| s.marks_allotted);
+
+    let marks = parsed
+        .rows
+        .iter()
+        .map(|r| mark_row(r, previous(r)))
+        .collect::<Vec<_>>();
+
+    // Marks that would overwrite a different one already given
+    let conflicts = parsed
+        .rows
+        .iter()
+        .filter(|r| matches!(previous(r), Some(p) if p != r.mark))
+        .map(|r| mark_row(r, previous(r)))
+        .collect::<Vec<_>>();
+
+    let errors = errors
+        .iter()
+        .map(|e| json!({"row": e.row, "message": e.message}))
+        .collect::<Vec<_>>();
+
+    if dry_run || !errors.is_empty() {
+        return Ok(Json(json!({
+            "dry_run": dry_run,
+            "imported": 0,
+            "marks": marks,
+            "conflicts": conflicts,
+            "errors": errors,
+        })));
+    }
+
+    let rubric = match Rubric::load(&assignment.assignment_id, &conn) {
+        Ok(r) => r,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let ip = client_ip(ip);
+
+    let imported = conn.transaction::<_, ErrorKind, _>(|| {
+        // Assignees without a submission get theirs, in their team
+        if parsed.rows.iter().any(|r| !existing.contains_key(&r.user_id)) {
+            assignment.sync_submissions(&conn)?;
+        }
+
+        for row in &parsed.rows {
+            let submission = Submissions::get_by_id(&assignment.assignment_id, &row.user_id, &conn)?;
+
+            give_team_mark(&submission, &key.0, &FillableMark::plain(row.mark), row.mark, &rubric, None, &conn)?;
+        }
+
+        AuditLog::create(
+            FillableAuditLog {
+                action: "marks_imported",
+                user_id: None,
+                actor_id: Some(&key.0),
+                ip: Some(&ip),
+                detail: Some(format!(
+                    "{} marks for assignment {}, {} replaced",
+                    parsed.rows.len(),
+                    assignment.assignment_id,
+                    conflicts.len()
+                )),
+            },
+            &conn,
+        )?;
+
+        Ok(parsed.rows.len())
+    });
+
+    let imported = match imported {
+        Ok(n) => n,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    Ok(Json(json!({
+        "dry_run": false,
+        "imported": imported,
+        "marks": marks,
+        "conflicts": conflicts,
+        "errors": [],
+    })))
 }
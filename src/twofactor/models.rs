@@ -0,0 +1,168 @@
+use chrono::{Local, NaiveDateTime};
+use diesel;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{ErrorKind, ThearningResult};
+use crate::schema::{recovery_codes, two_factor};
+use crate::twofactor::utils::{generate_recovery_code, generate_totp_secret, verify_totp};
+use crate::users::models::User;
+use crate::utils::{generate_random_id, hash_secret};
+
+const RECOVERY_CODES: usize = 10;
+
+#[derive(Serialize, Deserialize, Queryable, Insertable, Associations, Clone)]
+#[belongs_to(User)]
+#[table_name = "two_factor"]
+pub struct TwoFactor {
+    pub user_id: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub enabled: bool,
+    pub last_step: Option<i64>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, Queryable, Insertable, Associations, Clone)]
+#[belongs_to(User)]
+#[table_name = "recovery_codes"]
+pub struct RecoveryCode {
+    pub id: String,
+    pub user_id: String,
+    pub code: String,
+    pub used_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TwoFactorCode<'a> {
+    pub code: &'a str,
+}
+
+impl TwoFactor {
+    pub fn find(uid: &String, conn: &PgConnection) -> ThearningResult<Self> {
+        Ok(two_factor::table.find(uid).get_result::<Self>(conn)?)
+    }
+
+    pub fn is_enabled(uid: &String, conn: &PgConnection) -> bool {
+        match Self::find(uid, conn) {
+            Ok(t) => t.enabled,
+            Err(_) => false,
+        }
+    }
+
+    /// Starts (or restarts) enrollment with a fresh secret. 2FA stays off
+    /// until the first code is confirmed.
+    pub fn enroll(uid: &String, conn: &PgConnection) -> ThearningResult<Self> {
+        diesel::delete(two_factor::table.find(uid)).execute(conn)?;
+
+        let new = Self {
+            user_id: uid.to_string(),
+            secret: generate_totp_secret(),
+            enabled: false,
+            last_step: None,
+            created_at: Local::now().naive_local(),
+        };
+
+        diesel::insert_into(two_factor::table)
+            .values(&new)
+            .execute(conn)?;
+
+        Ok(two_factor::table.find(uid).get_result::<Self>(conn)?)
+    }
+
+    /// Accepts a TOTP code once; replaying a code from an already used time
+    /// step fails.
+    pub fn check_code(&self, code: &str, conn: &PgConnection) -> ThearningResult<()> {
+        let now = Local::now().timestamp() as u64;
+
+        let step = match verify_totp(&self.secret, code, now) {
+            Some(s) => s as i64,
+            None => return Err(ErrorKind::InvalidValue),
+        };
+
+        if let Some(last) = self.last_step {
+            if step <= last {
+                return Err(ErrorKind::InvalidValue);
+            }
+        }
+
+        diesel::update(two_factor::table.find(&self.user_id))
+            .set(two_factor::last_step.eq(step))
+            .execute(conn)?;
+
+        Ok(())
+    }
+
+    /// Accepts either a TOTP code or an unused recovery code.
+    pub fn check_code_or_recovery(&self, code: &str, conn: &PgConnection) -> ThearningResult<()> {
+        match self.check_code(code, conn) {
+            Ok(_) => Ok(()),
+            Err(_) => RecoveryCode::consume(&self.user_id, code, conn),
+        }
+    }
+
+    pub fn enable(&self, conn: &PgConnection) -> ThearningResult<Vec<String>> {
+        diesel::update(two_factor::table.find(&self.user_id))
+            .set(two_factor::enabled.eq(true))
+            .execute(conn)?;
+
+        RecoveryCode::regenerate(&self.user_id, conn)
+    }
+
+    pub fn disable(&self, conn: &PgConnection) -> ThearningResult<()> {
+        diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(&self.user_id)))
+            .execute(conn)?;
+
+        diesel::delete(two_factor::table.find(&self.user_id)).execute(conn)?;
+
+        Ok(())
+    }
+}
+
+impl RecoveryCode {
+    /// Replaces all recovery codes of a user. The plain codes are only ever
+    /// returned here.
+    pub fn regenerate(uid: &String, conn: &PgConnection) -> ThearningResult<Vec<String>> {
+        diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(uid)))
+            .execute(conn)?;
+
+        let codes = (0..RECOVERY_CODES)
+            .map(|_| generate_recovery_code())
+            .collect::<Vec<String>>();
+
+        let rows = codes
+            .iter()
+            .map(|c| Self {
+                id: format!("{}{}", generate_random_id(), generate_random_id()),
+                user_id: uid.to_string(),
+                code: hash_secret(c),
+                used_at: None,
+                created_at: Local::now().naive_local(),
+            })
+            .collect::<Vec<Self>>();
+
+        diesel::insert_into(recovery_codes::table)
+            .values(&rows)
+            .execute(conn)?;
+
+        Ok(codes)
+    }
+
+    pub fn consume(uid: &String, code: &str, conn: &PgConnection) -> ThearningResult<()> {
+        let updated = diesel::update(
+            recovery_codes::table
+                .filter(recovery_codes::user_id.eq(uid))
+                .filter(recovery_codes::code.eq(hash_secret(code.trim())))
+                .filter(recovery_codes::used_at.is_null()),
+        )
+        .set(recovery_codes::used_at.eq(Local::now().naive_local()))
+        .execute(conn)?;
+
+        match updated {
+            0 => Err(ErrorKind::InvalidValue),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use rocket::http::Status;
+use rocket::serde::json::serde_json::json;
+use rocket::serde::json::Json;
+use rocket_dyn_templates::handlebars::JsonValue;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+use crate::auth::{new_session, read_challenge, ApiKey};
+use crate::db;
+use crate::lockouts::models::{AccountLock, Throttle};
+use crate::lockouts::utils::{client_ip, register_failed_login, send_lockout_mail};
+use crate::twofactor::models::{RecoveryCode, TwoFactor, TwoFactorCode};
+use crate::twofactor::utils::provisioning_uri;
+use crate::users::models::User;
+
+#[post("/")]
+fn enroll(key: ApiKey, conn: db::DbConn) -> Result<Json<JsonValue>, Status> {
+    let user = match User::find_user(&key.0, &conn) {
+        Ok(u) => u,
+        Err(_) => return Err(Status::NotFound),
+    };
+
+    if TwoFactor::is_enabled(&user.user_id, &conn) {
+        return Err(Status::Conflict);
+    }
+
+    let two_factor = match TwoFactor::enroll(&user.user_id, &conn) {
+        Ok(t) => t,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    Ok(Json(json!({
+        "secret": two_factor.secret,
+        "uri": provisioning_uri(&user.email, &two_factor.secret),
+    })))
+}
+
+#[post("/confirm", format = "application/json", data = "<data>")]
+fn confirm(
+    key: ApiKey,
+    data: Json<TwoFactorCode>,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let two_factor = match TwoFactor::find(&key.0, &conn) {
+        Ok(t) => t,
+        Err(_) => return Err(Status::NotFound),
+    };
+
+    if two_factor.enabled {
+        return Err(Status::Conflict);
+    }
+
+    if two_factor.check_code(data.code, &conn).is_err() {
+        return Err(Status::Unauthorized);
+    }
+
+    match two_factor.enable(&conn) {
+        Ok(codes) => Ok(Json(json!({ "recovery_codes": codes }))),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+#[post("/recovery", format = "application/json", data = "<data>")]
+fn regenerate_recovery(
+    key: ApiKey,
+    data: Json<TwoFactorCode>,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let two_factor = match TwoFactor::find(&key.0, &conn) {
+        Ok(t) if t.enabled => t,
+        _ => return Err(Status::NotFound),
+    };
+
+    if two_factor.check_code(data.code, &conn).is_err() {
+        return Err(Status::Unauthorized);
+    }
+
+    match RecoveryCode::regenerate(&two_factor.user_id, &conn) {
+        Ok(codes) => Ok(Json(json!({ "recovery_codes": codes }))),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+#[delete("/", format = "application/json", data = "<data>")]
+fn disable(key: ApiKey, data: Json<TwoFactorCode>, conn: db::DbConn) -> Result<Status, Status> {
+    let two_factor = match TwoFactor::find(&key.0, &conn) {
+        Ok(t) => t,
+        Err(_) => return Err(Status::NotFound),
+    };
+
+    if two_factor.enabled && two_factor.check_code_or_recovery(data.code, &conn).is_err() {
+        return Err(Status::Unauthorized);
+    }
+
+    match two_factor.disable(&conn) {
+        Ok(_) => Ok(Status::Ok),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Challenge<'a> {
+    challenge: &'a str,
+    code: &'a str,
+}
+
+#[post("/2fa", format = "application/json", data = "<data>")]
+async fn verify_challenge(
+    data: Json<Challenge<'_>>,
+    ip: Option<IpAddr>,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    let uid = match read_challenge(data.challenge) {
+        Ok(u) => u,
+        Err(_) => return Err(Status::Unauthorized),
+    };
+
+    let ip = client_ip(ip);
+
+    // Codes are short, so they get the same throttling as passwords
+    match AccountLock::find(&uid, &conn) {
+        Ok(Some(lock)) => match lock.throttle() {
+            Throttle::Open => {}
+            Throttle::Backoff(_) => return Err(Status::TooManyRequests),
+            Throttle::Locked(_) => return Err(Status::Locked),
+        },
+        Ok(None) => {}
+        Err(_) => return Err(Status::InternalServerError),
+    }
+
+    let two_factor = match TwoFactor::find(&uid, &conn) {
+        Ok(t) if t.enabled => t,
+        _ => return Err(Status::Unauthorized),
+    };
+
+    if two_factor.check_code_or_recovery(data.code, &conn).is_err() {
+        if let Ok(Some((user, until))) = register_failed_login(&uid, &ip, &conn) {
+            send_lockout_mail(user, until).await;
+        }
+        return Err(Status::Unauthorized);
+    }
+
+    if AccountLock::reset(&uid, &conn).is_err() {
+        return Err(Status::InternalServerError);
+    }
+
+    match new_session(&uid, &conn) {
+        Ok((token, refresh_token)) => Ok(Json(
+            json!({ "status": 200, "token": token, "refresh_token": refresh_token }),
+        )),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+pub fn mount(rocket: rocket::Rocket<rocket::Build>) -> rocket::Rocket<rocket::Build> {
+    rocket
+        .mount(
+            "/api/user/2fa",
+            routes![enroll, confirm, regenerate_recovery, disable],
+        )
+        .mount("/api/auth", routes![verify_challenge])
+}
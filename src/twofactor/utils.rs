@@ -0,0 +1,116 @@
+use hmac::{Hmac, Mac};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use rocket::http::RawStr;
+use sha1::Sha1;
+
+const STEP: u64 = 30;
+const DIGITS: u32 = 6;
+const ISSUER: &str = "Thearning";
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// 160 bits, as recommended by RFC 4226.
+pub fn generate_totp_secret() -> String {
+    let bytes = (0..20).map(|_| thread_rng().gen::<u8>()).collect::<Vec<u8>>();
+
+    base32_encode(&bytes)
+}
+
+pub fn generate_recovery_code() -> String {
+    let code = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(10)
+        .map(char::from)
+        .collect::<String>()
+        .to_lowercase();
+
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut res = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+
+        while bits >= 5 {
+            bits -= 5;
+            res.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+
+    if bits > 0 {
+        res.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+
+    res
+}
+
+pub fn base32_decode(data: &str) -> Option<Vec<u8>> {
+    let mut res = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for c in data.trim_end_matches('=').bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|x| *x == c.to_ascii_uppercase())? as u32;
+
+        buffer = (buffer << 5) | value;
+        bits += 5;
+
+        if bits >= 8 {
+            bits -= 8;
+            res.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(res)
+}
+
+/// RFC 4226 HOTP with dynamic truncation.
+pub fn hotp(secret: &[u8], counter: u64, digits: u32) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).unwrap();
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let binary = ((hash[offset] as u32 & 0x7f) << 24)
+        | ((hash[offset + 1] as u32) << 16)
+        | ((hash[offset + 2] as u32) << 8)
+        | hash[offset + 3] as u32;
+
+    binary % 10u32.pow(digits)
+}
+
+pub fn time_step(unix_time: u64) -> u64 {
+    unix_time / STEP
+}
+
+/// Checks a code against the current step and one step of clock drift on each
+/// side. Returns the matching step so it can't be used twice.
+pub fn verify_totp(secret: &str, code: &str, unix_time: u64) -> Option<u64> {
+    let secret = base32_decode(secret)?;
+    let code = code.trim().parse::<u32>().ok()?;
+    let current = time_step(unix_time);
+
+    [current.saturating_sub(1), current, current + 1]
+        .into_iter()
+        .find(|step| hotp(&secret, *step, DIGITS) == code)
+}
+
+/// The `otpauth://` URI authenticator apps read from a QR code.
+pub fn provisioning_uri(account: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        ISSUER,
+        RawStr::new(account).percent_encode(),
+        secret,
+        ISSUER,
+        DIGITS,
+        STEP
+    )
+}
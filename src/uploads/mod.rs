@@ -0,0 +1,3 @@
+pub mod models;
+pub(crate) mod routes;
+pub(crate) mod utils;
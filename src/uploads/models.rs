@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use chrono::{Duration, Local, NaiveDateTime};
+use diesel::prelude::*;
+use diesel::PgConnection;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{ErrorKind, ThearningResult};
+use crate::schema::{resumable_upload_chunks, resumable_uploads};
+use crate::storage;
+use crate::uploads::utils::UPLOAD_EXPIRY_HOURS;
+use crate::utils::generate_random_id;
+
+/// A tus upload. Once the last byte is in it points to the attachment it
+/// became.
+#[derive(Serialize, Deserialize, Queryable, Insertable, Clone)]
+#[table_name = "resumable_uploads"]
+pub struct ResumableUpload {
+    pub id: String,
+    pub uploader: String,
+    pub upload_length: i64,
+    pub upload_offset: i64,
+    pub filename: String,
+    pub filetype: Option<String>,
+    pub assignment_id: Option<String>,
+    pub announcement_id: Option<String>,
+    pub submission_id: Option<String>,
+    pub attachment_id: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+}
+
+/// The bytes one PATCH brought, starting at `chunk_offset`.
+#[derive(Serialize, Deserialize, Queryable, Insertable, Clone)]
+#[table_name = "resumable_upload_chunks"]
+pub struct UploadChunk {
+    pub id: String,
+    pub upload_id: String,
+    pub chunk_offset: i64,
+    pub size: i64,
+}
+
+impl UploadChunk {
+    pub fn key(&self) -> String {
+        chunk_key(&self.upload_id, &self.id)
+    }
+}
+
+pub fn chunk_key(upload_id: &str, chunk_id: &str) -> String {
+    format!("uploads/{}/{}", upload_id, chunk_id)
+}
+
+/// Removes the chunks from storage in the background.
+fn forget_chunks(chunks: Vec<UploadChunk>) {
+    tokio::task::spawn(async move {
+        if let Ok(backend) = storage::backend() {
+            for c in chunks {
+                backend.delete(&c.key()).await.ok();
+            }
+        }
+    });
+}
+
+impl ResumableUpload {
+    /// Starts an upload of `length` bytes. The metadata names the file, its
+    /// declared type and where it's to be attached, like the fields of a
+    /// regular upload.
+    pub fn create(
+        uploader: &String,
+        length: i64,
+        metadata: &HashMap<String, String>,
+        conn: &PgConnection,
+    ) -> ThearningResult<Self> {
+        let now = Local::now().naive_local();
+        let field = |k: &str| metadata.get(k).filter(|v| !v.is_empty()).cloned();
+
+        let upload = Self {
+            id: format!("{}{}", generate_random_id(), generate_random_id()),
+            uploader: uploader.clone(),
+            upload_length: length,
+            upload_offset: 0,
+            filename: field("filename").unwrap_or_default(),
+            filetype: field("filetype"),
+            assignment_id: field("assignment_id"),
+            announcement_id: field("announcement_id"),
+            submission_id: field("submission_id"),
+            attachment_id: None,
+            created_at: now,
+            expires_at: now + Duration::hours(UPLOAD_EXPIRY_HOURS),
+        };
+
+        Ok(diesel::insert_into(resumable_uploads::table)
+            .values(&upload)
+            .get_result::<Self>(conn)?)
+    }
+
+    pub fn find(id: &String, conn: &PgConnection) -> ThearningResult<Self> {
+        Ok(resumable_uploads::table.find(id).get_result::<Self>(conn)?)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at < Local::now().naive_local()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.upload_offset == self.upload_length
+    }
+
+    pub fn chunks(&self, conn: &PgConnection) -> ThearningResult<Vec<UploadChunk>> {
+        Ok(resumable_upload_chunks::table
+            .filter(resumable_upload_chunks::upload_id.eq(&self.id))
+            .order(resumable_upload_chunks::chunk_offset.asc())
+            .load::<UploadChunk>(conn)?)
+    }
+
+    /// Records a chunk already in storage as the bytes from the current
+    /// offset on, and pushes the expiry back. None when another chunk got
+    /// to that offset first.
+    pub fn append(&self, chunk_id: &String, size: i64, conn: &PgConnection) -> ThearningResult<Option<Self>> {
+        conn.transaction::<_, ErrorKind, _>(|| {
+            let updated = diesel::update(
+                resumable_uploads::table
+                    .find(&self.id)
+                    .filter(resumable_uploads::upload_offset.eq(self.upload_offset)),
+            )
+            .set((
+                resumable_uploads::upload_offset.eq(self.upload_offset + size),
+                resumable_uploads::expires_at.eq(Local::now().naive_local() + Duration::hours(UPLOAD_EXPIRY_HOURS)),
+            ))
+            .get_result::<Self>(conn)
+            .optional()?;
+
+            if updated.is_some() {
+                diesel::insert_into(resumable_upload_chunks::table)
+                    .values(&UploadChunk {
+                        id: chunk_id.clone(),
+                        upload_id: self.id.clone(),
+                        chunk_offset: self.upload_offset,
+                        size,
+                    })
+                    .execute(conn)?;
+            }
+
+            Ok(updated)
+        })
+    }
+
+    /// Points the finished upload at its attachment. The chunks aren't
+    /// needed anymore.
+    pub fn finish(&self, attachment_id: &String, conn: &PgConnection) -> ThearningResult<Self> {
+        let chunks = self.chunks(conn)?;
+
+        diesel::delete(resumable_upload_chunks::table.filter(resumable_upload_chunks::upload_id.eq(&self.id)))
+            .execute(conn)?;
+
+        forget_chunks(chunks);
+
+        Ok(diesel::update(resumable_uploads::table.find(&self.id))
+            .set(resumable_uploads::attachment_id.eq(attachment_id))
+            .get_result::<Self>(conn)?)
+    }
+
+    /// Drops the upload along with whatever chunks came in.
+    pub fn discard(&self, conn: &PgConnection) -> ThearningResult<usize> {
+        forget_chunks(self.chunks(conn)?);
+
+        Ok(diesel::delete(resumable_uploads::table.find(&self.id)).execute(conn)?)
+    }
+
+    /// Drops every upload past its expiry, finished or not. The attachments
+    /// finished ones became stay.
+    pub fn purge_expired(conn: &PgConnection) -> ThearningResult<usize> {
+        let expired = resumable_uploads::table
+            .filter(resumable_uploads::expires_at.lt(Local::now().naive_local()))
+            .load::<Self>(conn)?;
+
+        for upload in &expired {
+            upload.discard(conn)?;
+        }
+
+        Ok(expired.len())
+    }
+}
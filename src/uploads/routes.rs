@@ -0,0 +1,292 @@
+use diesel::prelude::*;
+use diesel::PgConnection;
+use rocket::data::{Data, Limits, ToByteUnit};
+use rocket::http::{ContentType, Status};
+use rocket::serde::json::serde_json::json;
+
+use crate::attachments::models::{Attachment, FillableAttachment};
+use crate::auth::ApiKey;
+use crate::db;
+use crate::errors::ThearningResult;
+use crate::files::models::{UploadError, UploadType, UploadedFile};
+use crate::files::routes::{store_attachment, Contents};
+use crate::storage;
+use crate::uploads::models::{chunk_key, ResumableUpload, UploadChunk};
+use crate::uploads::utils::{http_date, parse_metadata, TusHeaders, TusResponse, HEAD_SIZE, TUS_EXTENSIONS, TUS_VERSION};
+use crate::utils::generate_random_id;
+
+fn unsupported_version() -> TusResponse {
+    TusResponse::new(Status::PreconditionFailed).header("Tus-Version", TUS_VERSION)
+}
+
+fn rejected(e: UploadError) -> TusResponse {
+    TusResponse::new(e.status()).body(e.body())
+}
+
+/// The uploader's own upload, while it lasts.
+fn own_upload(key: &ApiKey, upload_id: &str, conn: &db::DbConn) -> Result<ResumableUpload, TusResponse> {
+    match ResumableUpload::find(&upload_id.to_string(), conn) {
+        Ok(u) if u.uploader != key.0 => Err(TusResponse::new(Status::NotFound)),
+        Ok(u) if u.is_expired() => Err(TusResponse::new(Status::Gone)),
+        Ok(u) => Ok(u),
+        Err(_) => Err(TusResponse::new(Status::NotFound)),
+    }
+}
+
+#[options("/")]
+fn tus_options() -> TusResponse {
+    TusResponse::new(Status::NoContent)
+        .header("Tus-Version", TUS_VERSION)
+        .header("Tus-Extension", TUS_EXTENSIONS)
+        .header("Tus-Max-Size", UploadType::ResumableFile.max_size())
+}
+
+/// Starts an upload. `Upload-Metadata` can carry `filename`, `filetype` and
+/// the `assignment_id`, `announcement_id` or `submission_id` to attach the
+/// file to. Uploads that have expired are cleared out on the way.
+#[post("/")]
+fn create_upload(key: ApiKey, tus: TusHeaders, conn: db::DbConn) -> TusResponse {
+    if !tus.supported() {
+        return unsupported_version();
+    }
+
+    let length = match tus.upload_length {
+        Some(l) => l,
+        None => return TusResponse::new(Status::BadRequest),
+    };
+
+    let limit = UploadType::ResumableFile.max_size();
+
+    if length as u64 > limit {
+        return rejected(UploadError::TooLarge { size: length as u64, limit });
+    }
+
+    let metadata = match parse_metadata(tus.upload_metadata.as_deref().unwrap_or("")) {
+        Ok(m) => m,
+        Err(_) => return TusResponse::new(Status::BadRequest),
+    };
+
+    if ResumableUpload::purge_expired(&conn).is_err() {
+        return TusResponse::new(Status::InternalServerError);
+    }
+
+    match ResumableUpload::create(&key.0, length, &metadata, &conn) {
+        Ok(u) => TusResponse::new(Status::Created)
+            .header("Location", format!("/api/upload/tus/{}", u.id))
+            .header("Upload-Expires", http_date(u.expires_at)),
+        Err(_) => TusResponse::new(Status::InternalServerError),
+    }
+}
+
+#[head("/<upload_id>")]
+fn upload_offset(key: ApiKey, upload_id: &str, tus: TusHeaders, conn: db::DbConn) -> TusResponse {
+    if !tus.supported() {
+        return unsupported_version();
+    }
+
+    let upload = match own_upload(&key, upload_id, &conn) {
+        Ok(u) => u,
+        Err(r) => return r,
+    };
+
+    TusResponse::new(Status::Ok)
+        .header("Upload-Offset", upload.upload_offset)
+        .header("Upload-Length", upload.upload_length)
+        .header("Upload-Expires", http_date(upload.expires_at))
+        .header("Cache-Control", "no-store")
+}
+
+/// Puts the chunks of a finished upload together into a file, inspected
+/// and stored like any other upload. Only the start of the file is read to
+/// tell what it is, the chunks are joined in storage.
+async fn assemble(upload: &ResumableUpload, chunks: Vec<UploadChunk>) -> Result<UploadedFile, UploadError> {
+    let backend = storage::backend()?;
+
+    let mut head = Vec::new();
+
+    for chunk in &chunks {
+        if head.len() >= HEAD_SIZE {
+            break;
+        }
+
+        head.extend(backend.get(&chunk.key()).await?);
+    }
+
+    head.truncate(HEAD_SIZE);
+
+    let parts = chunks.iter().map(|c| c.key()).collect::<Vec<String>>();
+    let declared = upload.filetype.as_deref().and_then(ContentType::parse_flexible);
+
+    let contents = Contents::Joined {
+        parts: &parts,
+        head,
+        size: upload.upload_length as u64,
+    };
+
+    store_attachment(contents, declared.as_ref(), &upload.filename, &UploadType::ResumableFile).await
+}
+
+/// Attaches the file the way a regular upload is attached.
+fn attach(upload: &ResumableUpload, file: &UploadedFile, conn: &PgConnection) -> ThearningResult<ResumableUpload> {
+    conn.transaction(|| {
+        let new_attachment = FillableAttachment {
+            file_id: Some(file.file_id.clone()),
+            link_id: None,
+            assignment_id: upload.assignment_id.as_deref(),
+            announcement_id: upload.announcement_id.as_deref(),
+            submission_id: upload.submission_id.as_deref(),
+            uploader: upload.uploader.as_str(),
+        };
+
+        let attachment = Attachment::create(new_attachment, conn)?;
+
+        upload.finish(&attachment.attachment_id, conn)
+    })
+}
+
+/// Takes the bytes from `Upload-Offset` on. As much of the body as the
+/// request limits allow is kept, the client carries on from the offset
+/// returned.
+#[patch("/<upload_id>", data = "<data>")]
+async fn upload_chunk(
+    key: ApiKey,
+    upload_id: &str,
+    tus: TusHeaders,
+    limits: &Limits,
+    data: Data<'_>,
+    conn: db::DbConn,
+) -> TusResponse {
+    if !tus.supported() {
+        return unsupported_version();
+    }
+
+    if tus.content_type.as_deref() != Some("application/offset+octet-stream") {
+        return TusResponse::new(Status::UnsupportedMediaType);
+    }
+
+    let upload = match own_upload(&key, upload_id, &conn) {
+        Ok(u) => u,
+        Err(r) => return r,
+    };
+
+    match tus.upload_offset {
+        Some(o) if o == upload.upload_offset && !upload.is_complete() => {}
+        Some(_) => return TusResponse::new(Status::Conflict),
+        None => return TusResponse::new(Status::BadRequest),
+    }
+
+    let remaining = (upload.upload_length - upload.upload_offset) as u64;
+    let limit = limits.get("file").unwrap_or(50.mebibytes()).min(remaining.bytes());
+
+    let bytes = match data.open(limit).into_bytes().await {
+        Ok(b) => b.into_inner(),
+        Err(_) => return TusResponse::new(Status::InternalServerError),
+    };
+
+    let mut upload = upload;
+
+    if !bytes.is_empty() {
+        let chunk_id = format!("{}{}", generate_random_id(), generate_random_id());
+        let chunk = chunk_key(&upload.id, &chunk_id);
+        let size = bytes.len() as i64;
+
+        let backend = match storage::backend() {
+            Ok(b) => b,
+            Err(_) => return TusResponse::new(Status::InternalServerError),
+        };
+
+        if backend.put(&chunk, bytes, "application/octet-stream").await.is_err() {
+            return TusResponse::new(Status::InternalServerError);
+        }
+
+        upload = match upload.append(&chunk_id, size, &conn) {
+            Ok(Some(u)) => u,
+            Ok(None) => {
+                backend.delete(&chunk).await.ok();
+                return TusResponse::new(Status::Conflict);
+            }
+            Err(_) => return TusResponse::new(Status::InternalServerError),
+        };
+
+        // A file turned down on inspection goes with its upload, and so
+        // does one that couldn't be attached, rather than leave the upload
+        // complete with nothing to show for it
+        if upload.is_complete() {
+            let chunks = match upload.chunks(&conn) {
+                Ok(c) => c,
+                Err(_) => return TusResponse::new(Status::InternalServerError),
+            };
+
+            let file = match assemble(&upload, chunks).await {
+                Ok(f) => f,
+                Err(e) => {
+                    upload.discard(&conn).ok();
+                    return rejected(e);
+                }
+            };
+
+            if attach(&upload, &file, &conn).is_err() {
+                file.remove(&conn).ok();
+                upload.discard(&conn).ok();
+                return TusResponse::new(Status::InternalServerError);
+            }
+        }
+    }
+
+    TusResponse::new(Status::NoContent)
+        .header("Upload-Offset", upload.upload_offset)
+        .header("Upload-Expires", http_date(upload.expires_at))
+}
+
+/// What a finished upload became, the same as `/api/upload` answers with.
+#[get("/<upload_id>")]
+fn finished_upload(key: ApiKey, upload_id: &str, conn: db::DbConn) -> TusResponse {
+    let upload = match own_upload(&key, upload_id, &conn) {
+        Ok(u) => u,
+        Err(r) => return r,
+    };
+
+    let attachment = match upload.attachment_id.as_ref().map(|id| Attachment::find(id, &conn)) {
+        Some(Ok(a)) => a,
+        Some(Err(_)) => return TusResponse::new(Status::NotFound),
+        None => return TusResponse::new(Status::Conflict),
+    };
+
+    let file = match attachment.file_id.as_ref().map(|id| UploadedFile::receive(id, &conn)) {
+        Some(Ok(f)) => f,
+        _ => return TusResponse::new(Status::NotFound),
+    };
+
+    TusResponse::new(Status::Ok).body(json!({"attachment": &attachment, "file": file}))
+}
+
+#[delete("/<upload_id>")]
+fn terminate_upload(key: ApiKey, upload_id: &str, tus: TusHeaders, conn: db::DbConn) -> TusResponse {
+    if !tus.supported() {
+        return unsupported_version();
+    }
+
+    let upload = match own_upload(&key, upload_id, &conn) {
+        Ok(u) => u,
+        Err(r) => return r,
+    };
+
+    match upload.discard(&conn) {
+        Ok(_) => TusResponse::new(Status::NoContent),
+        Err(_) => TusResponse::new(Status::InternalServerError),
+    }
+}
+
+pub fn mount(rocket: rocket::Rocket<rocket::Build>) -> rocket::Rocket<rocket::Build> {
+    rocket.mount(
+        "/api/upload/tus",
+        routes![
+            tus_options,
+            create_upload,
+            upload_offset,
+            upload_chunk,
+            finished_upload,
+            terminate_upload
+        ],
+    )
+}
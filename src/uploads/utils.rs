@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+
+use chrono::{Local, NaiveDateTime, TimeZone, Utc};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::Json;
+use rocket_dyn_templates::handlebars::JsonValue;
+
+use crate::errors::{ErrorKind, ThearningResult};
+
+pub const TUS_VERSION: &str = "1.0.0";
+
+pub const TUS_EXTENSIONS: &str = "creation,expiration,termination";
+
+/// Hours an upload is kept after the last chunk came in.
+pub const UPLOAD_EXPIRY_HOURS: i64 = 24;
+
+/// Bytes from the start of a finished upload that are read to tell what it
+/// is.
+pub const HEAD_SIZE: usize = 64 * 1024;
+
+/// Reads `Upload-Metadata`, comma separated pairs of a key and its value in
+/// base64. A key can come without a value.
+pub fn parse_metadata(header: &str) -> ThearningResult<HashMap<String, String>> {
+    let mut metadata = HashMap::new();
+
+    for pair in header.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+        let (key, value) = match pair.split_once(' ') {
+            Some((k, v)) => {
+                let bytes = base64::decode(v.trim()).map_err(|_| ErrorKind::InvalidValue)?;
+
+                (k, String::from_utf8(bytes).map_err(|_| ErrorKind::InvalidValue)?)
+            }
+            None => (pair, String::new()),
+        };
+
+        metadata.insert(key.to_string(), value);
+    }
+
+    Ok(metadata)
+}
+
+/// `Upload-Expires` wants an HTTP date.
+pub fn http_date(at: NaiveDateTime) -> String {
+    let utc = match Local.from_local_datetime(&at).single() {
+        Some(t) => t.with_timezone(&Utc),
+        None => Utc.from_utc_datetime(&at),
+    };
+
+    utc.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// The tus headers of a request, whichever are there.
+pub struct TusHeaders {
+    pub resumable: Option<String>,
+    pub upload_length: Option<i64>,
+    pub upload_offset: Option<i64>,
+    pub upload_metadata: Option<String>,
+    pub content_type: Option<String>,
+}
+
+impl TusHeaders {
+    pub fn supported(&self) -> bool {
+        self.resumable.as_deref() == Some(TUS_VERSION)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for TusHeaders {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let header = |name: &str| request.headers().get_one(name).map(|v| v.to_string());
+        let number = |name: &str| header(name).and_then(|v| v.parse::<i64>().ok()).filter(|n| *n >= 0);
+
+        request::Outcome::Success(TusHeaders {
+            resumable: header("Tus-Resumable"),
+            upload_length: number("Upload-Length"),
+            upload_offset: number("Upload-Offset"),
+            upload_metadata: header("Upload-Metadata"),
+            content_type: header("Content-Type"),
+        })
+    }
+}
+
+/// A status with the headers tus asks for. Every response says which
+/// version of the protocol it speaks.
+pub struct TusResponse {
+    pub status: Status,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Option<JsonValue>,
+}
+
+impl TusResponse {
+    pub fn new(status: Status) -> Self {
+        TusResponse {
+            status,
+            headers: Vec::new(),
+            body: None,
+        }
+    }
+
+    pub fn header(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: JsonValue) -> Self {
+        self.body = Some(body);
+        self
+    }
+}
+
+impl<'r> Responder<'r, 'static> for TusResponse {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = match self.body {
+            Some(body) => Json(body).respond_to(request)?,
+            None => Response::new(),
+        };
+
+        response.set_status(self.status);
+        response.set_raw_header("Tus-Resumable", TUS_VERSION);
+
+        for (name, value) in self.headers {
+            response.set_raw_header(name, value);
+        }
+
+        Ok(response)
+    }
+}
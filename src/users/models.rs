@@ -1,34 +1,44 @@
+use std::collections::HashMap;
 use std::fmt;
 
 use crate::errors::{ErrorKind, ThearningResult};
 use bcrypt::{hash, verify, DEFAULT_COST};
-use chrono::{Local, NaiveDate, NaiveDateTime};
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime};
 use diesel;
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use rocket::fs::TempFile;
 use serde::{Deserialize, Serialize};
 
+use crate::files::models::FileVariant;
 use crate::schema::admins;
+use crate::schema::password_resets;
 use crate::schema::students;
 use crate::schema::teachers;
 use crate::schema::users;
+use crate::schema::verification_resends;
 use crate::traits::{ClassUser, Manipulable};
-use crate::utils::{generate_random_id, NaiveDateForm};
+use crate::utils::{generate_random_id, generate_secret, hash_secret, split_token, NaiveDateForm};
 
+/// A user's role inside one class, resolved from the `students`, `teachers`
+/// and `admins` tables.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
 pub enum Role {
     Student,
     Teacher,
     Admin,
 }
 
-impl From<&str> for Role {
-    fn from(role: &str) -> Self {
+impl TryFrom<&str> for Role {
+    type Error = ErrorKind;
+
+    fn try_from(role: &str) -> Result<Self, Self::Error> {
         match role {
-            "admin" => Self::Admin,
-            "teacher" => Self::Teacher,
-            "student" => Self::Student,
-            _ => unimplemented!(),
+            "admin" => Ok(Self::Admin),
+            "teacher" => Ok(Self::Teacher),
+            "student" => Ok(Self::Student),
+            _ => Err(ErrorKind::InvalidValue),
         }
     }
 }
@@ -43,6 +53,48 @@ impl fmt::Display for Role {
     }
 }
 
+/// A user's role across the whole school, stored in `users.system_role`.
+/// Admins are school administrators and teachers may run classes; everyone
+/// gets their rights in a class from their role in it.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SystemRole {
+    User,
+    Teacher,
+    Admin,
+}
+
+impl SystemRole {
+    /// Teacher accounts and school admins can create classes and join them
+    /// as teachers, see `POST /api/user/<uid>/role`.
+    pub fn can_teach(&self) -> bool {
+        matches!(self, Self::Teacher | Self::Admin)
+    }
+}
+
+impl TryFrom<&str> for SystemRole {
+    type Error = ErrorKind;
+
+    fn try_from(role: &str) -> Result<Self, Self::Error> {
+        match role {
+            "admin" => Ok(Self::Admin),
+            "teacher" => Ok(Self::Teacher),
+            "user" => Ok(Self::User),
+            _ => Err(ErrorKind::InvalidValue),
+        }
+    }
+}
+
+impl fmt::Display for SystemRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SystemRole::Admin => write!(f, "admin"),
+            SystemRole::Teacher => write!(f, "teacher"),
+            SystemRole::User => write!(f, "user"),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Queryable, AsChangeset, Insertable, Associations, Clone)]
 #[table_name = "users"]
 pub struct User {
@@ -54,8 +106,9 @@ pub struct User {
     pub birth_place: String,
     pub birth_date: NaiveDate,
     pub bio: String,
-    pub status: String,
+    pub system_role: String,
     pub created_at: NaiveDateTime,
+    pub verified_at: Option<NaiveDateTime>,
 }
 
 #[derive(FromForm)]
@@ -67,11 +120,21 @@ pub struct InsertableUser<'a> {
     pub birth_place: String,
     pub birth_date: NaiveDateForm,
     pub bio: String,
-    pub status: String,
     pub image: Option<TempFile<'a>>,
     pub file_name: Option<String>,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct NewSystemRole {
+    pub system_role: SystemRole,
+}
+
+#[derive(FromForm)]
+pub struct RosterUpload<'a> {
+    pub file: TempFile<'a>,
+    pub class_id: Option<String>,
+}
+
 #[derive(FromForm)]
 pub struct UpdatableUser<'a> {
     pub fullname: String,
@@ -90,6 +153,35 @@ pub struct PasswordChange<'a> {
     pub new_password: &'a str,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct PasswordReset<'a> {
+    pub token: &'a str,
+    pub new_password: &'a str,
+}
+
+#[derive(Serialize, Deserialize, Queryable, Insertable, Associations, Clone)]
+#[belongs_to(User)]
+#[table_name = "password_resets"]
+pub struct ResetToken {
+    pub id: String,
+    pub user_id: String,
+    pub token: String,
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+/// A verification email sent again on request. Kept apart from login
+/// attempts, so resends neither count as failed logins nor eat their limits.
+#[derive(Serialize, Deserialize, Queryable, Insertable, Clone)]
+#[table_name = "verification_resends"]
+pub struct VerificationResend {
+    pub id: String,
+    pub user_id: Option<String>,
+    pub ip: String,
+    pub created_at: NaiveDateTime,
+}
+
 #[derive(
     Serialize, Deserialize, Queryable, AsChangeset, Insertable, Associations, Identifiable, Debug, Clone
 )]
@@ -151,12 +243,12 @@ impl User {
         }
     }
 
-    pub fn get_role(key_: &String, connection: &PgConnection) -> ThearningResult<Role> {
+    pub fn get_system_role(key_: &String, connection: &PgConnection) -> ThearningResult<SystemRole> {
         let res = users::table
             .filter(users::user_id.eq(key_))
             .get_result::<Self>(connection)?;
 
-        Ok(Role::from(res.status.as_str()))
+        SystemRole::try_from(res.system_role.as_str())
     }
 
     pub fn get_id_from_email(email: &String, connection: &PgConnection) -> ThearningResult<String> {
@@ -191,16 +283,146 @@ impl User {
         }
     }
 
+    pub fn set_system_role(&self, role: SystemRole, conn: &PgConnection) -> ThearningResult<Self> {
+        Ok(diesel::update(users::table.find(&self.user_id))
+            .set(users::system_role.eq(role.to_string()))
+            .get_result::<Self>(conn)?)
+    }
+
+    pub fn set_password(&self, new_password: &str, conn: &PgConnection) -> ThearningResult<()> {
+        let new_hashed = hash(new_password, DEFAULT_COST).unwrap();
+
+        diesel::update(users::table.filter(users::user_id.eq(&self.user_id)))
+            .set(users::password.eq(&new_hashed))
+            .execute(conn)?;
+
+        Ok(())
+    }
+
+    pub fn verify_email(&self, conn: &PgConnection) -> ThearningResult<Self> {
+        Ok(diesel::update(users::table.find(&self.user_id))
+            .set(users::verified_at.eq(Local::now().naive_local()))
+            .get_result::<Self>(conn)?)
+    }
+
+    pub fn is_verified(&self) -> bool {
+        self.verified_at.is_some()
+    }
+
     pub fn is_admin(&self) -> bool {
-        self.status == "admin"
+        self.system_role == SystemRole::Admin.to_string()
     }
+}
+
+pub const RESET_TOKEN_MINUTES: i64 = 60;
+
+impl ResetToken {
+    /// Issues a reset token for the user. Only its hash is stored; the returned
+    /// `<id>.<secret>` string is what goes into the emailed link.
+    pub fn create(uid: &String, conn: &PgConnection) -> ThearningResult<(Self, String)> {
+        let secret = generate_secret();
 
-    pub fn is_teacher(&self) -> bool {
-        self.status == "teacher"
+        let now = Local::now().naive_local();
+
+        let reset = Self {
+            id: format!("{}{}", generate_random_id(), generate_random_id()),
+            user_id: uid.to_string(),
+            token: hash_secret(&secret),
+            expires_at: now + Duration::minutes(RESET_TOKEN_MINUTES),
+            used_at: None,
+            created_at: now,
+        };
+
+        diesel::insert_into(password_resets::table)
+            .values(&reset)
+            .execute(conn)?;
+
+        let res = password_resets::table
+            .find(&reset.id)
+            .get_result::<Self>(conn)?;
+
+        let token = format!("{}.{}", res.id, secret);
+
+        Ok((res, token))
     }
 
-    pub fn is_student(&self) -> bool {
-        self.status == "student"
+    /// Finds the reset a token belongs to, rejecting used or expired ones.
+    pub fn from_token(token: &str, conn: &PgConnection) -> ThearningResult<Self> {
+        let (id, secret) = split_token(token)?;
+
+        let reset = password_resets::table
+            .find(id)
+            .get_result::<Self>(conn)?;
+
+        if reset.token != hash_secret(secret)
+            || reset.used_at.is_some()
+            || reset.expires_at < Local::now().naive_local()
+        {
+            return Err(ErrorKind::InvalidValue);
+        }
+
+        Ok(reset)
+    }
+
+    /// Marks the token as used so the link can't be replayed. None when it
+    /// was used in the meantime.
+    pub fn consume(&self, conn: &PgConnection) -> ThearningResult<Option<Self>> {
+        Ok(diesel::update(
+            password_resets::table
+                .find(&self.id)
+                .filter(password_resets::used_at.is_null()),
+        )
+        .set(password_resets::used_at.eq(Local::now().naive_local()))
+        .get_result::<Self>(conn)
+        .optional()?)
+    }
+}
+
+/// How long an account waits before another verification email is sent.
+pub const RESEND_COOLDOWN_MINUTES: i64 = 5;
+/// Resend requests one IP can make in an hour. Loose, since a whole school
+/// can sit behind one address.
+pub const RESEND_IP_LIMIT: i64 = 50;
+
+impl VerificationResend {
+    /// Records a resend request from the IP. `uid` is set only when an email
+    /// actually went out, so retries don't push the cooldown further away.
+    pub fn record(uid: Option<&String>, ip: &str, conn: &PgConnection) -> ThearningResult<Self> {
+        let resend = Self {
+            id: format!("{}{}", generate_random_id(), generate_random_id()),
+            user_id: uid.cloned(),
+            ip: ip.to_string(),
+            created_at: Local::now().naive_local(),
+        };
+
+        Ok(diesel::insert_into(verification_resends::table)
+            .values(&resend)
+            .get_result::<Self>(conn)?)
+    }
+
+    /// Whether the account was sent a verification email within the cooldown.
+    pub fn cooling_down(uid: &String, conn: &PgConnection) -> ThearningResult<bool> {
+        let since = Local::now().naive_local() - Duration::minutes(RESEND_COOLDOWN_MINUTES);
+
+        Ok(diesel::select(diesel::dsl::exists(
+            verification_resends::table
+                .filter(verification_resends::user_id.eq(uid))
+                .filter(verification_resends::created_at.gt(since)),
+        ))
+        .get_result(conn)?)
+    }
+
+    /// Whether the IP has used up its resends for the last hour.
+    pub fn ip_exhausted(ip: &str, conn: &PgConnection) -> ThearningResult<bool> {
+        let since = Local::now().naive_local() - Duration::hours(1);
+
+        let count: i64 = verification_resends::table
+            .filter(verification_resends::ip.eq(ip))
+            .filter(verification_resends::created_at.gt(since))
+            .count()
+            .get_result(conn)?;
+
+        Ok(count >= RESEND_IP_LIMIT)
     }
 }
 
@@ -209,12 +431,34 @@ pub struct ResponseUser {
     pub user_id: String,
     pub fullname: String,
     pub profile_photo: String,
+    /// The avatar sizes of the photo by name, see
+    /// [`UploadType::variants`](crate::files::models::UploadType::variants)
+    #[serde(default)]
+    pub profile_photo_variants: HashMap<String, String>,
     pub email: String,
     pub birth_place: String,
     pub birth_date: NaiveDate,
     pub bio: String,
-    pub status: String,
+    pub system_role: String,
     pub created_at: NaiveDateTime,
+    pub verified_at: Option<NaiveDateTime>,
+}
+
+impl ResponseUser {
+    /// The users along with the sizes their profile photos come in, looked
+    /// up together.
+    pub fn load(users: Vec<User>, conn: &PgConnection) -> ThearningResult<Vec<Self>> {
+        let photos = users.iter().map(|u| u.profile_photo.clone()).collect::<Vec<_>>();
+        let variants = FileVariant::urls_of_all(&photos, conn)?;
+
+        Ok(users
+            .into_iter()
+            .map(|user| Self {
+                profile_photo_variants: variants.get(&user.profile_photo).cloned().unwrap_or_default(),
+                ..Self::from(user)
+            })
+            .collect())
+    }
 }
 
 impl From<User> for ResponseUser {
@@ -223,12 +467,14 @@ impl From<User> for ResponseUser {
             user_id: data.user_id,
             fullname: data.fullname,
             profile_photo: data.profile_photo,
+            profile_photo_variants: HashMap::new(),
             email: data.email,
             birth_place: data.birth_place,
             birth_date: data.birth_date,
             bio: data.bio,
-            status: data.status,
+            system_role: data.system_role,
             created_at: data.created_at,
+            verified_at: data.verified_at,
         }
     }
 }
@@ -242,12 +488,14 @@ impl FromIterator<User> for Vec<ResponseUser> {
                 user_id: i.user_id,
                 fullname: i.fullname,
                 profile_photo: i.profile_photo,
+                profile_photo_variants: HashMap::new(),
                 email: i.email,
                 birth_place: i.birth_place,
                 birth_date: i.birth_date,
                 bio: i.bio,
-                status: i.status,
+                system_role: i.system_role,
                 created_at: i.created_at,
+                verified_at: i.verified_at,
             });
         }
 
@@ -279,6 +527,7 @@ impl Manipulable<Self> for User {
                 users::bio.eq(&update.bio),
                 users::birth_place.eq(&update.birth_place),
                 users::birth_date.eq(&update.birth_date),
+                users::verified_at.eq(&update.verified_at),
             ))
             .execute(conn)?;
 
@@ -320,6 +569,7 @@ macro_rules! impl_classuser {
 
                 let res = $d::table
                     .filter($d::user_id.eq(u.user_id))
+                    .filter($d::class_id.eq(u.class_id))
                     .get_result::<Self>(conn)?;
 
                 Ok(res)
@@ -1,6 +1,6 @@
 use chrono::Local;
 use diesel::associations::HasTable;
-use diesel::{EqAll, PgConnection, QueryDsl, RunQueryDsl};
+use diesel::{Connection, EqAll, PgConnection, QueryDsl, RunQueryDsl};
 use dotenv::var;
 use jsonwebtoken::{Algorithm, Header};
 use rocket::form::Form;
@@ -9,23 +9,39 @@ use rocket::serde::json::serde_json::json;
 use rocket::serde::json::Json;
 use rocket_dyn_templates::handlebars::JsonValue;
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
 use std::path::Path;
 use std::{env, fs};
 
-use crate::auth::{generate_token, ApiKey};
+use crate::audit::models::{AuditLog, FillableAuditLog};
+use crate::classes::models::Classroom;
+use crate::classes::utils::enroll_student;
+use crate::errors::ErrorKind;
+use crate::auth::{generate_challenge, generate_token, new_session, verify_signature, ApiKey};
 use crate::db;
 use crate::file_routes::process_image;
-use crate::files::models::{UploadType, UploadedFile};
+use kosuzers::extractor::{extract_roster, read_sheet, RowError};
+use crate::files::models::{FileVariant, UploadType, UploadedFile};
 use crate::files::routes;
+use crate::lockouts::models::{AccountLock, LoginAttempt, Throttle};
+use crate::lockouts::utils::{client_ip, register_failed_login, send_lockout_mail};
 use crate::pagination::Paginate;
 use crate::schema::files::dsl::files;
 use crate::schema::files::{file_path, file_url};
 use crate::schema::users;
 use crate::schema::users::{email, profile_photo, user_id};
+use crate::sessions::models::Session;
 use crate::traits::Manipulable;
-use crate::users::models::{InsertableUser, PasswordChange, Role, UpdatableUser, User};
-use crate::users::utils::is_email;
-use crate::utils::update;
+use crate::twofactor::models::TwoFactor;
+use crate::users::models::{
+    InsertableUser, NewSystemRole, PasswordChange, PasswordReset, ResetToken, RosterUpload, SystemRole,
+    UpdatableUser, User, VerificationResend, RESET_TOKEN_MINUTES,
+};
+use crate::users::utils::{
+    generate_password, is_email, login_requires_verification, send_credentials,
+    send_verification, verification_payload,
+};
+use crate::utils::{read_upload, send_mail, update};
 
 #[post("/", data = "<user>")]
 async fn create<'a>(
@@ -43,18 +59,19 @@ async fn create<'a>(
         birth_place: user.birth_place,
         birth_date: *user.birth_date,
         bio: user.bio.to_string(),
-        status: user.status.to_string(),
+        system_role: SystemRole::User.to_string(),
         created_at: Local::now().naive_local(),
+        verified_at: None,
     };
 
     let cloned_user = new_user.clone();
 
-    match User::create(new_user, &connection) {
-        Ok(query) => {}
+    let created = match User::create(new_user, &connection) {
+        Ok(query) => query,
         Err(_) => {
             return Err(Status::Conflict);
         }
-    }
+    };
 
     let image_file = match user.image {
         Some(img) => {
@@ -66,12 +83,12 @@ async fn create<'a>(
             .await
             {
                 Ok(v) => v,
-                Err(_) => return Err(Status::BadRequest),
+                Err(e) => return Err(e.status()),
             }
         }
         None => {
             let url = env::var("SITE_URL").unwrap();
-            format!("{}/api/media/img/placeholder.png", url)
+            format!("{}/api/media/placeholder.png", url)
         }
     };
 
@@ -80,13 +97,15 @@ async fn create<'a>(
         .execute(&*connection)
         .unwrap();
 
+    send_verification(created).await;
+
     Ok(Json(json!({"status": 200})))
 }
 
 #[delete("/", data = "<uid>")]
 fn delete_user(key: ApiKey, uid: String, conn: db::DbConn) -> Result<Json<JsonValue>, Status> {
-    match User::get_role(&key.0, &*conn).unwrap() {
-        Role::Admin => {
+    match User::get_system_role(&key.0, &*conn).unwrap() {
+        SystemRole::Admin => {
             match diesel::delete(users::dsl::users.filter(user_id.eq_all(&uid))).execute(&*conn) {
                 Ok(_) => {}
                 Err(_) => {
@@ -107,6 +126,224 @@ fn delete_user(key: ApiKey, uid: String, conn: db::DbConn) -> Result<Json<JsonVa
     Ok(Json(json!({"status": 200})))
 }
 
+#[post("/<uid>/unlock")]
+fn unlock_user(
+    key: ApiKey,
+    uid: String,
+    ip: Option<IpAddr>,
+    conn: db::DbConn,
+) -> Result<Status, Status> {
+    match User::get_system_role(&key.0, &conn) {
+        Ok(SystemRole::Admin) => {}
+        _ => return Err(Status::Forbidden),
+    }
+
+    match AccountLock::reset(&uid, &conn) {
+        Ok(0) => return Err(Status::NotFound),
+        Ok(_) => {}
+        Err(_) => return Err(Status::InternalServerError),
+    }
+
+    AuditLog::create(
+        FillableAuditLog {
+            action: "account_unlocked",
+            user_id: Some(&uid),
+            actor_id: Some(&key.0),
+            ip: Some(&client_ip(ip)),
+            detail: None,
+        },
+        &conn,
+    )
+    .unwrap();
+
+    Ok(Status::Ok)
+}
+
+/// Lets a school admin make an account a teacher's, or take it back.
+#[post("/<uid>/role", format = "application/json", data = "<data>")]
+fn set_role(
+    key: ApiKey,
+    uid: String,
+    data: Json<NewSystemRole>,
+    ip: Option<IpAddr>,
+    conn: db::DbConn,
+) -> Result<Status, Status> {
+    match User::get_system_role(&key.0, &conn) {
+        Ok(SystemRole::Admin) => {}
+        _ => return Err(Status::Forbidden),
+    }
+
+    let user = match User::find_user(&uid, &conn) {
+        Ok(u) => u,
+        Err(_) => return Err(Status::NotFound),
+    };
+
+    let changed = conn.transaction::<_, ErrorKind, _>(|| {
+        user.set_system_role(data.system_role, &conn)?;
+
+        AuditLog::create(
+            FillableAuditLog {
+                action: "system_role_changed",
+                user_id: Some(&uid),
+                actor_id: Some(&key.0),
+                ip: Some(&client_ip(ip)),
+                detail: Some(data.system_role.to_string()),
+            },
+            &conn,
+        )
+    });
+
+    match changed {
+        Ok(_) => Ok(Status::Ok),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+fn row_errors(errors: &[RowError]) -> JsonValue {
+    json!(errors
+        .iter()
+        .map(|e| json!({"row": e.row, "message": e.message}))
+        .collect::<Vec<_>>())
+}
+
+/// Creates accounts from an .xlsx roster and mails each student their
+/// password. Nothing is created unless every row is valid.
+#[post("/import", data = "<roster>")]
+async fn import_users<'a>(
+    key: ApiKey,
+    roster: Form<RosterUpload<'a>>,
+    ip: Option<IpAddr>,
+    conn: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    match User::get_system_role(&key.0, &conn) {
+        Ok(SystemRole::Admin) => {}
+        _ => return Err(Status::Forbidden),
+    }
+
+    let mut roster = roster.into_inner();
+
+    if let Some(class_id) = &roster.class_id {
+        if Classroom::find(class_id, &conn).is_err() {
+            return Err(Status::NotFound);
+        }
+    }
+
+    let bytes = match read_upload(&mut roster.file).await {
+        Ok(b) => b,
+        Err(_) => return Err(Status::BadRequest),
+    };
+
+    let sheet = match read_sheet(bytes) {
+        Ok(s) => s,
+        Err(_) => return Err(Status::UnprocessableEntity),
+    };
+
+    let parsed = match extract_roster(&sheet) {
+        Ok(r) => r,
+        Err(e) => {
+            return Ok(Json(json!({
+                "created": [],
+                "errors": [{"row": 1, "message": e.to_string()}],
+            })))
+        }
+    };
+
+    let mut errors = parsed.errors.clone();
+
+    for row in &parsed.rows {
+        let taken = users::table
+            .filter(user_id.eq_all(&row.user_id))
+            .or_filter(email.eq_all(&row.email))
+            .count()
+            .get_result::<i64>(&*conn);
+
+        match taken {
+            Ok(0) => {}
+            Ok(_) => errors.push(RowError {
+                row: row.row,
+                message: "NIS or email already registered".to_string(),
+            }),
+            Err(_) => return Err(Status::InternalServerError),
+        }
+    }
+
+    if !errors.is_empty() {
+        errors.sort_by_key(|e| e.row);
+
+        return Ok(Json(json!({ "created": [], "errors": row_errors(&errors) })));
+    }
+
+    let url = env::var("SITE_URL").unwrap();
+
+    let created = conn.transaction::<_, ErrorKind, _>(|| {
+        let mut created = Vec::new();
+
+        for row in &parsed.rows {
+            let password = generate_password();
+
+            let new_user = User {
+                user_id: row.user_id.clone(),
+                fullname: row.fullname.clone(),
+                profile_photo: format!("{}/api/media/placeholder.png", url),
+                email: row.email.clone(),
+                password: password.clone(),
+                birth_place: row.birth_place.clone(),
+                birth_date: row.birth_date,
+                bio: "".to_string(),
+                system_role: SystemRole::User.to_string(),
+                created_at: Local::now().naive_local(),
+                verified_at: None,
+            };
+
+            let user = User::create(new_user, &conn)?;
+
+            if let Some(class_id) = &roster.class_id {
+                enroll_student(&user.user_id, class_id, &conn)?;
+            }
+
+            created.push((user, password));
+        }
+
+        Ok(created)
+    });
+
+    let created = match created {
+        Ok(c) => c,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    AuditLog::create(
+        FillableAuditLog {
+            action: "users_imported",
+            user_id: None,
+            actor_id: Some(&key.0),
+            ip: Some(&client_ip(ip)),
+            detail: Some(format!(
+                "{} users{}",
+                created.len(),
+                roster
+                    .class_id
+                    .as_ref()
+                    .map(|c| format!(" into class {}", c))
+                    .unwrap_or_default()
+            )),
+        },
+        &conn,
+    )
+    .unwrap();
+
+    let ids = created
+        .iter()
+        .map(|(u, _)| u.user_id.clone())
+        .collect::<Vec<_>>();
+
+    for (user, password) in created {
+        send_credentials(user, &password).await;
+    }
+
+    Ok(Json(json!({ "created": ids, "errors": [] })))
+}
+
 #[derive(Serialize, Deserialize)]
 struct Credentials {
     key: String,
@@ -114,30 +351,133 @@ struct Credentials {
 }
 
 #[post("/", format = "application/json", data = "<credentials>")]
-fn login(
+async fn login(
     credentials: Json<Credentials>,
+    ip: Option<IpAddr>,
     connection: db::DbConn,
 ) -> Result<Json<JsonValue>, Status> {
-    let header: Header = Header::new(Algorithm::HS512);
+    let ip = client_ip(ip);
+
+    match LoginAttempt::ip_throttle(&ip, &connection) {
+        Ok(Throttle::Open) => {}
+        Ok(_) => return Err(Status::TooManyRequests),
+        Err(_) => return Err(Status::InternalServerError),
+    }
+
     let key = if is_email(&credentials.key.to_string()) {
         match User::get_id_from_email(&credentials.key.to_string(), &connection) {
             Ok(id) => id,
-            Err(_) => return Err(Status::NotFound),
+            Err(_) => {
+                if LoginAttempt::record(None, &ip, false, &connection).is_err() {
+                    return Err(Status::InternalServerError);
+                }
+                return Err(Status::NotFound);
+            }
         }
     } else {
         credentials.key.to_string()
     };
     let password = credentials.password.to_string();
 
-    match User::get_by_key(&key, password, &connection) {
-        None => Err(Status::NotFound),
-        Some(user) => match User::get_role(&key, &connection) {
-            Ok(k) => match generate_token(&key, &k) {
-                Ok(v) => Ok(Json(json!({ "status": 200, "token": v }))),
-                Err(_) => Err(Status::InternalServerError),
-            },
-            Err(e) => Err(Status::InternalServerError),
+    match AccountLock::find(&key, &connection) {
+        Ok(Some(lock)) => match lock.throttle() {
+            Throttle::Open => {}
+            Throttle::Backoff(_) => return Err(Status::TooManyRequests),
+            Throttle::Locked(_) => return Err(Status::Locked),
         },
+        Ok(None) => {}
+        Err(_) => return Err(Status::InternalServerError),
+    }
+
+    let user = match User::get_by_key(&key, password, &connection) {
+        Some(user) => user,
+        None => {
+            if let Ok(Some((user, until))) = register_failed_login(&key, &ip, &connection) {
+                send_lockout_mail(user, until).await;
+            }
+            return Err(Status::NotFound);
+        }
+    };
+
+    if LoginAttempt::record(Some(&user.user_id), &ip, true, &connection).is_err()
+        || AccountLock::reset(&user.user_id, &connection).is_err()
+    {
+        return Err(Status::InternalServerError);
+    }
+
+    if login_requires_verification() && !user.is_verified() {
+        return Err(Status::Forbidden);
+    }
+
+    // The password alone isn't enough, hand out a challenge for the second step
+    if TwoFactor::is_enabled(&user.user_id, &connection) {
+        return Ok(Json(json!({
+            "status": 202,
+            "two_factor": true,
+            "challenge": generate_challenge(&user.user_id),
+        })));
+    }
+
+    match new_session(&user.user_id, &connection) {
+        Ok((token, refresh_token)) => Ok(Json(
+            json!({ "status": 200, "token": token, "refresh_token": refresh_token }),
+        )),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct RefreshToken {
+    refresh_token: String,
+}
+
+#[post("/refresh", format = "application/json", data = "<data>")]
+fn refresh(data: Json<RefreshToken>, connection: db::DbConn) -> Result<Json<JsonValue>, Status> {
+    let session = match Session::from_refresh_token(&data.refresh_token, &connection) {
+        Ok(s) => s,
+        Err(_) => return Err(Status::Unauthorized),
+    };
+
+    let role = match User::get_system_role(&session.user_id, &connection) {
+        Ok(r) => r,
+        Err(_) => return Err(Status::Unauthorized),
+    };
+
+    let (session, refresh_token) = match session.rotate(&connection) {
+        Ok(s) => s,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    match generate_token(&session.user_id, &role, &session.id) {
+        Ok(v) => Ok(Json(
+            json!({ "status": 200, "token": v, "refresh_token": refresh_token }),
+        )),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Logout {
+    refresh_token: String,
+    everywhere: Option<bool>,
+}
+
+#[post("/logout", format = "application/json", data = "<data>")]
+fn logout(data: Json<Logout>, connection: db::DbConn) -> Result<Status, Status> {
+    let session = match Session::from_refresh_token(&data.refresh_token, &connection) {
+        Ok(s) => s,
+        Err(_) => return Err(Status::Unauthorized),
+    };
+
+    let res = if data.everywhere.unwrap_or(false) {
+        Session::revoke_all(&session.user_id, &connection).map(|_| ())
+    } else {
+        session.revoke(&connection).map(|_| ())
+    };
+
+    match res {
+        Ok(_) => Ok(Status::Ok),
+        Err(_) => Err(Status::InternalServerError),
     }
 }
 
@@ -166,18 +506,23 @@ async fn update_user<'a>(
             .await
             {
                 Ok(res) => {
-                    let old_file =
-                        UploadedFile::get_from_url(&cloned_user.profile_photo, &conn).unwrap();
-                    fs::remove_file(old_file.file_path).unwrap();
+                    // The placeholder isn't an upload of theirs
+                    if let Ok(old_file) = UploadedFile::get_from_url(&cloned_user.profile_photo, &conn) {
+                        if old_file.remove(&conn).is_err() {
+                            return Err(Status::InternalServerError);
+                        }
+                    }
 
                     Some(res)
                 }
-                Err(_) => return Err(Status::BadRequest),
+                Err(e) => return Err(e.status()),
             }
         }
         None => None,
     };
 
+    let email_changed = data.email != cloned_user.email;
+
     let updated_user = User {
         user_id: key.0,
 
@@ -191,15 +536,24 @@ async fn update_user<'a>(
         birth_place: data.birth_place,
         birth_date: *data.birth_date,
         bio: data.bio,
-        status: cloned_user.status,
+        system_role: cloned_user.system_role,
         created_at: Local::now().naive_local(),
+        // A new address has to be verified again
+        verified_at: match email_changed {
+            true => None,
+            false => cloned_user.verified_at,
+        },
     };
 
-    match update(user, updated_user, &conn) {
-        Ok(_) => {}
+    let updated = match update(user, updated_user, &conn) {
+        Ok(u) => u,
         Err(_) => return Err(Status::UnprocessableEntity),
     };
 
+    if email_changed {
+        send_verification(updated).await;
+    }
+
     Ok(Status::Ok)
 }
 
@@ -222,6 +576,171 @@ fn password_change(
     }
 }
 
+#[derive(Serialize, Deserialize)]
+struct AccountKey {
+    key: String,
+}
+
+#[get("/verify?<uid>&<expires>&<signature>")]
+fn verify_email(
+    uid: String,
+    expires: i64,
+    signature: String,
+    connection: db::DbConn,
+) -> Result<Json<JsonValue>, Status> {
+    if expires < Local::now().timestamp() {
+        return Err(Status::Gone);
+    }
+
+    let user = match User::find_user(&uid, &connection) {
+        Ok(u) => u,
+        Err(_) => return Err(Status::NotFound),
+    };
+
+    if !verify_signature(&verification_payload(&uid, &user.email, expires), &signature) {
+        return Err(Status::Unauthorized);
+    }
+
+    if !user.is_verified() {
+        user.verify_email(&connection).unwrap();
+    }
+
+    Ok(Json(json!({"status": 200})))
+}
+
+#[post("/verify/resend", format = "application/json", data = "<data>")]
+async fn resend_verification(
+    data: Json<AccountKey>,
+    ip: Option<IpAddr>,
+    connection: db::DbConn,
+) -> Result<Status, Status> {
+    let ip = client_ip(ip);
+
+    match VerificationResend::ip_exhausted(&ip, &connection) {
+        Ok(false) => {}
+        Ok(true) => return Err(Status::TooManyRequests),
+        Err(_) => return Err(Status::InternalServerError),
+    }
+
+    let key = if is_email(&data.key) {
+        User::get_id_from_email(&data.key, &connection).ok()
+    } else {
+        Some(data.key.to_string())
+    };
+
+    // Don't tell whether the account exists, is verified or is cooling down
+    let user = match key.map(|k| User::find_user(&k, &connection)) {
+        Some(Ok(u)) if !u.is_verified() => {
+            match VerificationResend::cooling_down(&u.user_id, &connection) {
+                Ok(false) => Some(u),
+                Ok(true) => None,
+                Err(_) => return Err(Status::InternalServerError),
+            }
+        }
+        _ => None,
+    };
+
+    // Every request counts against the IP, so the endpoint can't be used to
+    // flood inboxes
+    if VerificationResend::record(user.as_ref().map(|u| &u.user_id), &ip, &connection).is_err() {
+        return Err(Status::InternalServerError);
+    }
+
+    if let Some(user) = user {
+        send_verification(user).await;
+    }
+
+    Ok(Status::Ok)
+}
+
+#[post("/forgot", format = "application/json", data = "<data>")]
+async fn forgot_password(
+    data: Json<AccountKey>,
+    connection: db::DbConn,
+) -> Result<Status, Status> {
+    let key = if is_email(&data.key) {
+        match User::get_id_from_email(&data.key, &connection) {
+            Ok(id) => id,
+            // Don't tell whether the account exists
+            Err(_) => return Ok(Status::Ok),
+        }
+    } else {
+        data.key.to_string()
+    };
+
+    let user = match User::find_user(&key, &connection) {
+        Ok(u) => u,
+        Err(_) => return Ok(Status::Ok),
+    };
+
+    let (_, token) = match ResetToken::create(&user.user_id, &connection) {
+        Ok(r) => r,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let url = match env::var("DOMAIN").or_else(|_| env::var("SITE_URL")) {
+        Ok(url) => url,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let html = format!(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Password Reset</title>
+</head>
+<body>
+    <div style="display: block; align-items: center;">
+        <h2 style="font-family: Arial, Helvetica, sans-serif;">Hi {}, we received a request to reset your password.</h2>
+        <br>
+        <h4 style="font-family: Arial, Helvetica, sans-serif;"><a href="{}/reset-password?token={}">Reset your password</a>. The link expires in {} minutes.</h4>
+    </div>
+</body>
+</html>"#, &user.fullname, url, token, RESET_TOKEN_MINUTES);
+
+    let emails = vec![user.email.clone()];
+
+    send_mail(user, emails, html, "Password Reset").await;
+
+    Ok(Status::Ok)
+}
+
+#[post("/reset", format = "application/json", data = "<data>")]
+fn reset_password(data: Json<PasswordReset>, connection: db::DbConn) -> Result<Status, Status> {
+    let data = data.into_inner();
+
+    let reset = match ResetToken::from_token(data.token, &connection) {
+        Ok(r) => r,
+        Err(_) => return Err(Status::Unauthorized),
+    };
+
+    let user = match User::find_user(&reset.user_id, &connection) {
+        Ok(u) => u,
+        Err(_) => return Err(Status::NotFound),
+    };
+
+    // Consumed first, so a token used twice at once only goes through once
+    let reset = connection.transaction::<_, ErrorKind, _>(|| {
+        if reset.consume(&connection)?.is_none() {
+            return Ok(false);
+        }
+
+        user.set_password(data.new_password, &connection)?;
+
+        // Whoever had the old password shouldn't stay signed in
+        Session::revoke_all(&user.user_id, &connection)?;
+
+        Ok(true)
+    });
+
+    match reset {
+        Ok(true) => Ok(Status::Ok),
+        Ok(false) => Err(Status::Unauthorized),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
 #[get("/", format = "application/json")]
 fn info(key: ApiKey, connection: db::DbConn) -> Result<Json<JsonValue>, Status> {
     match User::find_user(&key.0, &connection) {
@@ -230,11 +749,13 @@ fn info(key: ApiKey, connection: db::DbConn) -> Result<Json<JsonValue>, Status>
             "data": {
             "user_id": user.user_id,
             "fullname": user.fullname,
+            "profile_photo_variants": FileVariant::urls_of(&user.profile_photo, &connection).unwrap_or_default(),
             "profile_photo": user.profile_photo,
             "email": user.email,
             "bio": user.bio,
-            "status": user.status,
+            "system_role": user.system_role,
             "created_at": user.created_at,
+            "verified_at": user.verified_at,
                 }
         }))),
         Err(_) => Err(Status::NotFound),
@@ -279,8 +800,22 @@ pub fn mount(rocket: rocket::Rocket<rocket::Build>) -> rocket::Rocket<rocket::Bu
                 delete_user,
                 password_change,
                 update_user,
-                get_all
+                get_all,
+                unlock_user,
+                set_role,
+                import_users
+            ],
+        )
+        .mount(
+            "/api/auth",
+            routes![
+                login,
+                refresh,
+                logout,
+                forgot_password,
+                reset_password,
+                verify_email,
+                resend_verification
             ],
         )
-        .mount("/api/auth", routes![login])
 }
@@ -1,3 +1,109 @@
+use chrono::{Duration, Local};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use rocket::http::RawStr;
+use std::env;
+
+use crate::auth::sign;
+use crate::users::models::User;
+use crate::utils::send_mail;
+
+const VERIFICATION_DAYS: i64 = 3;
+const PASSWORD_LENGTH: usize = 12;
+
 pub fn is_email(s: &String) -> bool {
     s.as_str().contains("@")
 }
+
+fn env_flag(name: &str) -> bool {
+    match env::var(name) {
+        Ok(v) => v == "true" || v == "1",
+        Err(_) => false,
+    }
+}
+
+/// Set `REQUIRE_VERIFIED_LOGIN` to keep unverified accounts from logging in.
+pub fn login_requires_verification() -> bool {
+    env_flag("REQUIRE_VERIFIED_LOGIN")
+}
+
+/// Set `REQUIRE_VERIFIED_JOIN` to keep unverified accounts from joining classes.
+pub fn join_requires_verification() -> bool {
+    env_flag("REQUIRE_VERIFIED_JOIN")
+}
+
+/// The signature covers the email too, so a link stops working once the
+/// address is changed.
+pub fn verification_payload(uid: &str, email: &str, expires: i64) -> String {
+    format!("{}:{}:{}", uid, email, expires)
+}
+
+pub fn verification_link(user: &User) -> String {
+    let url = env::var("SITE_URL").unwrap();
+
+    let expires = (Local::now() + Duration::days(VERIFICATION_DAYS)).timestamp();
+
+    let signature = sign(&verification_payload(&user.user_id, &user.email, expires));
+
+    format!(
+        "{}/api/auth/verify?uid={}&expires={}&signature={}",
+        url,
+        RawStr::new(&user.user_id).percent_encode(),
+        expires,
+        signature
+    )
+}
+
+pub async fn send_verification(user: User) {
+    let html = format!(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Verify your email</title>
+</head>
+<body>
+    <div style="display: block; align-items: center;">
+        <h2 style="font-family: Arial, Helvetica, sans-serif;">Welcome to Thearning, {}!</h2>
+        <br>
+        <h4 style="font-family: Arial, Helvetica, sans-serif;"><a href="{}">Verify your email address</a>. The link expires in {} days.</h4>
+    </div>
+</body>
+</html>"#, &user.fullname, verification_link(&user), VERIFICATION_DAYS);
+
+    let emails = vec![user.email.clone()];
+
+    send_mail(user, emails, html, "Verify your email").await;
+}
+
+/// An initial password for accounts made on someone else's behalf.
+pub fn generate_password() -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(PASSWORD_LENGTH)
+        .map(char::from)
+        .collect::<String>()
+}
+
+pub async fn send_credentials(user: User, password: &str) {
+    let html = format!(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Your Thearning account</title>
+</head>
+<body>
+    <div style="display: block; align-items: center;">
+        <h2 style="font-family: Arial, Helvetica, sans-serif;">Welcome to Thearning, {}!</h2>
+        <br>
+        <h4 style="font-family: Arial, Helvetica, sans-serif;">Your school made an account for you. Sign in with <b>{}</b> and the password <b>{}</b>, then change it.</h4>
+        <h4 style="font-family: Arial, Helvetica, sans-serif;"><a href="{}">Verify your email address</a>. The link expires in {} days.</h4>
+    </div>
+</body>
+</html>"#, &user.fullname, &user.user_id, password, verification_link(&user), VERIFICATION_DAYS);
+
+    let emails = vec![user.email.clone()];
+
+    send_mail(user, emails, html, "Your Thearning account").await;
+}
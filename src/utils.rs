@@ -1,13 +1,16 @@
 use std::ops::Deref;
 use std::env;
 
-use crate::errors::ThearningResult;
+use crate::errors::{ErrorKind, ThearningResult};
 use chrono::NaiveDate;
 use diesel::PgConnection;
-use rand::Rng;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
 use rocket::form;
 use rocket::form::{DataField, FromFormField, ValueField};
+use rocket::fs::TempFile;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use ayaya::Mailer;
 use crate::attachments::models::Attachment;
 use crate::comments::models::Commenter;
@@ -30,6 +33,46 @@ pub fn generate_random_id() -> i32 {
     rng.gen::<i32>().abs()
 }
 
+pub fn generate_secret() -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(64)
+        .map(char::from)
+        .collect::<String>()
+}
+
+pub fn hash_secret(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+/// Reads an upload into memory. Small uploads only live in a buffer, so
+/// they're written to the temp dir first.
+pub async fn read_upload(file: &mut TempFile<'_>) -> ThearningResult<Vec<u8>> {
+    if file.path().is_none() {
+        let path = env::temp_dir().join(format!("thearning-{}{}", generate_random_id(), generate_random_id()));
+        file.persist_to(&path).await?;
+    }
+
+    let path = match file.path() {
+        Some(p) => p.to_path_buf(),
+        None => return Err(ErrorKind::InvalidValue),
+    };
+
+    let bytes = std::fs::read(&path)?;
+
+    std::fs::remove_file(&path).ok();
+
+    Ok(bytes)
+}
+
+/// Splits a `<row id>.<secret>` token as handed out for sessions and resets.
+pub fn split_token(token: &str) -> ThearningResult<(&str, &str)> {
+    match token.split_once('.') {
+        Some((id, secret)) if !id.is_empty() && !secret.is_empty() => Ok((id, secret)),
+        _ => Err(ErrorKind::InvalidValue),
+    }
+}
+
 pub fn update<T, U>(table: T, new_data: U, conn: &PgConnection) -> ThearningResult<T>
 where
     T: Manipulable<U>,
@@ -85,22 +128,18 @@ pub async fn send_mail(user: User, emails: Vec<String>, message: String, subject
     }
 }
 
-pub fn get_comments<'a, T>(vec: &'a Vec<T>, conn: &PgConnection) -> Vec<UserComment<'a, T>>
+pub fn get_comments<'a, T>(vec: &'a Vec<T>, conn: &PgConnection) -> ThearningResult<Vec<UserComment<'a, T>>>
 where T: Commenter<Output=String> + Serialize {
-    let mut res = Vec::<UserComment<T>>::new();
-
-    for thing in vec {
-        let resp = UserComment {
-            commenter: {
-                let user = User::find_user(thing.get_user_id(), &conn).unwrap();
-                ResponseUser::from(user)
-            },
-            comment: thing,
-        };
-        res.push(resp)
-    }
-
-    res
+    let users = vec
+        .iter()
+        .map(|thing| User::find_user(thing.get_user_id(), conn))
+        .collect::<ThearningResult<Vec<User>>>()?;
+
+    Ok(ResponseUser::load(users, conn)?
+        .into_iter()
+        .zip(vec)
+        .map(|(commenter, comment)| UserComment { commenter, comment })
+        .collect())
 }
 
 #[derive(Serialize)]